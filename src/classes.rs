@@ -0,0 +1,128 @@
+// Asset classes held through several tickers; for example, VTI and ITOT both held as "US Stock".
+//
+// Targets are set, and the portfolio rebalanced, at the level of the asset class. The money to buy or sell for a class
+// is then traded through its preferred ticker, which is the first ticker listed for the class.
+
+// rust imports
+
+use std::collections::HashMap;
+
+// local imports
+
+use error::RebalanceError;
+use input::Input;
+
+#[derive(Default)]
+pub struct AssetClasses {
+    // Tickers of each asset class, starting with its preferred ticker.
+    classes: HashMap<String, Vec<String>>,
+    // Asset class of each ticker.
+    ticker_classes: HashMap<String, String>,
+}
+
+impl AssetClasses {
+    pub fn add(&mut self, class_name: &str, tickers: Vec<String>) -> Result<(), String> {
+        let class_name = class_name.trim().to_string();
+
+        if tickers.is_empty() {
+            return Err(format!("asset class \"{}\" has no tickers", class_name));
+        }
+
+        if self.classes.contains_key(&class_name) {
+            return Err(format!("asset class \"{}\" is declared twice", class_name));
+        }
+
+        for ticker in &tickers {
+            if let Some(other_class_name) = self.ticker_classes.get(ticker) {
+                return Err(format!(
+                    "ticker \"{}\" belongs to both asset classes \"{}\" and \"{}\"",
+                    ticker, other_class_name, class_name
+                ));
+            }
+
+            self.ticker_classes
+                .insert(ticker.clone(), class_name.clone());
+        }
+
+        self.classes.insert(class_name, tickers);
+
+        Ok(())
+    }
+
+    // Asset class of the given ticker, if it belongs to one.
+    pub fn class_of(&self, ticker: &str) -> Option<&str> {
+        self.ticker_classes.get(ticker).map(|name| name.as_str())
+    }
+
+    // Ticker through which the asset class is bought or sold.
+    pub fn preferred_ticker(&self, class_name: &str) -> Option<&str> {
+        self.classes
+            .get(class_name)
+            .map(|tickers| tickers[0].as_str())
+    }
+}
+
+// Reads asset classes from a headerless CSV file with one row per class: the class name, followed by its tickers,
+// preferred ticker first.
+//
+// Example:
+//
+//     US Stock,   VTI,  ITOT
+//     Bonds,      BND,  AGG
+pub fn read_asset_classes(input: &Input) -> Result<AssetClasses, RebalanceError> {
+    let path = input.name.as_str();
+
+    let mut reader = input.csv_reader();
+
+    let mut asset_classes = AssetClasses::default();
+
+    for result in reader.records() {
+        let record = result.map_err(|error| RebalanceError::csv(path, error))?;
+
+        let line = record.position().map(|position| position.line());
+
+        let mut cells = record.iter().map(|cell| cell.trim());
+
+        let class_name = match cells.next() {
+            Some(class_name) if !class_name.is_empty() => class_name,
+            _ => continue,
+        };
+
+        let tickers = cells
+            .filter(|ticker| !ticker.is_empty())
+            .map(|ticker| ticker.to_string())
+            .collect();
+
+        asset_classes
+            .add(class_name, tickers)
+            .map_err(|error| RebalanceError::parse(path, line, None, error))?;
+    }
+
+    Ok(asset_classes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_asset_classes() {
+        let mut asset_classes = AssetClasses::default();
+
+        asset_classes
+            .add("US Stock", vec!["VTI".to_string(), "ITOT".to_string()])
+            .unwrap();
+
+        assert_eq!(asset_classes.class_of("ITOT"), Some("US Stock"));
+        assert_eq!(asset_classes.class_of("BND"), None);
+        assert_eq!(asset_classes.preferred_ticker("US Stock"), Some("VTI"));
+
+        assert_eq!(
+            asset_classes.add("Total Market", vec!["ITOT".to_string()]),
+            Err(
+                "ticker \"ITOT\" belongs to both asset classes \"US Stock\" and \"Total Market\""
+                    .to_string()
+            )
+        );
+    }
+}
@@ -0,0 +1,127 @@
+// Errors of reading the inputs and rebalancing the portfolio.
+
+// rust imports
+
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+// 3rd-party imports
+
+use csv;
+
+#[derive(Debug)]
+pub enum RebalanceError {
+    // A file could not be read.
+    Io {
+        path: String,
+        error: io::Error,
+    },
+    // A CSV file could not be read.
+    Csv {
+        path: String,
+        error: csv::Error,
+    },
+    // A file was read, but its contents are invalid. The line and column are 1-based.
+    Parse {
+        path: String,
+        line: Option<u64>,
+        column: Option<usize>,
+        message: String,
+    },
+    // A target allocation, in percent, is negative, above 100% or not a number.
+    InvalidAllocation {
+        asset_name: String,
+        allocation: f64,
+    },
+    // There are no assets to rebalance.
+    EmptyPortfolio,
+    // Rebalancing needs a division by an amount that is zero, such as the target value of an asset.
+    DivisionByZero(String),
+    // The costs of the trades still change by more than the tolerance after the last round of rebalancing net of
+    // them, e.g. as a trade is left out in one round and placed in the next.
+    CostsNotSettled {
+        rounds: usize,
+        change: f64,
+    },
+    // Any other invalid input, such as a command line option.
+    Invalid(String),
+}
+
+impl RebalanceError {
+    pub fn parse(path: &str, line: Option<u64>, column: Option<usize>, message: String) -> Self {
+        RebalanceError::Parse {
+            path: path.to_string(),
+            line,
+            column,
+            message,
+        }
+    }
+
+    pub fn io(path: &str, error: io::Error) -> Self {
+        RebalanceError::Io {
+            path: path.to_string(),
+            error,
+        }
+    }
+
+    pub fn csv(path: &str, error: csv::Error) -> Self {
+        RebalanceError::Csv {
+            path: path.to_string(),
+            error,
+        }
+    }
+}
+
+impl fmt::Display for RebalanceError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            RebalanceError::Io {
+                ref path,
+                ref error,
+            } => write!(f, "{}: {}", path, error),
+            RebalanceError::Csv {
+                ref path,
+                ref error,
+            } => write!(f, "{}: {}", path, error),
+            RebalanceError::Parse {
+                ref path,
+                line,
+                column,
+                ref message,
+            } => match (line, column) {
+                (Some(line), Some(column)) => {
+                    write!(f, "{}: line {}, column {}: {}", path, line, column, message)
+                }
+                (Some(line), None) => write!(f, "{}: line {}: {}", path, line, message),
+                _ => write!(f, "{}: {}", path, message),
+            },
+            RebalanceError::InvalidAllocation {
+                ref asset_name,
+                allocation,
+            } => write!(
+                f,
+                "invalid target allocation {}% for \"{}\"; expected between 0% and 100%",
+                allocation, asset_name
+            ),
+            RebalanceError::EmptyPortfolio => write!(f, "portfolio has no assets to rebalance"),
+            RebalanceError::DivisionByZero(ref context) => {
+                write!(f, "cannot rebalance: {} is zero", context)
+            }
+            RebalanceError::CostsNotSettled { rounds, change } => write!(
+                f,
+                "cannot rebalance net of costs: the costs of the trades still change by {} after {} rounds",
+                change, rounds
+            ),
+            RebalanceError::Invalid(ref message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl Error for RebalanceError {}
+
+impl From<String> for RebalanceError {
+    fn from(message: String) -> Self {
+        RebalanceError::Invalid(message)
+    }
+}
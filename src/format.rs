@@ -0,0 +1,493 @@
+// Layout of a portfolio CSV file, and built-in layouts for common broker position exports.
+
+// 3rd-party imports
+
+use csv::StringRecord;
+
+// local imports
+
+use error::RebalanceError;
+use holding::Holding;
+use input::Input;
+use journal::JournalOptions;
+use money::{is_currency_code, parse_amount, parse_currency};
+
+// A column of the portfolio file, addressed either by its position or by its header.
+pub enum Column {
+    Index(usize),
+    Header(String),
+}
+
+impl Column {
+    pub fn resolve(&self, headers: Option<&StringRecord>) -> Result<usize, String> {
+        match *self {
+            Column::Index(index) => Ok(index),
+            Column::Header(ref name) => {
+                let headers = match headers {
+                    Some(headers) => headers,
+                    None => {
+                        return Err(format!(
+                            "column \"{}\" can only be used when the portfolio file has headers",
+                            name
+                        ))
+                    }
+                };
+
+                // Broker exports are inconsistent with whitespace and casing in their headers.
+                let position = headers
+                    .iter()
+                    .position(|header| header.trim().eq_ignore_ascii_case(name.trim()));
+
+                match position {
+                    Some(index) => Ok(index),
+                    None => {
+                        let available: Vec<String> = headers
+                            .iter()
+                            .filter(|header| !header.trim().is_empty())
+                            .map(|header| format!("\"{}\"", header.trim()))
+                            .collect();
+
+                        Err(format!(
+                            "column \"{}\" not found in portfolio file; available columns: {}",
+                            name,
+                            available.join(", ")
+                        ))
+                    }
+                }
+            }
+        }
+    }
+
+    fn header(name: &str) -> Option<Column> {
+        Some(Column::Header(name.to_string()))
+    }
+}
+
+pub struct PortfolioFormat {
+    pub has_headers: bool,
+    pub name: Column,
+    pub value: Option<Column>,
+    pub quantity: Option<Column>,
+    pub price: Option<Column>,
+    pub account: Option<Column>,
+    // Currency of each holding. Without it, a currency code in the value (e.g. `100.00 USD`) is used, if any.
+    pub currency: Option<Column>,
+    pub decimal_separator: char,
+
+    // Skip any rows before the header row, which is the first row containing the header of the name column.
+    pub skip_preamble: bool,
+    // Stop reading at the first blank row after the header row. Brokers often append disclaimers or other tables
+    // after the positions.
+    pub stop_at_blank_row: bool,
+    // Asset names of summary rows to skip, such as account totals.
+    pub skip_names: Vec<String>,
+    // Characters trimmed from the end of asset names.
+    pub name_suffix_chars: Vec<char>,
+    // Read only this section of a multi-section statement. Each row of such a statement starts with the section name,
+    // followed by the row type: Header, Data, SubTotal or Total.
+    pub section: Option<String>,
+
+    // How holdings are read when the portfolio file is an accounting journal.
+    pub journal: JournalOptions,
+}
+
+impl Default for PortfolioFormat {
+    fn default() -> Self {
+        PortfolioFormat {
+            has_headers: false,
+            name: Column::Index(0),
+            value: Some(Column::Index(1)),
+            quantity: None,
+            price: None,
+            account: None,
+            currency: None,
+            decimal_separator: '.',
+
+            skip_preamble: false,
+            stop_at_blank_row: false,
+            skip_names: vec![],
+            name_suffix_chars: vec![],
+            section: None,
+
+            journal: JournalOptions::default(),
+        }
+    }
+}
+
+pub const PRESET_NAMES: &[&str] = &[
+    "questrade",
+    "wealthsimple",
+    "vanguard",
+    "fidelity",
+    "schwab",
+    "ibkr",
+];
+
+impl PortfolioFormat {
+    pub fn preset(name: &str) -> Option<PortfolioFormat> {
+        let format = match name.to_lowercase().as_str() {
+            "questrade" => PortfolioFormat {
+                has_headers: true,
+                name: Column::Header("Symbol".to_string()),
+                value: Column::header("Market Value"),
+                quantity: Column::header("Quantity"),
+                price: Column::header("Price"),
+                skip_preamble: true,
+                stop_at_blank_row: true,
+                ..PortfolioFormat::default()
+            },
+            "wealthsimple" => PortfolioFormat {
+                has_headers: true,
+                name: Column::Header("Symbol".to_string()),
+                value: Column::header("Market Value"),
+                quantity: Column::header("Quantity"),
+                price: Column::header("Market Price"),
+                account: Column::header("Account Name"),
+                // The export ends with an "As of ..." line after a blank row.
+                stop_at_blank_row: true,
+                ..PortfolioFormat::default()
+            },
+            "vanguard" => PortfolioFormat {
+                has_headers: true,
+                name: Column::Header("Symbol".to_string()),
+                value: Column::header("Total Value"),
+                quantity: Column::header("Shares"),
+                price: Column::header("Share Price"),
+                account: Column::header("Account Number"),
+                // Positions are followed by a blank row and a table of transactions.
+                stop_at_blank_row: true,
+                ..PortfolioFormat::default()
+            },
+            "fidelity" => PortfolioFormat {
+                has_headers: true,
+                name: Column::Header("Symbol".to_string()),
+                value: Column::header("Current Value"),
+                quantity: Column::header("Quantity"),
+                price: Column::header("Last Price"),
+                account: Column::header("Account Name"),
+                // Positions are followed by a blank row and disclaimers.
+                stop_at_blank_row: true,
+                skip_names: vec!["Pending Activity".to_string()],
+                // Money market funds are marked as core positions, e.g. SPAXX**.
+                name_suffix_chars: vec!['*'],
+                ..PortfolioFormat::default()
+            },
+            "schwab" => PortfolioFormat {
+                has_headers: true,
+                name: Column::Header("Symbol".to_string()),
+                value: Column::header("Market Value"),
+                quantity: Column::header("Quantity"),
+                price: Column::header("Price"),
+                // The export starts with a "Positions for account ..." title row.
+                skip_preamble: true,
+                skip_names: vec![
+                    "Cash & Cash Investments".to_string(),
+                    "Account Total".to_string(),
+                ],
+                ..PortfolioFormat::default()
+            },
+            "ibkr" => PortfolioFormat {
+                has_headers: true,
+                name: Column::Header("Symbol".to_string()),
+                value: Column::header("Value"),
+                quantity: Column::header("Quantity"),
+                price: Column::header("Close Price"),
+                currency: Column::header("Currency"),
+                section: Some("Open Positions".to_string()),
+                ..PortfolioFormat::default()
+            },
+            _ => return None,
+        };
+
+        Some(format)
+    }
+
+    fn trim_name<'a>(&self, name: &'a str) -> &'a str {
+        name.trim()
+            .trim_end_matches(|c| self.name_suffix_chars.contains(&c))
+            .trim()
+    }
+
+    fn is_skipped_name(&self, name: &str) -> bool {
+        name.is_empty()
+            || self
+                .skip_names
+                .iter()
+                .any(|skip_name| skip_name.eq_ignore_ascii_case(name))
+    }
+
+    // Reads the holdings of a portfolio file in this format.
+    pub fn read_holdings(&self, input: &Input) -> Result<Vec<Holding>, RebalanceError> {
+        let path = input.name.as_str();
+
+        let (headers, rows) = self.read_rows(input)?;
+
+        let header_line = headers
+            .as_ref()
+            .and_then(|headers| headers.position())
+            .map(|position| position.line());
+
+        let (name_index, value_index, quantity_index, price_index, account_index, currency_index) = {
+            let resolve_name = |column: &Column| -> Result<usize, RebalanceError> {
+                column
+                    .resolve(headers.as_ref())
+                    .map_err(|error| RebalanceError::parse(path, header_line, None, error))
+            };
+
+            let resolve = |column: &Option<Column>| -> Result<Option<usize>, RebalanceError> {
+                match *column {
+                    Some(ref column) => resolve_name(column).map(Some),
+                    None => Ok(None),
+                }
+            };
+
+            (
+                resolve_name(&self.name)?,
+                resolve(&self.value)?,
+                resolve(&self.quantity)?,
+                resolve(&self.price)?,
+                resolve(&self.account)?,
+                resolve(&self.currency)?,
+            )
+        };
+
+        if value_index.is_none() && quantity_index.is_none() {
+            return Err(RebalanceError::parse(
+                path,
+                None,
+                None,
+                "portfolio file needs a value column, or a quantity column".to_string(),
+            ));
+        }
+
+        let mut holdings = vec![];
+
+        for record in rows {
+            let line = record
+                .position()
+                .map(|position| position.line())
+                .unwrap_or(0);
+
+            let cell = |index: usize| -> Result<&str, RebalanceError> {
+                match record.get(index) {
+                    Some(cell) => Ok(cell),
+                    None => Err(RebalanceError::parse(
+                        path,
+                        Some(line),
+                        None,
+                        format!("missing column {}", index + 1),
+                    )),
+                }
+            };
+
+            let name = self.trim_name(cell(name_index)?).to_string();
+
+            if self.is_skipped_name(&name) {
+                continue;
+            }
+
+            let parse_column = |index: Option<usize>| -> Result<Option<f64>, RebalanceError> {
+                let index = match index {
+                    Some(index) => index,
+                    None => return Ok(None),
+                };
+
+                match parse_amount(cell(index)?, self.decimal_separator) {
+                    Ok(amount) => Ok(Some(amount)),
+                    Err(error) => Err(RebalanceError::parse(
+                        path,
+                        Some(line),
+                        Some(index + 1),
+                        error,
+                    )),
+                }
+            };
+
+            // Units are informational; brokers write placeholders such as "--" when they don't apply.
+            let parse_units_column = |index: Option<usize>| -> Result<Option<f64>, RebalanceError> {
+                match index {
+                    Some(index) if !cell(index)?.chars().any(|c| c.is_ascii_digit()) => Ok(None),
+                    _ => parse_column(index),
+                }
+            };
+
+            let value = parse_column(value_index)?;
+            let quantity = parse_units_column(quantity_index)?;
+            let price = parse_units_column(price_index)?;
+
+            let mut unvalued_quantity = None;
+
+            let (value, units) = match (value, quantity, price) {
+                (Some(value), Some(quantity), Some(price)) => (value, Some((quantity, price))),
+                (Some(value), Some(quantity), None) if quantity != 0.0 => {
+                    (value, Some((quantity, value / quantity)))
+                }
+                (Some(value), _, _) => (value, None),
+                (None, Some(quantity), Some(price)) => (quantity * price, Some((quantity, price))),
+                // Valued later, from a price file.
+                (None, Some(quantity), None) if price_index.is_none() => {
+                    unvalued_quantity = Some(quantity);
+                    (0.0, None)
+                }
+                (None, _, _) => {
+                    return Err(RebalanceError::parse(
+                        path,
+                        Some(line),
+                        None,
+                        "holding has no value, or quantity and price".to_string(),
+                    ))
+                }
+            };
+
+            let account = match account_index {
+                Some(account_index) => {
+                    let account = cell(account_index)?.trim();
+
+                    if account.is_empty() {
+                        None
+                    } else {
+                        Some(account.to_string())
+                    }
+                }
+                None => None,
+            };
+
+            let currency = match currency_index {
+                Some(currency_index) => {
+                    let currency = cell(currency_index)?.trim();
+
+                    if currency.is_empty() {
+                        None
+                    } else if is_currency_code(currency) {
+                        Some(currency.to_uppercase())
+                    } else {
+                        return Err(RebalanceError::parse(
+                            path,
+                            Some(line),
+                            Some(currency_index + 1),
+                            format!("invalid currency \"{}\"", currency),
+                        ));
+                    }
+                }
+                None => match value_index {
+                    Some(value_index) => parse_currency(cell(value_index)?),
+                    None => None,
+                },
+            };
+
+            holdings.push(Holding {
+                name,
+                value,
+                units,
+                account,
+                currency,
+                unvalued_quantity,
+            });
+        }
+
+        Ok(holdings)
+    }
+
+    // Reads the header row, if any, and the rows holding positions.
+    fn read_rows(
+        &self,
+        input: &Input,
+    ) -> Result<(Option<StringRecord>, Vec<StringRecord>), RebalanceError> {
+        let path = input.name.as_str();
+
+        let mut reader = input.csv_reader();
+
+        let lines: Vec<&str> = input.contents.lines().collect();
+
+        let mut headers: Option<StringRecord> = None;
+        let mut rows = vec![];
+
+        for result in reader.records() {
+            let record = result.map_err(|error| RebalanceError::csv(path, error))?;
+
+            // The reader skips empty lines, and reports the record after them as starting on the first of them.
+            let follows_empty_line = record
+                .position()
+                .and_then(|position| lines.get(position.line() as usize - 1))
+                .is_some_and(|line| line.trim().is_empty());
+
+            let is_blank = record.iter().all(|cell| cell.trim().is_empty());
+
+            if let Some(ref section) = self.section {
+                if record.get(0).map(|cell| cell.trim()) != Some(section.as_str()) {
+                    continue;
+                }
+
+                match record.get(1).map(|cell| cell.trim()) {
+                    Some("Header") if headers.is_none() => headers = Some(record),
+                    Some("Data") if headers.is_some() => {
+                        // Statements may list each tax lot after the summary of a position.
+                        let is_lot = headers
+                            .as_ref()
+                            .and_then(|headers| {
+                                headers
+                                    .iter()
+                                    .position(|header| header == "DataDiscriminator")
+                            })
+                            .and_then(|index| record.get(index))
+                            .map(|discriminator| discriminator != "Summary")
+                            .unwrap_or(false);
+
+                        if !is_lot {
+                            rows.push(record);
+                        }
+                    }
+                    _ => {}
+                }
+
+                continue;
+            }
+
+            if follows_empty_line && self.stop_at_blank_row && headers.is_some() {
+                break;
+            }
+
+            if self.has_headers && headers.is_none() {
+                if is_blank {
+                    continue;
+                }
+
+                if self.skip_preamble && !self.is_header_row(&record) {
+                    continue;
+                }
+
+                headers = Some(record);
+                continue;
+            }
+
+            if is_blank {
+                if self.stop_at_blank_row {
+                    break;
+                }
+                continue;
+            }
+
+            rows.push(record);
+        }
+
+        if self.has_headers && headers.is_none() {
+            return Err(RebalanceError::parse(
+                path,
+                None,
+                None,
+                "portfolio file has no header row".to_string(),
+            ));
+        }
+
+        Ok((headers, rows))
+    }
+
+    fn is_header_row(&self, record: &StringRecord) -> bool {
+        match self.name {
+            Column::Header(ref name) => record
+                .iter()
+                .any(|cell| cell.trim().eq_ignore_ascii_case(name.trim())),
+            Column::Index(_) => true,
+        }
+    }
+}
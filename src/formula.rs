@@ -0,0 +1,276 @@
+// Formulas for target allocations, evaluated at run time: `age - 20`, or `100 - Bonds` to take the rest of the
+// portfolio.
+//
+// A formula is made of numbers, the operators `+`, `-`, `*` and `/`, parentheses, and names: `age` (the investor's age
+// in whole years) or the name of another target, whose allocation it stands for. A name with spaces or other
+// characters is written in brackets, e.g. `100 - [Bond fund]`.
+
+// 3rd-party imports
+
+use num::BigRational;
+use num::Zero;
+
+// local imports
+
+use targets::parse_decimal;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Formula {
+    Number(BigRational),
+    Name(String),
+    Negate(Box<Formula>),
+    Add(Box<Formula>, Box<Formula>),
+    Subtract(Box<Formula>, Box<Formula>),
+    Multiply(Box<Formula>, Box<Formula>),
+    Divide(Box<Formula>, Box<Formula>),
+}
+
+impl Formula {
+    pub fn parse(input: &str) -> Result<Formula, String> {
+        let tokens = tokenize(input)?;
+
+        let mut parser = Parser { tokens, index: 0 };
+
+        let formula = parser.sum()?;
+
+        match parser.tokens.get(parser.index) {
+            None => Ok(formula),
+            Some(token) => Err(format!("unexpected {} in formula \"{}\"", token, input)),
+        }
+    }
+
+    // Names the formula refers to.
+    pub fn names(&self) -> Vec<&str> {
+        match *self {
+            Formula::Number(_) => vec![],
+            Formula::Name(ref name) => vec![name.as_str()],
+            Formula::Negate(ref formula) => formula.names(),
+            Formula::Add(ref left, ref right)
+            | Formula::Subtract(ref left, ref right)
+            | Formula::Multiply(ref left, ref right)
+            | Formula::Divide(ref left, ref right) => {
+                let mut names = left.names();
+                names.extend(right.names());
+                names
+            }
+        }
+    }
+
+    // Evaluates the formula, with the value of each name it refers to.
+    pub fn evaluate(
+        &self,
+        value_of: &dyn Fn(&str) -> Result<BigRational, String>,
+    ) -> Result<BigRational, String> {
+        Ok(match *self {
+            Formula::Number(ref number) => number.clone(),
+            Formula::Name(ref name) => value_of(name)?,
+            Formula::Negate(ref formula) => -formula.evaluate(value_of)?,
+            Formula::Add(ref left, ref right) => {
+                left.evaluate(value_of)? + right.evaluate(value_of)?
+            }
+            Formula::Subtract(ref left, ref right) => {
+                left.evaluate(value_of)? - right.evaluate(value_of)?
+            }
+            Formula::Multiply(ref left, ref right) => {
+                left.evaluate(value_of)? * right.evaluate(value_of)?
+            }
+            Formula::Divide(ref left, ref right) => {
+                let divisor = right.evaluate(value_of)?;
+
+                if divisor.is_zero() {
+                    return Err("division by zero".to_string());
+                }
+
+                left.evaluate(value_of)? / divisor
+            }
+        })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Number(BigRational),
+    Name(String),
+    Operator(char),
+}
+
+impl ::std::fmt::Display for Token {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        match *self {
+            Token::Number(_) => write!(f, "number"),
+            Token::Name(ref name) => write!(f, "\"{}\"", name),
+            Token::Operator(operator) => write!(f, "\"{}\"", operator),
+        }
+    }
+}
+
+fn tokenize(input: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = vec![];
+
+    let mut chars = input.chars().peekable();
+
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if "+-*/()".contains(c) {
+            tokens.push(Token::Operator(c));
+            chars.next();
+        } else if c == '[' {
+            chars.next();
+
+            let name: String = chars.by_ref().take_while(|&c| c != ']').collect();
+
+            if name.trim().is_empty() {
+                return Err(format!("empty or unclosed [name] in formula \"{}\"", input));
+            }
+
+            tokens.push(Token::Name(name.trim().to_string()));
+        } else if c.is_ascii_digit() || c == '.' {
+            let mut number = String::new();
+
+            while let Some(&c) = chars.peek() {
+                if !(c.is_ascii_digit() || c == '.') {
+                    break;
+                }
+                number.push(c);
+                chars.next();
+            }
+
+            match parse_decimal(&number) {
+                Some(number) => tokens.push(Token::Number(number)),
+                None => return Err(format!("invalid number \"{}\" in formula", number)),
+            }
+        } else if c.is_alphanumeric() || c == '_' {
+            let mut name = String::new();
+
+            while let Some(&c) = chars.peek() {
+                if !(c.is_alphanumeric() || c == '_' || c == '.') {
+                    break;
+                }
+                name.push(c);
+                chars.next();
+            }
+
+            tokens.push(Token::Name(name));
+        } else {
+            return Err(format!("unexpected \"{}\" in formula \"{}\"", c, input));
+        }
+    }
+
+    Ok(tokens)
+}
+
+// Recursive descent, with the usual precedence: sums of products of (negated) terms.
+struct Parser {
+    tokens: Vec<Token>,
+    index: usize,
+}
+
+impl Parser {
+    fn next_operator(&mut self, operators: &str) -> Option<char> {
+        match self.tokens.get(self.index) {
+            Some(&Token::Operator(operator)) if operators.contains(operator) => {
+                self.index += 1;
+                Some(operator)
+            }
+            _ => None,
+        }
+    }
+
+    fn sum(&mut self) -> Result<Formula, String> {
+        let mut formula = self.product()?;
+
+        while let Some(operator) = self.next_operator("+-") {
+            let right = Box::new(self.product()?);
+
+            formula = match operator {
+                '+' => Formula::Add(Box::new(formula), right),
+                _ => Formula::Subtract(Box::new(formula), right),
+            };
+        }
+
+        Ok(formula)
+    }
+
+    fn product(&mut self) -> Result<Formula, String> {
+        let mut formula = self.term()?;
+
+        while let Some(operator) = self.next_operator("*/") {
+            let right = Box::new(self.term()?);
+
+            formula = match operator {
+                '*' => Formula::Multiply(Box::new(formula), right),
+                _ => Formula::Divide(Box::new(formula), right),
+            };
+        }
+
+        Ok(formula)
+    }
+
+    fn term(&mut self) -> Result<Formula, String> {
+        if self.next_operator("-").is_some() {
+            return Ok(Formula::Negate(Box::new(self.term()?)));
+        }
+
+        if self.next_operator("(").is_some() {
+            let formula = self.sum()?;
+
+            return match self.next_operator(")") {
+                Some(_) => Ok(formula),
+                None => Err("missing \")\" in formula".to_string()),
+            };
+        }
+
+        let token = self.tokens.get(self.index).cloned();
+        self.index += 1;
+
+        match token {
+            Some(Token::Number(number)) => Ok(Formula::Number(number)),
+            Some(Token::Name(name)) => Ok(Formula::Name(name)),
+            Some(token) => Err(format!("unexpected {} in formula", token)),
+            None => Err("formula ends unexpectedly".to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use num::bigint::BigInt;
+
+    #[test]
+    fn test_formula() {
+        let number = |number: i64| BigRational::from_integer(BigInt::from(number));
+
+        let value_of = |name: &str| match name {
+            "age" => Ok(number(45)),
+            "Bond fund" => Ok(number(25)),
+            _ => Err(format!("unknown name \"{}\"", name)),
+        };
+
+        let evaluate = |input: &str| Formula::parse(input)?.evaluate(&value_of);
+
+        assert_eq!(evaluate("age - 20"), Ok(number(25)));
+        assert_eq!(evaluate("100 - [Bond fund] * 2"), Ok(number(50)));
+        assert_eq!(evaluate("(100 - age) / 2 + -2.5"), Ok(number(25)));
+        assert_eq!(
+            evaluate("100 - Stocks"),
+            Err("unknown name \"Stocks\"".to_string())
+        );
+        assert_eq!(evaluate("age / 0"), Err("division by zero".to_string()));
+        assert_eq!(
+            Formula::parse("age - ").err().unwrap(),
+            "formula ends unexpectedly"
+        );
+        assert_eq!(
+            Formula::parse("(age - 20").err().unwrap(),
+            "missing \")\" in formula"
+        );
+
+        assert_eq!(
+            Formula::parse("100 - [Bond fund] - age").unwrap().names(),
+            vec!["Bond fund", "age"]
+        );
+    }
+}
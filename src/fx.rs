@@ -0,0 +1,172 @@
+// Exchange rates, to convert holdings in other currencies to the base currency that the portfolio is rebalanced in.
+//
+// Rates are read from a CSV file with one rate per row, or from the price directives of a ledger, hledger or beancount
+// journal (`P 2019/08/02 USD 1.35 CAD`).
+//
+// Example:
+//
+//     USD, 1.35, CAD
+//     EUR, 1.47
+//
+// Each row reads as "1 USD is 1.35 CAD". Without a third column, the rate is to the base currency. A rate may also be
+// given the other way round, from the base currency.
+
+// local imports
+
+use error::RebalanceError;
+use holding::Holding;
+use input::Input;
+use journal;
+use money::{is_currency_code, parse_amount};
+
+// The base currency when holdings are in several currencies, and none is set.
+pub const DEFAULT_BASE_CURRENCY: &str = "CAD";
+
+struct Rate {
+    from: String,
+    // None for the base currency, whichever it is.
+    to: Option<String>,
+    rate: f64,
+}
+
+#[derive(Default)]
+pub struct FxRates {
+    // Later rates take precedence over earlier ones.
+    rates: Vec<Rate>,
+}
+
+impl FxRates {
+    pub fn add(&mut self, from: &str, to: Option<&str>, rate: f64) -> Result<(), String> {
+        if !(rate > 0.0 && rate.is_finite()) {
+            return Err(format!(
+                "invalid exchange rate {} for {}; expected a positive number",
+                rate, from
+            ));
+        }
+
+        self.rates.push(Rate {
+            from: from.trim().to_uppercase(),
+            to: to.map(|to| to.trim().to_uppercase()),
+            rate,
+        });
+
+        Ok(())
+    }
+
+    // Value in the base currency of one unit of the given currency, if known.
+    pub fn rate(&self, currency: &str, base_currency: &str) -> Option<f64> {
+        if currency.eq_ignore_ascii_case(base_currency) {
+            return Some(1.0);
+        }
+
+        let currency = currency.to_uppercase();
+        let base_currency = base_currency.to_uppercase();
+
+        self.rates.iter().rev().find_map(|rate| {
+            let to = rate.to.as_ref().unwrap_or(&base_currency);
+
+            if rate.from == currency && *to == base_currency {
+                Some(rate.rate)
+            } else if rate.from == base_currency && *to == currency {
+                Some(1.0 / rate.rate)
+            } else {
+                None
+            }
+        })
+    }
+}
+
+pub fn read_fx_rates(input: &Input) -> Result<FxRates, RebalanceError> {
+    let mut fx_rates = FxRates::default();
+
+    if journal::is_journal(input) {
+        for (commodity, price, price_commodity) in journal::read_prices(input)? {
+            // Prices of securities are not exchange rates.
+            if !is_currency_code(&commodity) || !is_currency_code(&price_commodity) {
+                continue;
+            }
+
+            fx_rates
+                .add(&commodity, Some(&price_commodity), price)
+                .map_err(|error| RebalanceError::parse(&input.name, None, None, error))?;
+        }
+
+        return Ok(fx_rates);
+    }
+
+    let path = input.name.as_str();
+
+    let mut reader = input.csv_reader();
+
+    for result in reader.records() {
+        let record = result.map_err(|error| RebalanceError::csv(path, error))?;
+
+        let line = record.position().map(|position| position.line());
+        let error = |message: String| RebalanceError::parse(path, line, None, message);
+
+        let cells: Vec<&str> = record
+            .iter()
+            .map(|cell| cell.trim())
+            .filter(|cell| !cell.is_empty())
+            .collect();
+
+        let (from, rate, to) = match cells.as_slice() {
+            [] => continue,
+            [from, rate] => (from, rate, None),
+            [from, rate, to] => (from, rate, Some(*to)),
+            _ => {
+                return Err(error(
+                    "expected a currency, a rate and, optionally, the currency of the rate"
+                        .to_string(),
+                ))
+            }
+        };
+
+        for currency in Some(*from).iter().chain(to.iter()) {
+            if !is_currency_code(currency) {
+                return Err(error(format!("invalid currency \"{}\"", currency)));
+            }
+        }
+
+        let rate = parse_amount(rate, '.').map_err(error)?;
+
+        fx_rates.add(from, to, rate).map_err(error)?;
+    }
+
+    Ok(fx_rates)
+}
+
+// The base currency is the one given, or else the currency of the holdings when they are all in one currency.
+pub fn base_currency(currency: Option<String>, holdings: &[Holding]) -> String {
+    if let Some(currency) = currency {
+        return currency;
+    }
+
+    let mut currencies = holdings
+        .iter()
+        .filter_map(|holding| holding.currency.as_ref());
+
+    match currencies.next() {
+        Some(first) if currencies.all(|currency| currency.eq_ignore_ascii_case(first)) => {
+            first.to_uppercase()
+        }
+        _ => DEFAULT_BASE_CURRENCY.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fx_rates() {
+        let input = Input::inline("rates.csv", "USD, 1.35, CAD\nEUR, 1.5\nCAD, 0.68, USD\n");
+
+        let fx_rates = read_fx_rates(&input).unwrap();
+
+        assert_eq!(fx_rates.rate("CAD", "CAD"), Some(1.0));
+        assert_eq!(fx_rates.rate("EUR", "CAD"), Some(1.5));
+        assert_eq!(fx_rates.rate("usd", "CAD"), Some(1.0 / 0.68));
+        assert_eq!(fx_rates.rate("GBP", "CAD"), None);
+    }
+}
@@ -0,0 +1,12 @@
+// A position read from a portfolio source, before it is matched against the targets.
+pub struct Holding {
+    pub name: String,
+    pub value: f64,
+    // Number of units held, and the price of each unit.
+    pub units: Option<(f64, f64)>,
+    pub account: Option<String>,
+    // Currency of the value and unit price, if the source reports one; otherwise, the base currency.
+    pub currency: Option<String>,
+    // Number of units held, for a holding listed without a value or a price. It is valued from a price file.
+    pub unvalued_quantity: Option<f64>,
+}
@@ -0,0 +1,89 @@
+// An input file: read from a path, from standard input when the path is `-`, or given inline on the command line.
+//
+// Inputs are read whole, so that nothing is written to temporary files; portfolios hold sensitive balances.
+
+// rust imports
+
+use std::fs;
+use std::io::{self, Read};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+// local imports
+
+use error::RebalanceError;
+
+pub const STDIN_PATH: &str = "-";
+
+static HAS_READ_STDIN: AtomicBool = AtomicBool::new(false);
+
+pub struct Input {
+    // Path of the file, or a description of where the input comes from, for messages.
+    pub name: String,
+    pub contents: String,
+    is_file: bool,
+}
+
+impl Input {
+    pub fn read(path: &str) -> Result<Input, RebalanceError> {
+        if path == STDIN_PATH {
+            if HAS_READ_STDIN.swap(true, Ordering::SeqCst) {
+                return Err(RebalanceError::Invalid(
+                    "only one input can be read from standard input (-)".to_string(),
+                ));
+            }
+
+            let mut contents = String::new();
+
+            io::stdin()
+                .read_to_string(&mut contents)
+                .map_err(|error| RebalanceError::io("<stdin>", error))?;
+
+            return Ok(Input {
+                name: "<stdin>".to_string(),
+                contents,
+                is_file: false,
+            });
+        }
+
+        let contents = fs::read_to_string(path).map_err(|error| RebalanceError::io(path, error))?;
+
+        Ok(Input {
+            name: path.to_string(),
+            contents,
+            is_file: true,
+        })
+    }
+
+    pub fn inline(name: &str, contents: &str) -> Input {
+        Input {
+            name: name.to_string(),
+            contents: contents.to_string(),
+            is_file: false,
+        }
+    }
+
+    // Whether this is a file with one of the given extensions.
+    pub fn has_extension(&self, extensions: &[&str]) -> bool {
+        if !self.is_file {
+            return false;
+        }
+
+        match Path::new(&self.name)
+            .extension()
+            .and_then(|extension| extension.to_str())
+        {
+            Some(extension) => extensions
+                .iter()
+                .any(|candidate| extension.eq_ignore_ascii_case(candidate)),
+            None => false,
+        }
+    }
+
+    pub fn csv_reader(&self) -> csv::Reader<&[u8]> {
+        csv::ReaderBuilder::new()
+            .has_headers(false)
+            .flexible(true)
+            .from_reader(self.contents.as_bytes())
+    }
+}
@@ -0,0 +1,644 @@
+// Reading of holdings from a plain-text accounting journal (ledger, hledger or beancount).
+//
+// Balances are summed for every account under the configured prefix. Each commodity with a price directive (ledger's
+// `P` or beancount's `price`) is a security valued at its latest price, and becomes a holding named after the
+// commodity. Any other commodity is treated as cash, and becomes a holding named after the last segment of its
+// account. Accounts may instead be mapped explicitly to asset names.
+//
+// The currency of a holding is the commodity of its price, or the commodity of the cash.
+
+// rust imports
+
+use std::collections::HashMap;
+
+// local imports
+
+use error::RebalanceError;
+use holding::Holding;
+use input::Input;
+use money::is_currency_code;
+
+pub struct JournalOptions {
+    // Only accounts equal to, or under, this account are read.
+    pub account_prefix: String,
+    // Accounts (or parents of accounts) mapped to asset names.
+    pub account_names: Vec<(String, String)>,
+}
+
+impl Default for JournalOptions {
+    fn default() -> Self {
+        JournalOptions {
+            account_prefix: "Assets:Investments".to_string(),
+            account_names: vec![],
+        }
+    }
+}
+
+impl JournalOptions {
+    fn is_included(&self, account: &str) -> bool {
+        let prefix = self
+            .account_prefix
+            .trim_end_matches('*')
+            .trim_end_matches(':');
+
+        account == prefix || account.starts_with(&format!("{}:", prefix))
+    }
+
+    // Finds the asset name of the account, preferring the most specific mapping.
+    fn mapped_name(&self, account: &str) -> Option<&str> {
+        self.account_names
+            .iter()
+            .filter(|(mapped_account, _)| {
+                account == mapped_account || account.starts_with(&format!("{}:", mapped_account))
+            })
+            .max_by_key(|(mapped_account, _)| mapped_account.len())
+            .map(|(_, name)| name.as_str())
+    }
+}
+
+const JOURNAL_EXTENSIONS: &[&str] = &["journal", "ledger", "hledger", "beancount", "bean"];
+
+pub fn is_journal(input: &Input) -> bool {
+    input.has_extension(JOURNAL_EXTENSIONS)
+}
+
+pub fn read_holdings(
+    input: &Input,
+    options: &JournalOptions,
+) -> Result<Vec<Holding>, RebalanceError> {
+    parse_holdings(&input.contents, options)
+        .map_err(|(line, error)| RebalanceError::parse(&input.name, line, None, error))
+}
+
+// Reads the latest price of each commodity from the price directives of a journal, as (commodity, price, price
+// commodity); e.g. `P 2019/08/02 USD 1.35 CAD` is read as ("USD", 1.35, "CAD").
+pub fn read_prices(input: &Input) -> Result<Vec<(String, f64, String)>, RebalanceError> {
+    let mut prices: Prices = HashMap::new();
+
+    for (index, line) in input.contents.lines().enumerate() {
+        if line.starts_with(' ') || line.starts_with('\t') || is_comment(line) {
+            continue;
+        }
+
+        let content = strip_comment(line).trim();
+
+        if let Some(result) = parse_price_directive(content) {
+            let (date, commodity, price) = result.map_err(|error| {
+                RebalanceError::parse(&input.name, Some(index as u64 + 1), None, error)
+            })?;
+
+            insert_price(&mut prices, &date, &commodity, price);
+        }
+    }
+
+    let mut prices: Vec<(String, f64, String)> = prices
+        .into_iter()
+        .map(|(commodity, (_, price))| (commodity, price.quantity, price.commodity))
+        .collect();
+    prices.sort_by(|left, right| left.0.cmp(&right.0));
+
+    Ok(prices)
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct Amount {
+    quantity: f64,
+    commodity: String,
+}
+
+struct Posting {
+    account: String,
+    amount: Option<Amount>,
+    // The amount this posting contributes towards balancing its transaction; i.e. its cost, if it has one.
+    weight: Option<Amount>,
+}
+
+// Balances of each account, per commodity.
+type Balances = HashMap<String, HashMap<String, f64>>;
+
+// The latest price of each commodity, with the date it was quoted.
+type Prices = HashMap<String, (String, Amount)>;
+
+// Errors are reported with the line they occur on, if any.
+fn parse_holdings(
+    contents: &str,
+    options: &JournalOptions,
+) -> Result<Vec<Holding>, (Option<u64>, String)> {
+    let mut balances: Balances = HashMap::new();
+    let mut prices: Prices = HashMap::new();
+
+    let mut postings: Vec<Posting> = vec![];
+    let mut in_transaction = false;
+
+    for (index, line) in contents.lines().enumerate() {
+        let line_number = index as u64 + 1;
+        let error = |message: String| (Some(line_number), message);
+
+        let is_indented = line.starts_with(' ') || line.starts_with('\t');
+        let content = strip_comment(line).trim();
+
+        if is_indented && in_transaction {
+            if content.is_empty() || is_metadata(content) {
+                continue;
+            }
+
+            postings.push(parse_posting(content).map_err(error)?);
+            continue;
+        }
+
+        if in_transaction {
+            post_transaction(&mut balances, &mut postings).map_err(error)?;
+            in_transaction = false;
+        }
+
+        if content.is_empty() || is_indented || is_comment(line) {
+            continue;
+        }
+
+        if let Some(result) = parse_price_directive(content) {
+            let (date, commodity, price) = result.map_err(error)?;
+            insert_price(&mut prices, &date, &commodity, price);
+            continue;
+        }
+
+        let mut tokens = content.split_whitespace();
+        let first = tokens.next().unwrap();
+
+        if !first.starts_with(|c: char| c.is_ascii_digit()) {
+            // Other directives, such as account declarations and includes, don't affect balances.
+            continue;
+        }
+
+        let directive = tokens.next().unwrap_or("");
+
+        match directive {
+            "open" | "close" | "commodity" | "balance" | "pad" | "note" | "document" | "event"
+            | "custom" | "query" => {}
+            _ => in_transaction = true,
+        }
+    }
+
+    if in_transaction {
+        post_transaction(&mut balances, &mut postings).map_err(|error| (None, error))?;
+    }
+
+    let mut accounts: Vec<&String> = balances
+        .keys()
+        .filter(|account| options.is_included(account))
+        .collect();
+    accounts.sort();
+
+    let mut holdings = vec![];
+
+    for account in accounts {
+        let mut commodities: Vec<(&String, &f64)> = balances[account].iter().collect();
+        commodities.sort_by(|left, right| left.0.cmp(right.0));
+
+        for (commodity, &quantity) in commodities {
+            if quantity == 0.0 {
+                continue;
+            }
+
+            let (name, value, units, currency) = match prices.get(commodity) {
+                Some((_, price)) => (
+                    commodity.to_string(),
+                    quantity * price.quantity,
+                    Some((quantity, price.quantity)),
+                    &price.commodity,
+                ),
+                None => (last_segment(account).to_string(), quantity, None, commodity),
+            };
+
+            let name = match options.mapped_name(account) {
+                Some(mapped_name) => mapped_name.to_string(),
+                None => name,
+            };
+
+            holdings.push(Holding {
+                name,
+                value,
+                units,
+                account: Some(account.to_string()),
+                // Symbols such as `$` are ambiguous, and taken to be the base currency.
+                currency: if is_currency_code(currency) {
+                    Some(currency.to_uppercase())
+                } else {
+                    None
+                },
+                unvalued_quantity: None,
+            });
+        }
+    }
+
+    Ok(holdings)
+}
+
+// Parses a price directive, if the line is one: ledger's `P DATE [TIME] COMMODITY AMOUNT` or beancount's
+// `DATE price COMMODITY AMOUNT`.
+fn parse_price_directive(content: &str) -> Option<Result<(String, String, Amount), String>> {
+    let tokens: Vec<&str> = content.split_whitespace().collect();
+
+    let (date, rest) = match tokens.as_slice() {
+        ["P", date, rest @ ..] => match rest.first() {
+            Some(time) if time.contains(':') => (date, &rest[1..]),
+            _ => (date, rest),
+        },
+        [date, "price", rest @ ..] if date.starts_with(|c: char| c.is_ascii_digit()) => {
+            (date, rest)
+        }
+        _ => return None,
+    };
+
+    if rest.len() < 2 {
+        return Some(Err(format!("invalid price directive \"{}\"", content)));
+    }
+
+    Some(
+        parse_amount(&rest[1..].join(" "))
+            .map(|price| (date.to_string(), unquote(rest[0]).to_string(), price)),
+    )
+}
+
+fn post_transaction(balances: &mut Balances, postings: &mut Vec<Posting>) -> Result<(), String> {
+    let elided: Vec<usize> = postings
+        .iter()
+        .enumerate()
+        .filter(|&(_, posting)| posting.amount.is_none())
+        .map(|(index, _)| index)
+        .collect();
+
+    if elided.len() > 1 {
+        return Err("transaction has more than one posting without an amount".to_string());
+    }
+
+    for posting in postings.iter() {
+        if let Some(ref amount) = posting.amount {
+            add_to_balance(
+                balances,
+                &posting.account,
+                amount.quantity,
+                &amount.commodity,
+            );
+        }
+    }
+
+    if let Some(&index) = elided.first() {
+        // The posting without an amount balances the transaction, in each commodity.
+        let mut remainders: HashMap<String, f64> = HashMap::new();
+
+        for posting in postings.iter() {
+            if let Some(ref weight) = posting.weight {
+                *remainders.entry(weight.commodity.clone()).or_insert(0.0) -= weight.quantity;
+            }
+        }
+
+        let account = postings[index].account.clone();
+
+        for (commodity, quantity) in remainders {
+            add_to_balance(balances, &account, quantity, &commodity);
+        }
+    }
+
+    postings.clear();
+
+    Ok(())
+}
+
+fn add_to_balance(balances: &mut Balances, account: &str, quantity: f64, commodity: &str) {
+    *balances
+        .entry(account.to_string())
+        .or_default()
+        .entry(commodity.to_string())
+        .or_insert(0.0) += quantity;
+}
+
+fn insert_price(prices: &mut Prices, date: &str, commodity: &str, price: Amount) {
+    // ledger dates may use slashes; normalize them so that dates compare as strings.
+    let date = date.replace('/', "-");
+
+    let is_newer = match prices.get(commodity) {
+        Some((latest_date, _)) => date >= *latest_date,
+        None => true,
+    };
+
+    if is_newer {
+        prices.insert(commodity.to_string(), (date, price));
+    }
+}
+
+fn parse_posting(content: &str) -> Result<Posting, String> {
+    // beancount postings may be flagged.
+    let content = content.trim_start_matches(['!', '*']).trim_start();
+
+    // Accounts are separated from amounts by two spaces or a tab. beancount account names can't contain spaces, so
+    // a single space is enough there.
+    let separator = content
+        .find("  ")
+        .or_else(|| content.find('\t'))
+        .or_else(|| content.find(' '));
+
+    let (account, rest) = match separator {
+        Some(separator) => (&content[..separator], content[separator..].trim()),
+        None => (content, ""),
+    };
+
+    // ledger virtual postings are written as (Account) or [Account].
+    let account = account
+        .trim_matches(|c| c == '(' || c == ')' || c == '[' || c == ']')
+        .to_string();
+
+    // Drop balance assertions.
+    let rest = match rest.find('=') {
+        Some(index) => rest[..index].trim(),
+        None => rest,
+    };
+
+    if rest.is_empty() {
+        return Ok(Posting {
+            account,
+            amount: None,
+            weight: None,
+        });
+    }
+
+    let (amount_text, cost) = split_cost(rest);
+
+    let amount = match parse_amount(amount_text) {
+        Ok(amount) => amount,
+        // An account name with a single space, without an amount.
+        Err(_) if separator.map(|index| content[index..].starts_with("  ")) != Some(true) => {
+            return Ok(Posting {
+                account: content.to_string(),
+                amount: None,
+                weight: None,
+            })
+        }
+        Err(error) => return Err(error),
+    };
+
+    let weight = match cost {
+        Some((cost_text, is_total)) => {
+            let cost = parse_amount(cost_text)?;
+
+            let quantity = if is_total {
+                cost.quantity.abs() * amount.quantity.signum()
+            } else {
+                cost.quantity * amount.quantity
+            };
+
+            Amount {
+                quantity,
+                commodity: cost.commodity,
+            }
+        }
+        None => amount.clone(),
+    };
+
+    Ok(Posting {
+        account,
+        amount: Some(amount),
+        weight: Some(weight),
+    })
+}
+
+// Splits an amount from its cost or price annotation, if any: `{unit cost}`, `{{total cost}}`, `@ unit price` or
+// `@@ total price`. The boolean is true for total costs.
+fn split_cost(text: &str) -> (&str, Option<(&str, bool)>) {
+    if let Some(index) = text.find("{{") {
+        let end = text[index..]
+            .find("}}")
+            .map(|end| index + end)
+            .unwrap_or(text.len());
+        return (
+            text[..index].trim(),
+            Some((text[index + 2..end].trim(), true)),
+        );
+    }
+
+    if let Some(index) = text.find('{') {
+        let end = text[index..]
+            .find('}')
+            .map(|end| index + end)
+            .unwrap_or(text.len());
+        let cost = text[index + 1..end].trim();
+
+        // beancount costs may carry a date and a label after the amount.
+        let cost = cost.split(',').next().unwrap_or("").trim();
+
+        if !cost.is_empty() {
+            return (text[..index].trim(), Some((cost, false)));
+        }
+
+        return (text[..index].trim(), None);
+    }
+
+    if let Some(index) = text.find("@@") {
+        return (text[..index].trim(), Some((text[index + 2..].trim(), true)));
+    }
+
+    if let Some(index) = text.find('@') {
+        return (
+            text[..index].trim(),
+            Some((text[index + 1..].trim(), false)),
+        );
+    }
+
+    (text, None)
+}
+
+// Parses amounts such as `10 VTI`, `VTI 10`, `$1,234.56`, `-$5`, `100.00 CAD` and `3 "VANGUARD 500"`.
+fn parse_amount(text: &str) -> Result<Amount, String> {
+    let text = text.trim();
+    let invalid = || format!("invalid amount \"{}\"", text);
+
+    let start = text
+        .find(|c: char| c.is_ascii_digit() || c == '.')
+        .ok_or_else(invalid)?;
+    let end = text[start..]
+        .find(|c: char| !(c.is_ascii_digit() || c == '.' || c == ','))
+        .map(|end| start + end)
+        .unwrap_or(text.len());
+
+    let before = text[..start].trim();
+    let after = text[end..].trim();
+
+    let is_negative = before.contains('-');
+    let before = before.trim_matches(|c: char| c == '-' || c == '+' || c.is_whitespace());
+
+    let commodity = match (before.is_empty(), after.is_empty()) {
+        (false, true) => before,
+        (true, false) => after,
+        (true, true) => "",
+        (false, false) => return Err(invalid()),
+    };
+
+    let quantity = text[start..end]
+        .replace(',', "")
+        .parse::<f64>()
+        .map_err(|_| invalid())?;
+
+    Ok(Amount {
+        quantity: if is_negative { -quantity } else { quantity },
+        commodity: unquote(commodity).to_string(),
+    })
+}
+
+fn unquote(text: &str) -> &str {
+    text.trim_matches('"')
+}
+
+fn strip_comment(line: &str) -> &str {
+    match line.find(';') {
+        Some(index) => &line[..index],
+        None => line,
+    }
+}
+
+fn is_comment(line: &str) -> bool {
+    line.starts_with([';', '#', '%', '|', '*'])
+}
+
+// beancount metadata, such as `note: "..."`, may follow a transaction or a posting.
+fn is_metadata(content: &str) -> bool {
+    match content.find(':') {
+        Some(index) => {
+            content[..index]
+                .chars()
+                .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-' || c == '_')
+                && content[index + 1..].starts_with(' ')
+        }
+        None => false,
+    }
+}
+
+fn last_segment(account: &str) -> &str {
+    account.rsplit(':').next().unwrap_or(account)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_beancount_holdings() {
+        let contents = r###"
+option "operating_currency" "USD"
+
+2019-01-01 open Assets:Investments:TFSA:VTI VTI
+2019-01-01 open Assets:Investments:TFSA:Cash USD
+2019-01-01 open Assets:Checking USD
+
+2019-01-15 * "Buy VTI"
+  Assets:Investments:TFSA:VTI   10 VTI {80.00 USD}
+  Assets:Investments:TFSA:Cash
+
+2019-01-15 * "Deposit"
+  Assets:Investments:TFSA:Cash   1000.00 USD
+    note: "monthly deposit"
+  Assets:Checking  -1000.00 USD
+
+2019-08-01 price VTI 85.00 USD
+2019-08-02 price VTI 87.00 USD
+        "###;
+
+        let options = JournalOptions {
+            account_names: vec![(
+                "Assets:Investments:TFSA:VTI".to_string(),
+                "Domestic Stock ETF".to_string(),
+            )],
+            ..JournalOptions::default()
+        };
+
+        let holdings = parse_holdings(contents, &options).unwrap();
+
+        assert_eq!(holdings.len(), 2);
+
+        assert_eq!(holdings[0].name, "Cash");
+        assert_eq!(holdings[0].value, 200.0);
+        assert_eq!(
+            holdings[0].account,
+            Some("Assets:Investments:TFSA:Cash".to_string())
+        );
+
+        assert_eq!(holdings[1].name, "Domestic Stock ETF");
+        assert_eq!(holdings[1].value, 870.0);
+        assert_eq!(holdings[1].units, Some((10.0, 87.0)));
+    }
+
+    #[test]
+    fn test_parse_ledger_holdings() {
+        let contents = r###"
+; Brokerage account
+account Assets:Investments:RRSP:Cash
+
+2019/01/15 * Deposit
+    Assets:Investments:RRSP:Cash    $1,000.00
+    Assets:Checking
+
+2019/01/20 Buy VTI
+    Assets:Investments:RRSP:VTI    10 VTI @ $80.00
+    Assets:Investments:RRSP:Cash
+
+P 2019/08/01 VTI 85.00 USD
+P 2019/08/02 12:00:00 VTI 87.00 USD
+        "###;
+
+        let holdings = parse_holdings(contents, &JournalOptions::default()).unwrap();
+
+        assert_eq!(holdings.len(), 2);
+
+        // The elided posting balances the purchase, and the `$` symbol is taken to be the base currency.
+        assert_eq!(holdings[0].name, "Cash");
+        assert_eq!(holdings[0].value, 200.0);
+        assert_eq!(holdings[0].units, None);
+        assert_eq!(holdings[0].currency, None);
+
+        assert_eq!(holdings[1].name, "VTI");
+        assert_eq!(holdings[1].value, 870.0);
+        assert_eq!(holdings[1].units, Some((10.0, 87.0)));
+        assert_eq!(
+            holdings[1].account,
+            Some("Assets:Investments:RRSP:VTI".to_string())
+        );
+        assert_eq!(holdings[1].currency, Some("USD".to_string()));
+    }
+
+    #[test]
+    fn test_parse_invalid_journals() {
+        let error = |contents: &str| {
+            parse_holdings(contents, &JournalOptions::default())
+                .err()
+                .unwrap()
+        };
+
+        assert_eq!(
+            error("2019/01/15 Transfer\n    Assets:Investments:Cash\n    Assets:Checking\n\nP 2019/08/01 VTI 87.00 USD"),
+            (
+                Some(4),
+                "transaction has more than one posting without an amount".to_string()
+            )
+        );
+        assert_eq!(
+            error("2019/01/15 Transfer\n    Assets:Investments:Cash\n    Assets:Checking"),
+            (
+                None,
+                "transaction has more than one posting without an amount".to_string()
+            )
+        );
+        assert_eq!(
+            error("2019/01/15 Buy\n    Assets:Investments:VTI    $10 VTI\n    Assets:Checking"),
+            (Some(2), "invalid amount \"$10 VTI\"".to_string())
+        );
+        assert_eq!(
+            error("P 2019/08/01 VTI"),
+            (
+                Some(1),
+                "invalid price directive \"P 2019/08/01 VTI\"".to_string()
+            )
+        );
+        assert_eq!(
+            error("2019-08-01 price VTI USD"),
+            (Some(1), "invalid amount \"USD\"".to_string())
+        );
+    }
+}
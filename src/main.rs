@@ -3,39 +3,114 @@ extern crate clap;
 extern crate csv;
 extern crate num;
 extern crate tabwriter;
+extern crate toml;
 
+mod classes;
+mod error;
+mod format;
+mod formula;
+mod fx;
+mod holding;
+mod input;
+mod journal;
+mod money;
+mod ofx;
+mod plan;
+mod prices;
 mod rebalance;
+mod targets;
+mod validation;
 
 // rust imports
 
 use std::collections::HashMap;
+use std::process;
 
 // 3rd-party imports
 
-use clap::{App, AppSettings, Arg};
+use chrono::Local;
+
+use clap::{App, AppSettings, Arg, ArgMatches};
+
+use num::{BigRational, Zero};
 
 // local imports
 
+use classes::{read_asset_classes, AssetClasses};
+use error::RebalanceError;
+use format::{Column, PortfolioFormat, PRESET_NAMES};
+use fx::{read_fx_rates, FxRates};
+use holding::Holding;
+use input::Input;
+use money::parse_amount;
+use plan::{read_plan, Plan, Source};
+use prices::{read_prices, Prices, DEFAULT_MAX_PRICE_AGE};
 use rebalance::{
-    convert_old_portfolio, lazy_rebalance, new_lazy_rebalance, new_to_string, to_ledger_string,
-    to_string, Asset,
+    assets_left_out_of_band, has_assets_out_of_band, has_left_out_trades, has_trades,
+    rebalance_net_of_costs, round_to_whole_units, to_ledger_string, to_string, to_tree_string,
+    Asset, AssetAmounts, BandTarget, OutputOptions, RebalanceMode, RebalanceOptions, TradeCosts,
+    UntargetedMode, BAND_TARGETS, DEFAULT_TOLERANCE, REBALANCE_MODES, UNTARGETED_MODES,
+};
+use targets::{
+    glide_position, is_glide_path_header, normalize_targets, parse_allocation, parse_date,
+    Allocation, GlideOptions, GlidePosition, TargetMap, TargetNotation, Targets, TARGET_NOTATIONS,
 };
 
 // app
 
 fn main() {
+    if let Err(error) = run() {
+        eprintln!("error: {}", error);
+        process::exit(1);
+    }
+}
+
+fn run() -> Result<(), RebalanceError> {
     let matches = App::new("rebalance-app")
         .version("1.2.0")
         .author("Alberto Leal (github.com/dashed) <mailforalberto@gmail.com>")
         .about("Optimal lazy portfolio rebalancing calculator")
         .setting(AppSettings::AllowNegativeNumbers)
+        .arg(
+            Arg::with_name("plan")
+                .long("plan")
+                .value_name("FILE")
+                .help("Sets a plan file declaring the targets, holdings and options (TOML)")
+                .required(false)
+                .takes_value(true),
+        )
         .arg(
             Arg::with_name("targets")
                 .short("t")
                 .long("targets")
                 .value_name("FILE")
-                .help("Sets a targets file")
-                .required(true)
+                .help("Sets a targets file (- for standard input)")
+                .required_unless("plan")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("target_notation")
+                .long("target-notation")
+                .value_name("NOTATION")
+                .help("Sets how target allocations without a % sign are read: as percentages (percent), as fractions such as 0.4 (fraction), or as relative weights (weight) (default: percent)")
+                .required(false)
+                .takes_value(true)
+                .possible_values(TARGET_NOTATIONS),
+        )
+        .arg(
+            Arg::with_name("as_of")
+                .long("as-of")
+                .value_name("DATE")
+                .help("Sets the date (YYYY-MM-DD) to read the allocations of a glide path at (default: today)")
+                .required(false)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("birth_date")
+                .long("birth-date")
+                .value_name("DATE")
+                .help("Sets the birth date (YYYY-MM-DD) of the investor, for a glide path by age")
+                .required(false)
                 .takes_value(true),
         )
         .arg(
@@ -43,8 +118,26 @@ fn main() {
                 .short("p")
                 .long("portfolio")
                 .value_name("FILE")
-                .help("Sets a portfolio file")
-                .required(true)
+                .help("Sets a portfolio file (CSV, an OFX/QFX statement, or a ledger/hledger/beancount journal; - for standard input)")
+                .required_unless_one(&["plan", "portfolio_inline"])
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("portfolio_inline")
+                .long("portfolio-inline")
+                .value_name("CSV")
+                .help("Sets the contents of the portfolio file, one holding per line, such as $'VTI,43500\\nBND,16500' in bash")
+                .required(false)
+                .takes_value(true)
+                .conflicts_with("portfolio"),
+        )
+        .arg(
+            Arg::with_name("classes")
+                .short("c")
+                .long("classes")
+                .value_name("FILE")
+                .help("Sets an asset classes file grouping several tickers into one target (- for standard input)")
+                .required(false)
                 .takes_value(true),
         )
         .arg(
@@ -56,12 +149,291 @@ fn main() {
                 .required(false)
                 .takes_value(true),
         )
+        .arg(
+            Arg::with_name("format")
+                .short("f")
+                .long("format")
+                .value_name("FORMAT")
+                .help("Sets the broker export format of the portfolio file")
+                .required(false)
+                .takes_value(true)
+                .possible_values(PRESET_NAMES),
+        )
+        .arg(
+            Arg::with_name("portfolio_headers")
+                .long("portfolio-headers")
+                .help("Treats the first row of the portfolio file as a header row")
+                .required(false)
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("name_column")
+                .long("name-column")
+                .value_name("HEADER")
+                .help("Sets the portfolio column header of the asset name (implies --portfolio-headers)")
+                .required(false)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("value_column")
+                .long("value-column")
+                .value_name("HEADER")
+                .help("Sets the portfolio column header of the asset value (implies --portfolio-headers)")
+                .required(false)
+                .takes_value(true)
+                .conflicts_with("portfolio_value_index"),
+        )
+        .arg(
+            Arg::with_name("quantity_column")
+                .long("quantity-column")
+                .value_name("HEADER")
+                .help("Sets the portfolio column header of the number of units held; without a value or price column, holdings are valued with --prices (implies --portfolio-headers)")
+                .required(false)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("price_column")
+                .long("price-column")
+                .value_name("HEADER")
+                .help("Sets the portfolio column header of the price per unit (implies --portfolio-headers)")
+                .required(false)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("account_column")
+                .long("account-column")
+                .value_name("HEADER")
+                .help("Sets the portfolio column header of the account holding the asset (implies --portfolio-headers)")
+                .required(false)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("currency_column")
+                .long("currency-column")
+                .value_name("HEADER")
+                .help("Sets the portfolio column header of the currency of each holding (implies --portfolio-headers)")
+                .required(false)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("journal_prefix")
+                .long("journal-prefix")
+                .value_name("ACCOUNT")
+                .help("Sets the journal account whose subaccounts hold the portfolio (default: Assets:Investments)")
+                .required(false)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("journal_account")
+                .long("journal-account")
+                .value_name("ACCOUNT=ASSET")
+                .help("Maps a journal account, and its subaccounts, to an asset name")
+                .required(false)
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1),
+        )
+        .arg(
+            Arg::with_name("decimal_separator")
+                .long("decimal-separator")
+                .value_name("CHAR")
+                .help("Sets the decimal separator of the portfolio values (default: .)")
+                .required(false)
+                .takes_value(true),
+        )
         .arg(
             Arg::with_name("contribution")
                 .help("Sets the contribution amount")
-                .required(true)
+                .required_unless_one(&["plan", "check", "cash", "mode"])
                 .index(1),
         )
+        .arg(
+            Arg::with_name("currency")
+                .long("currency")
+                .value_name("CODE")
+                .help("Sets the base currency that holdings are converted to and ledger transactions are in (default: the currency of the holdings, or CAD if they are in several)")
+                .required(false)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("fx_rates")
+                .long("fx-rates")
+                .value_name("FILE")
+                .help("Sets an FX rates file (CSV, or a journal with price directives) to convert holdings to the base currency (- for standard input)")
+                .required(false)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("mode")
+                .long("mode")
+                .value_name("MODE")
+                .help("Sets how far the portfolio is rebalanced: only by investing the contribution (lazy), by buying and selling every asset to its target (full), or by selling only what drifted beyond the tolerance (hybrid), or by trading only the assets outside their tolerance band (bands); the contribution defaults to 0 in all but lazy mode (default: lazy)")
+                .required(false)
+                .takes_value(true)
+                .possible_values(REBALANCE_MODES),
+        )
+        .arg(
+            Arg::with_name("tolerance")
+                .long("tolerance")
+                .value_name("POINTS")
+                .help("Sets how many percentage points an asset may drift from its target in hybrid mode (default: 5)")
+                .required(false)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("band_absolute")
+                .long("band-absolute")
+                .value_name("[ASSET=]POINTS")
+                .help("Sets how many percentage points of the portfolio an asset may drift from its target in bands mode, of every asset or of the one named (default: 5)")
+                .required(false)
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1),
+        )
+        .arg(
+            Arg::with_name("band_relative")
+                .long("band-relative")
+                .value_name("[ASSET=]PERCENT")
+                .help("Sets how far an asset may drift from its target in bands mode, in percent of its target allocation, of every asset or of the one named; the narrower band applies (default: 25)")
+                .required(false)
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1),
+        )
+        .arg(
+            Arg::with_name("band_trade_to")
+                .long("band-trade-to")
+                .value_name("TARGET")
+                .help("Sets where assets outside their band are traded to in bands mode: their target, or the edge of their band (default: target)")
+                .required(false)
+                .takes_value(true)
+                .possible_values(BAND_TARGETS),
+        )
+        .arg(
+            Arg::with_name("untargeted")
+                .long("untargeted")
+                .value_name("MODE")
+                .help("Sets how holdings without a target are rebalanced: left out (ignore), counted in the total but not traded (hold), or sold in full to fund the contribution (liquidate) (default: ignore)")
+                .required(false)
+                .takes_value(true)
+                .possible_values(UNTARGETED_MODES),
+        )
+        .arg(
+            Arg::with_name("cash")
+                .long("cash")
+                .value_name("NAME")
+                .help("Treats the holdings of this name as uninvested cash, all of which above the reserve is invested on top of the contribution (may be repeated)")
+                .required(false)
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1),
+        )
+        .arg(
+            Arg::with_name("cash_reserve")
+                .long("cash-reserve")
+                .value_name("AMOUNT")
+                .help("Sets the amount of cash to keep uninvested (default: 0)")
+                .required(false)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("min_trade")
+                .long("min-trade")
+                .value_name("[ASSET=]AMOUNT")
+                .help("Leaves out trades smaller than this amount, of every asset or of the one named, and invests their money in the other assets")
+                .required(false)
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1),
+        )
+        .arg(
+            Arg::with_name("buy_commission")
+                .long("buy-commission")
+                .value_name("[ASSET=]AMOUNT")
+                .help("Sets the commission per buy, of every asset or of the one named; costs are paid out of the contribution")
+                .required(false)
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1),
+        )
+        .arg(
+            Arg::with_name("sell_commission")
+                .long("sell-commission")
+                .value_name("[ASSET=]AMOUNT")
+                .help("Sets the commission per sell, of every asset or of the one named")
+                .required(false)
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1),
+        )
+        .arg(
+            Arg::with_name("share_fee")
+                .long("share-fee")
+                .value_name("[ASSET=]AMOUNT")
+                .help("Sets the fee per share traded, of every asset or of the one named")
+                .required(false)
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1),
+        )
+        .arg(
+            Arg::with_name("spread")
+                .long("spread")
+                .value_name("[ASSET=]PERCENT")
+                .help("Sets the cost of the bid/ask spread, as a percentage of the amount traded, of every asset or of the one named")
+                .required(false)
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1),
+        )
+        .arg(
+            Arg::with_name("prices")
+                .long("prices")
+                .value_name("FILE")
+                .help("Sets a price file (ticker, price, currency, date) to value holdings by their number of units (- for standard input)")
+                .required(false)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("max_price_age")
+                .long("max-price-age")
+                .value_name("DAYS")
+                .help("Warns of prices older than this number of days (default: 7)")
+                .required(false)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("rounding")
+                .long("rounding")
+                .value_name("PLACES")
+                .help("Sets the number of decimal places of money amounts (default: 2)")
+                .required(false)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("whole_shares")
+                .long("whole-shares")
+                .help("Rounds trades of holdings with a unit price to whole shares, and reports the cash left over")
+                .required(false)
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("check")
+                .long("check")
+                .help("Checks the targets and portfolio, and reports any issues without rebalancing")
+                .required(false)
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("validation")
+                .long("validation")
+                .value_name("ISSUE=LEVEL")
+                .help("Reports an issue as a warning or an error (issues: targets-sum, duplicate-target, invalid-target, untargeted-holding, unheld-target, stale-price, or all)")
+                .required(false)
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1),
+        )
         .arg(
             Arg::with_name("ledger")
                 .short("l")
@@ -89,202 +461,2180 @@ fn main() {
         )
         .get_matches();
 
-    let path_to_targets = matches.value_of("targets").unwrap();
+    let plan = match matches.value_of("plan") {
+        Some(path_to_plan) => Some(read_plan(path_to_plan)?),
+        None => None,
+    };
 
-    let path_to_portfolio = matches.value_of("portfolio").unwrap();
+    let Plan {
+        contribution: plan_contribution,
+        targets: plan_targets,
+        target_notation: plan_target_notation,
+        as_of: plan_as_of,
+        birth_date: plan_birth_date,
+        holdings: plan_holdings,
+        classes: plan_classes,
+        fx_rates: plan_fx_rates,
+        prices: plan_prices,
+        untargeted: plan_untargeted,
+        cash: plan_cash,
+        cash_reserve: plan_cash_reserve,
+        min_trades: plan_min_trades,
+        costs: plan_costs,
+        max_price_age: plan_max_price_age,
+        portfolio_format: plan_portfolio_format,
+        ledger: plan_ledger,
+        validation: plan_validation,
+        currency: plan_currency,
+        rounding: plan_rounding,
+        whole_shares: plan_whole_shares,
+        mode: plan_mode,
+        tolerance: plan_tolerance,
+        bands: plan_bands,
+    } = plan.unwrap_or_default();
 
-    let portfolio_value_index = matches
-        .value_of("portfolio_value_index")
-        .map(|x| x.parse::<usize>().unwrap())
-        .unwrap_or(1);
+    // Files given on the command line take precedence over the targets and holdings of the plan.
 
-    let contribution_amount: f64 = matches
-        .value_of("contribution")
-        .map(|x| x.parse::<f64>().unwrap())
-        .unwrap();
+    let targets = match matches.value_of("targets") {
+        Some(path_to_targets) => Source::File(path_to_targets.to_string()),
+        None => match plan_targets {
+            Some(targets) => targets,
+            None => {
+                return Err(RebalanceError::Invalid(
+                    "no targets given; use --targets or declare them in the plan".to_string(),
+                ))
+            }
+        },
+    };
 
-    println!(
-        "Contributing: {}\n",
-        format!("{:.*}", 2, contribution_amount)
-    );
+    let portfolio = match (
+        matches.value_of("portfolio"),
+        matches.value_of("portfolio_inline"),
+    ) {
+        (Some(path_to_portfolio), _) => Portfolio::File(Input::read(path_to_portfolio)?),
+        (None, Some(data)) => Portfolio::File(Input::inline("--portfolio-inline", data)),
+        (None, None) => match plan_holdings {
+            Some(Source::File(path_to_portfolio)) => {
+                Portfolio::File(Input::read(&path_to_portfolio)?)
+            }
+            Some(Source::Inline(holdings)) => Portfolio::Holdings(holdings),
+            None => {
+                return Err(RebalanceError::Invalid(
+                    "no portfolio given; use --portfolio or declare holdings in the plan"
+                        .to_string(),
+                ))
+            }
+        },
+    };
 
-    let target_map = create_target_map(path_to_targets);
+    let decimal_separator = match matches.value_of("decimal_separator") {
+        None => None,
+        Some(separator) if separator.chars().count() == 1 => separator.chars().next(),
+        Some(separator) => {
+            return Err(RebalanceError::Invalid(format!(
+                "decimal separator must be a single character, got \"{}\"",
+                separator
+            )));
+        }
+    };
 
-    let portfolio = create_portfolio(path_to_portfolio, portfolio_value_index, target_map);
+    let mut portfolio_format = match matches.value_of("format") {
+        Some(format) => PortfolioFormat::preset(format).unwrap(),
+        None => plan_portfolio_format,
+    };
 
-    let balanced_portfolio = lazy_rebalance(contribution_amount, portfolio);
+    // Columns given on the command line override those of the format.
 
-    if matches.is_present("ledger") {
-        let dest_account_name = matches
-            .value_of("dest_account_name")
-            .unwrap_or("destination_account");
-        let source_account_name = matches
-            .value_of("source_account_name")
-            .unwrap_or("source_account");
+    if let Some(index) = matches.value_of("portfolio_value_index") {
+        let index = index.parse::<usize>().map_err(|_| {
+            RebalanceError::Invalid(format!(
+                "portfolio value index must be a column index, got \"{}\"",
+                index
+            ))
+        })?;
 
-        println!(
-            "{}",
-            to_ledger_string(&balanced_portfolio, dest_account_name, source_account_name)
-        );
-        return;
+        portfolio_format.value = Some(Column::Index(index));
     }
 
-    println!("{}", to_string(&balanced_portfolio));
-}
+    let header_columns = [
+        "name_column",
+        "value_column",
+        "quantity_column",
+        "price_column",
+        "account_column",
+        "currency_column",
+    ];
 
-struct Percent(f64);
+    for &column in header_columns.iter() {
+        let header = match matches.value_of(column) {
+            Some(header) => Column::Header(header.to_string()),
+            None => continue,
+        };
 
-fn create_target_map(path_to_targets: &str) -> HashMap<String, Percent> {
-    let mut reader = csv::ReaderBuilder::new()
-        .has_headers(false)
-        .from_path(path_to_targets)
-        .unwrap();
+        match column {
+            "name_column" => portfolio_format.name = header,
+            "value_column" => portfolio_format.value = Some(header),
+            "quantity_column" => portfolio_format.quantity = Some(header),
+            "price_column" => portfolio_format.price = Some(header),
+            "account_column" => portfolio_format.account = Some(header),
+            _ => portfolio_format.currency = Some(header),
+        }
 
-    let mut target_map = HashMap::new();
+        portfolio_format.has_headers = true;
+    }
 
-    for result in reader.records() {
-        let record = result.unwrap();
+    // Without a value column, holdings given as quantity and price are valued as quantity × price, and holdings given as
+    // quantity alone are valued from the price file.
+    if !matches.is_present("format")
+        && !matches.is_present("value_column")
+        && !matches.is_present("portfolio_value_index")
+        && matches.is_present("quantity_column")
+    {
+        portfolio_format.value = None;
+    }
 
-        let asset_name = record.get(0).unwrap().trim().to_string();
-        let allocation: Percent = {
-            let column = record.get(1).unwrap().trim();
+    if matches.is_present("portfolio_headers") {
+        portfolio_format.has_headers = true;
+    }
 
-            let allocation = column.parse::<f64>().unwrap();
+    if let Some(decimal_separator) = decimal_separator {
+        portfolio_format.decimal_separator = decimal_separator;
+    }
 
-            if allocation <= 0.0 {
-                continue;
+    if let Some(prefix) = matches.value_of("journal_prefix") {
+        portfolio_format.journal.account_prefix = prefix.to_string();
+    }
+
+    if let Some(mappings) = matches.values_of("journal_account") {
+        for mapping in mappings {
+            let mut parts = mapping.splitn(2, '=');
+
+            match (parts.next(), parts.next()) {
+                (Some(account), Some(asset_name)) => portfolio_format
+                    .journal
+                    .account_names
+                    .push((account.trim().to_string(), asset_name.trim().to_string())),
+                _ => {
+                    return Err(RebalanceError::Invalid(format!(
+                        "journal account mapping must be of the form ACCOUNT=ASSET, got \"{}\"",
+                        mapping
+                    )));
+                }
             }
+        }
+    }
 
-            Percent(allocation)
-        };
+    let mut output_options = OutputOptions::default();
+
+    let currency = matches
+        .value_of("currency")
+        .map(|x| x.to_uppercase())
+        .or(plan_currency);
+
+    let rounding = match matches.value_of("rounding") {
+        Some(places) => match places.parse::<usize>() {
+            Ok(places) => Some(places),
+            Err(_) => {
+                return Err(RebalanceError::Invalid(format!(
+                    "rounding must be a non-negative number of decimal places, got \"{}\"",
+                    places
+                )));
+            }
+        },
+        None => plan_rounding,
+    };
 
-        target_map.insert(asset_name, allocation);
+    if let Some(places) = rounding {
+        output_options.decimal_places = places;
     }
 
-    target_map
-}
+    let mut validation_options = plan_validation;
 
-fn create_portfolio(
-    path_to_portfolio: &str,
-    portfolio_value_index: usize,
-    target_map: HashMap<String, Percent>,
-) -> Vec<Asset> {
-    let mut reader = csv::ReaderBuilder::new()
-        .has_headers(false)
-        .from_path(path_to_portfolio)
-        .unwrap();
+    if let Some(settings) = matches.values_of("validation") {
+        for setting in settings {
+            let mut parts = setting.splitn(2, '=');
 
-    let mut portfolio_map: HashMap<String, Asset> = HashMap::new();
+            match (parts.next(), parts.next()) {
+                (Some(kind_name), Some(severity_name)) => {
+                    validation_options.set(kind_name, severity_name)?
+                }
+                _ => {
+                    return Err(RebalanceError::Invalid(format!(
+                        "validation setting must be of the form ISSUE=LEVEL, got \"{}\"",
+                        setting
+                    )))
+                }
+            }
+        }
+    }
 
-    for result in reader.records() {
-        let record = result.unwrap();
+    let mut issues = vec![];
 
-        let asset_name = record.get(0).unwrap().trim().to_string();
+    let parse_date_option = |name: &str, date: &str| {
+        parse_date(date).ok_or_else(|| {
+            RebalanceError::Invalid(format!(
+                "invalid {} \"{}\"; expected YYYY-MM-DD",
+                name, date
+            ))
+        })
+    };
 
-        let value = {
-            let value: String = record
-                .get(portfolio_value_index)
-                .unwrap()
-                .trim()
-                .chars()
-                .skip(1)
-                .collect();
+    let glide_options = GlideOptions {
+        as_of: match matches.value_of("as_of") {
+            Some(date) => parse_date_option("date", date)?,
+            None => plan_as_of.unwrap_or_else(|| Local::today().naive_local()),
+        },
+        birth_date: match matches.value_of("birth_date") {
+            Some(date) => Some(parse_date_option("birth date", date)?),
+            None => plan_birth_date,
+        },
+    };
 
-            value.parse::<f64>().unwrap()
-        };
+    let mut targets = match targets {
+        Source::File(path_to_targets) => {
+            let input = Input::read(&path_to_targets)?;
 
-        match target_map.get(&asset_name) {
-            None => {}
-            Some(&Percent(target_allocation_percent)) => {
-                let target_allocation_percent =
-                    adjust_target_allocation_percent(target_allocation_percent);
+            issues.extend(validation::check_target_file(&input)?);
 
-                let asset = Asset::new(asset_name.clone(), target_allocation_percent, value);
+            read_target_map(&input, &glide_options)?
+        }
+        Source::Inline(targets) => targets,
+    };
 
-                portfolio_map.insert(asset_name, asset);
-            }
+    let has_formulas = targets.has_formulas();
+
+    targets.resolve_formulas(glide_options.age())?;
+
+    let target_notation = match matches.value_of("target_notation") {
+        Some(notation) => TargetNotation::from_name(notation).unwrap(),
+        None => plan_target_notation.unwrap_or(TargetNotation::Percent),
+    };
+
+    let target_tree = normalize_targets(targets, target_notation)?;
+
+    let target_issues = validation::check_targets(&target_tree);
+
+    // Unlike targets written out, formulas can't be fixed up by hand, so they must resolve to whole allocations.
+    if has_formulas {
+        if let Some(issue) = target_issues.first() {
+            return Err(RebalanceError::Invalid(format!(
+                "{}; formula targets must sum to 100%",
+                issue.message
+            )));
         }
     }
 
-    for asset_name in target_map.keys() {
-        if portfolio_map.contains_key(asset_name) {
-            continue;
-        }
+    issues.extend(target_issues);
 
-        let &Percent(target_allocation_percent) = target_map.get(asset_name).unwrap();
+    let target_map = target_tree.leaves();
 
-        let target_allocation_percent = adjust_target_allocation_percent(target_allocation_percent);
+    let asset_classes = match matches
+        .value_of("classes")
+        .map(|path_to_classes| Source::File(path_to_classes.to_string()))
+        .or(plan_classes)
+    {
+        Some(Source::File(path_to_classes)) => read_asset_classes(&Input::read(&path_to_classes)?)?,
+        Some(Source::Inline(asset_classes)) => asset_classes,
+        None => AssetClasses::default(),
+    };
 
-        let asset = Asset::new(asset_name.clone(), target_allocation_percent, 0.0);
+    let mut holdings = match portfolio {
+        Portfolio::File(input) => read_portfolio_holdings(&input, &portfolio_format)?,
+        Portfolio::Holdings(holdings) => holdings,
+    };
 
-        portfolio_map.insert(asset_name.to_string(), asset);
-    }
+    let prices = match matches
+        .value_of("prices")
+        .map(|x| x.to_string())
+        .or(plan_prices)
+    {
+        Some(path_to_prices) => read_prices(&Input::read(&path_to_prices)?)?,
+        None => Prices::default(),
+    };
 
-    let mut portfolio = vec![];
+    let max_price_age = match matches.value_of("max_price_age") {
+        Some(days) => match days.parse::<i64>() {
+            Ok(days) if days >= 0 => days,
+            _ => {
+                return Err(RebalanceError::Invalid(format!(
+                    "maximum price age must be a non-negative number of days, got \"{}\"",
+                    days
+                )));
+            }
+        },
+        None => plan_max_price_age.unwrap_or(DEFAULT_MAX_PRICE_AGE),
+    };
 
-    for (_asset_name, asset) in portfolio_map {
-        portfolio.push(asset);
+    prices::value_holdings(&mut holdings, &prices)?;
+
+    issues.extend(validation::check_price_ages(
+        &holdings,
+        &prices,
+        Local::today().naive_local(),
+        max_price_age,
+    ));
+
+    output_options.currency = fx::base_currency(currency, &holdings);
+
+    let fx_rates = match matches
+        .value_of("fx_rates")
+        .map(|path_to_fx_rates| Source::File(path_to_fx_rates.to_string()))
+        .or(plan_fx_rates)
+    {
+        Some(Source::File(path_to_fx_rates)) => read_fx_rates(&Input::read(&path_to_fx_rates)?)?,
+        Some(Source::Inline(fx_rates)) => fx_rates,
+        None => FxRates::default(),
+    };
+
+    let untargeted_mode = match matches.value_of("untargeted") {
+        Some(mode) => UntargetedMode::from_name(mode).unwrap(),
+        None => plan_untargeted.unwrap_or(UntargetedMode::Ignore),
+    };
+
+    let cash_names: Vec<String> = match matches.values_of("cash") {
+        Some(names) => names.map(|name| name.trim().to_string()).collect(),
+        None => plan_cash,
+    };
+
+    let cash_reserve = match matches.value_of("cash_reserve") {
+        Some(amount) => match parse_amount(amount, '.') {
+            Ok(amount) if amount >= 0.0 => amount,
+            _ => {
+                return Err(RebalanceError::Invalid(format!(
+                    "cash reserve must be a non-negative amount, got \"{}\"",
+                    amount
+                )));
+            }
+        },
+        None => plan_cash_reserve.unwrap_or(0.0),
+    };
+
+    let mut bands = plan_bands;
+    read_asset_amounts(&matches, "band_absolute", &mut bands.absolute)?;
+    read_asset_amounts(&matches, "band_relative", &mut bands.relative)?;
+
+    if let Some(trade_to) = matches.value_of("band_trade_to") {
+        bands.trade_to = BandTarget::from_name(trade_to).unwrap();
     }
 
-    portfolio
-}
+    let rebalance_options = RebalanceOptions {
+        mode: match matches.value_of("mode") {
+            Some(mode) => RebalanceMode::from_name(mode).unwrap(),
+            None => plan_mode.unwrap_or(RebalanceMode::Lazy),
+        },
+        tolerance: match matches.value_of("tolerance") {
+            Some(tolerance) => match tolerance.parse::<f64>() {
+                Ok(tolerance) if tolerance >= 0.0 => tolerance,
+                _ => {
+                    return Err(RebalanceError::Invalid(format!(
+                        "tolerance must be a non-negative number of percentage points, got \"{}\"",
+                        tolerance
+                    )));
+                }
+            },
+            None => plan_tolerance.unwrap_or(DEFAULT_TOLERANCE),
+        },
+        bands,
+    };
 
-fn adjust_target_allocation_percent(target_allocation_percent: f64) -> f64 {
-    target_allocation_percent / 100.0
-}
+    let mut min_trades = plan_min_trades;
+    read_asset_amounts(&matches, "min_trade", &mut min_trades)?;
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    let mut costs = plan_costs;
+    read_asset_amounts(&matches, "buy_commission", &mut costs.buy_commission)?;
+    read_asset_amounts(&matches, "sell_commission", &mut costs.sell_commission)?;
+    read_asset_amounts(&matches, "share_fee", &mut costs.share_fee)?;
+    read_asset_amounts(&matches, "spread", &mut costs.spread)?;
 
-    #[test]
-    fn test_example() {
-        let path_to_targets = "example/targets.csv";
-        let path_to_portfolio = "example/portfolio.csv";
-        let contribution_amount = 10000.00;
-        let portfolio_value_index = 1;
+    issues.extend(validation::check_holdings(
+        &holdings,
+        &target_map,
+        &asset_classes,
+        untargeted_mode,
+        &cash_names,
+    ));
 
-        let target_map = create_target_map(path_to_targets);
+    let (report, errors) = validation_options.report(&issues);
 
-        let portfolio = create_portfolio(path_to_portfolio, portfolio_value_index, target_map);
+    if matches.is_present("check") {
+        for line in &report {
+            println!("{}", line);
+        }
 
-        let balanced_portfolio = lazy_rebalance(contribution_amount, portfolio);
+        let warnings = report.len() - errors;
 
-        let expected = r###"
-Asset name               Asset value  Holdings %  New holdings %  Target allocation %  Target value  $ to buy/sell
-TIPS fund                6500.00      6.500       9.935           10.000               11000.00      4428.57
-Bond fund                16500.00     16.500      19.870          20.000               22000.00      5357.14
-Domestic Stock ETF       43500.00     43.500      39.740          40.000               44000.00      214.29
-International Stock ETF  33500.00     33.500      30.455          30.000               33000.00      0.00
-Total                    100000.00    100.000     100.000         100.000              110000.00     10000.00
-        "###.trim();
+        if report.is_empty() {
+            println!("No issues found");
+        } else {
+            println!("{} error(s), {} warning(s)", errors, warnings);
+        }
 
-        assert_eq!(to_string(&balanced_portfolio), expected);
+        if errors > 0 {
+            return Err(RebalanceError::Invalid(format!("{} error(s)", errors)));
+        }
+        return Ok(());
     }
 
-    #[test]
-    fn test_new_example() {
-        let path_to_targets = "example/targets.csv";
-        let path_to_portfolio = "example/portfolio.csv";
-        let contribution_amount = 10000.00;
-        let portfolio_value_index = 1;
+    for line in &report {
+        eprintln!("{}", line);
+    }
 
-        let target_map = create_target_map(path_to_targets);
+    if errors > 0 {
+        return Err(RebalanceError::Invalid(format!("{} error(s)", errors)));
+    }
 
-        let portfolio = create_portfolio(path_to_portfolio, portfolio_value_index, target_map);
-        let portfolio = convert_old_portfolio(portfolio);
+    let contribution_amount: f64 = match matches.value_of("contribution") {
+        Some(contribution) => match contribution.parse::<f64>() {
+            Ok(contribution) => contribution,
+            Err(_) => {
+                return Err(RebalanceError::Invalid(format!(
+                    "invalid contribution amount \"{}\"",
+                    contribution
+                )));
+            }
+        },
+        None => match plan_contribution {
+            Some(contribution) => contribution,
+            // Uninvested cash may be the whole contribution, and full and hybrid rebalancing may not need one.
+            None if !cash_names.is_empty() => 0.0,
+            None if rebalance_options.mode != RebalanceMode::Lazy => 0.0,
+            None => {
+                return Err(RebalanceError::Invalid(
+                    "no contribution given; pass it as an argument or declare it in the plan"
+                        .to_string(),
+                ))
+            }
+        },
+    };
 
-        let balanced_portfolio = new_lazy_rebalance(contribution_amount, portfolio);
+    let portfolio_options = PortfolioOptions {
+        base_currency: output_options.currency.clone(),
+        fx_rates,
+        untargeted: untargeted_mode,
+        cash_names,
+        cash_reserve,
+        min_trades,
+        costs,
+    };
 
-        let expected = r###"
-Asset name               Asset value  Holdings %  New holdings %  Target allocation %  Target value  $ to buy/sell
-TIPS fund                6500.00      6.500       9.935           10.000               11000.00      4428.57
-Bond fund                16500.00     16.500      19.870          20.000               22000.00      5357.14
-Domestic Stock ETF       43500.00     43.500      39.740          40.000               44000.00      214.29
-International Stock ETF  33500.00     33.500      30.455          30.000               33000.00      0.00
-Total                    100000.00    100.000     100.000         100.000              110000.00     10000.00
-        "###.trim();
+    let portfolio = build_portfolio(holdings, target_map, &asset_classes, &portfolio_options)?;
 
-        assert_eq!(new_to_string(&balanced_portfolio), expected);
+    let available_cash = rebalance::available_cash(&portfolio);
+
+    if available_cash > 0.0 {
+        println!(
+            "Contributing: {:.*} (with {:.*} of available cash)\n",
+            output_options.decimal_places,
+            contribution_amount + available_cash,
+            output_options.decimal_places,
+            available_cash
+        );
+    } else {
+        println!(
+            "Contributing: {:.*}\n",
+            output_options.decimal_places, contribution_amount
+        );
+    }
+
+    let mut balanced_portfolio =
+        rebalance_net_of_costs(contribution_amount, portfolio, &rebalance_options)?;
+
+    if has_left_out_trades(&balanced_portfolio) && !has_trades(&balanced_portfolio) {
+        eprintln!(
+            "warning: every trade is smaller than its minimum trade, or costs more than it is worth; nothing is bought or sold"
+        );
+    }
+
+    // Without a contribution, nothing is traded by bands while every asset is within its band.
+    let no_action_needed = rebalance_options.mode == RebalanceMode::Bands
+        && !has_trades(&balanced_portfolio)
+        && !has_assets_out_of_band(&balanced_portfolio)
+        && !has_left_out_trades(&balanced_portfolio);
+
+    // Assets within their band are not sold to buy those below theirs.
+    for asset_name in assets_left_out_of_band(&balanced_portfolio) {
+        eprintln!(
+            "warning: \"{}\" stays outside its tolerance band; the contribution and the sales of assets above their band do not cover it",
+            asset_name
+        );
+    }
+
+    let whole_shares = matches.is_present("whole_shares") || plan_whole_shares;
+
+    let leftover_cash = if whole_shares {
+        Some(round_to_whole_units(&mut balanced_portfolio))
+    } else {
+        None
+    };
+
+    if matches.is_present("ledger") || plan_ledger.enabled {
+        let dest_account_name = matches
+            .value_of("dest_account_name")
+            .map(|x| x.to_string())
+            .or(plan_ledger.dest_account_name)
+            .unwrap_or_else(|| "destination_account".to_string());
+        let source_account_name = matches
+            .value_of("source_account_name")
+            .map(|x| x.to_string())
+            .or(plan_ledger.source_account_name)
+            .unwrap_or_else(|| "source_account".to_string());
+
+        println!(
+            "{}",
+            to_ledger_string(
+                &balanced_portfolio,
+                &dest_account_name,
+                &source_account_name,
+                &output_options
+            )
+        );
+        return Ok(());
+    }
+
+    println!("{}", to_string(&balanced_portfolio, &output_options));
+
+    if no_action_needed {
+        println!("No action needed: every asset is within its tolerance band");
+    }
+
+    if let Some(leftover_cash) = leftover_cash {
+        println!(
+            "Leftover cash: {:.*} (not invested, to trade whole shares)",
+            output_options.decimal_places, leftover_cash
+        );
+    }
+
+    if target_tree.is_nested() {
+        println!(
+            "\n{}",
+            to_tree_string(&balanced_portfolio, &target_tree, &output_options)
+        );
+    }
+
+    Ok(())
+}
+
+// Reads the amounts of an option given as AMOUNT, for every asset, or ASSET=AMOUNT.
+fn read_asset_amounts(
+    matches: &ArgMatches,
+    option: &str,
+    amounts: &mut AssetAmounts,
+) -> Result<(), RebalanceError> {
+    let settings = match matches.values_of(option) {
+        Some(settings) => settings,
+        None => return Ok(()),
+    };
+
+    for setting in settings {
+        let mut parts = setting.rsplitn(2, '=');
+
+        let amount = parts.next().unwrap_or("");
+        let asset_name = parts.next();
+
+        let error = |message: String| {
+            RebalanceError::Invalid(format!("--{}: {}", option.replace('_', "-"), message))
+        };
+
+        let amount = parse_amount(amount, '.').map_err(|_| {
+            error(format!(
+                "expected an amount, or ASSET=AMOUNT, got \"{}\"",
+                setting
+            ))
+        })?;
+
+        amounts.set(asset_name, amount).map_err(error)?;
+    }
+
+    Ok(())
+}
+
+// How the holdings of the portfolio are turned into the assets to rebalance.
+struct PortfolioOptions {
+    // Currency that holdings are converted to, and the exchange rates to convert them.
+    base_currency: String,
+    fx_rates: FxRates,
+    untargeted: UntargetedMode,
+    // Holdings of uninvested cash, which fund the contribution, and the amount of cash to keep.
+    cash_names: Vec<String>,
+    cash_reserve: f64,
+    min_trades: AssetAmounts,
+    costs: TradeCosts,
+}
+
+impl Default for PortfolioOptions {
+    fn default() -> Self {
+        PortfolioOptions {
+            base_currency: fx::DEFAULT_BASE_CURRENCY.to_string(),
+            fx_rates: FxRates::default(),
+            untargeted: UntargetedMode::Ignore,
+            cash_names: vec![],
+            cash_reserve: 0.0,
+            min_trades: AssetAmounts::default(),
+            costs: TradeCosts::default(),
+        }
+    }
+}
+
+// Where the holdings of the portfolio come from: a portfolio file, or the holdings declared in a plan.
+enum Portfolio {
+    File(Input),
+    Holdings(Vec<Holding>),
+}
+
+// Reads the allocations of a targets file, as written; they are normalized once the target notation is known.
+// Targets are nested under the target named in a third column, or else under the last target with less indentation.
+// A glide path has its allocations at each date or age in columns, and is nested by indentation only.
+fn read_target_map(input: &Input, glide_options: &GlideOptions) -> Result<Targets, RebalanceError> {
+    let mut reader = input.csv_reader();
+
+    let mut targets = Targets::default();
+
+    // Indentation and name of the targets that the next rows may be nested under.
+    let mut parents: Vec<(usize, String)> = vec![];
+
+    // Where the rebalance falls on the glide path, if the targets file is one.
+    let mut glide_path: Option<GlidePosition> = None;
+
+    for result in reader.records() {
+        let record = result.map_err(|error| RebalanceError::csv(&input.name, error))?;
+
+        let line = record.position().map(|position| position.line());
+
+        if line == Some(1) && is_glide_path_header(record.get(0).unwrap_or("")) {
+            let header: Vec<&str> = record.iter().collect();
+
+            glide_path = Some(
+                glide_position(&header, glide_options)
+                    .map_err(|error| RebalanceError::parse(&input.name, line, None, error))?,
+            );
+            continue;
+        }
+
+        let name_cell = record.get(0).unwrap_or("");
+        let asset_name = name_cell.trim().to_string();
+
+        if asset_name.is_empty() {
+            continue;
+        }
+
+        let indentation = name_cell.len() - name_cell.trim_start().len();
+
+        while parents
+            .last()
+            .is_some_and(|&(parent_indentation, _)| parent_indentation >= indentation)
+        {
+            parents.pop();
+        }
+
+        let parent = match record.get(2).map(|cell| cell.trim()) {
+            Some(parent) if !parent.is_empty() && glide_path.is_none() => Some(parent.to_string()),
+            _ => parents.last().map(|(_, parent)| parent.clone()),
+        };
+
+        parents.push((indentation, asset_name.clone()));
+
+        let allocation: Allocation = {
+            // Rows without a valid allocation are reported by the validation pass.
+            let allocation = match glide_path {
+                Some(ref glide_path) => {
+                    let cells: Vec<&str> = record.iter().collect();
+
+                    match glide_path.allocation(&cells) {
+                        Ok(Some(allocation)) => allocation,
+                        Ok(None) => continue,
+                        Err(error) => {
+                            return Err(RebalanceError::parse(&input.name, line, None, error))
+                        }
+                    }
+                }
+                None => match record.get(1).map(parse_allocation) {
+                    Some(Ok(allocation)) => allocation,
+                    _ => continue,
+                },
+            };
+
+            if allocation.is_left_out() {
+                continue;
+            }
+
+            allocation
+        };
+
+        targets.insert(asset_name, allocation, parent);
+    }
+
+    Ok(targets)
+}
+
+// Reads the holdings of a portfolio file, which is either a CSV file, an OFX/QFX statement or an accounting journal.
+fn read_portfolio_holdings(
+    input: &Input,
+    format: &PortfolioFormat,
+) -> Result<Vec<Holding>, RebalanceError> {
+    if ofx::is_ofx(input) {
+        ofx::read_holdings(input)
+    } else if journal::is_journal(input) {
+        journal::read_holdings(input, &format.journal)
+    } else {
+        format.read_holdings(input)
+    }
+}
+
+// Matches holdings against the targets. Holdings of the same asset are summed, and targets without holdings are
+// added with a value of zero. Holdings of a ticker belonging to an asset class count towards that class.
+fn build_portfolio(
+    holdings: Vec<Holding>,
+    target_map: TargetMap,
+    asset_classes: &AssetClasses,
+    options: &PortfolioOptions,
+) -> Result<Vec<Asset>, RebalanceError> {
+    let base_currency = options.base_currency.as_str();
+    let untargeted_mode = options.untargeted;
+
+    let mut portfolio_map: HashMap<String, Asset> = HashMap::new();
+
+    // Number of rows seen for each holding, and the accounts they are held in, to report holdings that were summed
+    // together.
+    let mut row_counts: HashMap<String, usize> = HashMap::new();
+    let mut row_accounts: HashMap<String, Vec<String>> = HashMap::new();
+
+    // Units of the preferred ticker of each asset class: the total quantity, and its total value.
+    let mut preferred_units: HashMap<String, (f64, f64)> = HashMap::new();
+
+    // All holdings of cash are summed into one asset, named after the first of them.
+    let mut cash_name: Option<String> = None;
+
+    for holding in holdings {
+        let is_cash = options.cash_names.contains(&holding.name);
+
+        let class_name = if is_cash {
+            None
+        } else {
+            asset_classes.class_of(&holding.name)
+        };
+
+        let asset_name = match class_name {
+            Some(class_name) => class_name.to_string(),
+            None if is_cash => cash_name.get_or_insert(holding.name.clone()).clone(),
+            None => holding.name.clone(),
+        };
+
+        // Holdings without a target are left out, unless they are held or liquidated. Cash has no target.
+        let target_allocation = match target_map.get(&asset_name) {
+            Some(_) if is_cash => {
+                return Err(RebalanceError::Invalid(format!(
+                    "cash holding \"{}\" has a target; remove it from the targets, or from the cash holdings",
+                    asset_name
+                )))
+            }
+            Some(target_allocation) => target_allocation.clone(),
+            None if is_cash => BigRational::zero(),
+            None if untargeted_mode == UntargetedMode::Ignore => continue,
+            None => BigRational::zero(),
+        };
+
+        // Values and unit prices are converted to the base currency.
+        let currency = holding
+            .currency
+            .filter(|currency| !currency.eq_ignore_ascii_case(base_currency));
+
+        let rate = match currency {
+            Some(ref currency) => match options.fx_rates.rate(currency, base_currency) {
+                Some(rate) => rate,
+                None => {
+                    return Err(RebalanceError::Invalid(format!(
+                    "no exchange rate from {} to {} for holding \"{}\"; add one with --fx-rates",
+                    currency, base_currency, holding.name
+                )))
+                }
+            },
+            None => 1.0,
+        };
+
+        let units = holding
+            .units
+            .map(|(quantity, unit_price)| (quantity, unit_price * rate));
+
+        let asset = Asset::new(asset_name.clone(), target_allocation, holding.value * rate)?;
+
+        // An asset class is traded in the currency of its preferred ticker.
+        let is_traded = match class_name {
+            Some(class_name) => {
+                asset_classes.preferred_ticker(class_name) == Some(holding.name.as_str())
+            }
+            None => true,
+        };
+
+        let asset = match currency {
+            Some(currency) if is_traded => asset.with_currency(currency, rate)?,
+            _ => asset,
+        };
+
+        let asset = if is_cash {
+            asset.with_cash_reserve(options.cash_reserve)?
+        } else if target_map.contains_key(&asset_name) {
+            asset
+        } else {
+            asset.with_untargeted(untargeted_mode)
+        };
+
+        // Units of an asset class are those of its preferred ticker; they are added once all holdings are
+        // summed.
+        let asset = match (class_name, units) {
+            (Some(_), Some((quantity, unit_price))) => {
+                if is_traded {
+                    let units = preferred_units
+                        .entry(asset_name.clone())
+                        .or_insert((0.0, 0.0));
+                    units.0 += quantity;
+                    units.1 += quantity * unit_price;
+                }
+                asset
+            }
+            (None, Some((quantity, unit_price))) if unit_price > 0.0 => {
+                asset.with_units(quantity, unit_price)?
+            }
+            // A holding without a price, such as a delisted security, is still rebalanced by its value.
+            (None, Some((_, unit_price))) => {
+                eprintln!(
+                    "warning: holding \"{}\" has a unit price of {}; its trade is not shown in shares",
+                    holding.name, unit_price
+                );
+                asset
+            }
+            _ => asset,
+        };
+
+        let asset = match holding.account {
+            Some(account) => {
+                let accounts = row_accounts.entry(holding.name.clone()).or_default();
+
+                if !accounts.contains(&account) {
+                    accounts.push(account.clone());
+                }
+
+                asset.with_account(account)
+            }
+            None => asset,
+        };
+
+        *row_counts.entry(holding.name).or_insert(0) += 1;
+
+        match portfolio_map.get_mut(&asset_name) {
+            Some(existing_asset) => existing_asset.merge(asset),
+            None => {
+                portfolio_map.insert(asset_name, asset);
+            }
+        }
+    }
+
+    if cash_name.is_none() && !options.cash_names.is_empty() {
+        eprintln!(
+            "warning: no cash holding named {} in the portfolio",
+            options
+                .cash_names
+                .iter()
+                .map(|name| format!("\"{}\"", name))
+                .collect::<Vec<String>>()
+                .join(" or ")
+        );
+    }
+
+    for (class_name, (quantity, value)) in preferred_units {
+        if quantity == 0.0 || value <= 0.0 {
+            continue;
+        }
+
+        let asset = portfolio_map.remove(&class_name).unwrap();
+
+        portfolio_map.insert(class_name, asset.with_units(quantity, value / quantity)?);
+    }
+
+    let mut duplicate_names: Vec<&String> = row_counts
+        .iter()
+        .filter(|&(_, &count)| count > 1)
+        .map(|(name, _)| name)
+        .collect();
+    duplicate_names.sort();
+
+    for asset_name in duplicate_names {
+        match row_accounts.get(asset_name) {
+            Some(accounts) => eprintln!(
+                "warning: \"{}\" appears in {} portfolio rows ({}); their holdings were summed",
+                asset_name,
+                row_counts[asset_name],
+                accounts.join(", ")
+            ),
+            None => eprintln!(
+                "warning: \"{}\" appears in {} portfolio rows; their holdings were summed",
+                asset_name, row_counts[asset_name]
+            ),
+        }
+    }
+
+    for asset_name in target_map.keys() {
+        if portfolio_map.contains_key(asset_name) {
+            continue;
+        }
+
+        let target_allocation = target_map[asset_name].clone();
+
+        let asset = Asset::new(asset_name.clone(), target_allocation, 0.0)?;
+
+        portfolio_map.insert(asset_name.to_string(), asset);
+    }
+
+    let mut portfolio = vec![];
+
+    for (asset_name, asset) in portfolio_map {
+        let asset = match asset_classes.preferred_ticker(&asset_name) {
+            Some(ticker) => asset.with_ticker(ticker.to_string()),
+            None => asset,
+        };
+
+        let asset = match options.min_trades.of(&asset_name) {
+            Some(min_trade) => asset.with_min_trade(min_trade)?,
+            None => asset,
+        };
+
+        // Cash is not traded.
+        let asset = if cash_name.as_ref() == Some(&asset_name) {
+            asset
+        } else {
+            asset.with_costs(&options.costs)
+        };
+
+        portfolio.push(asset);
+    }
+
+    Ok(portfolio)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use chrono::NaiveDate;
+
+    use num::One;
+
+    use rebalance::{
+        assets_left_out_of_band, convert_old_portfolio, has_assets_out_of_band, lazy_rebalance,
+        new_lazy_rebalance, new_to_string, ToleranceBands,
+    };
+
+    fn glide_options() -> GlideOptions {
+        GlideOptions {
+            as_of: NaiveDate::from_ymd(2019, 8, 2),
+            birth_date: None,
+        }
+    }
+
+    fn create_target_map(path_to_targets: &str) -> Result<TargetMap, RebalanceError> {
+        let target_tree = normalize_targets(
+            read_target_map(&Input::read(path_to_targets)?, &glide_options())?,
+            TargetNotation::Percent,
+        )?;
+
+        Ok(target_tree.leaves())
+    }
+
+    fn create_portfolio(
+        path_to_portfolio: &str,
+        format: &PortfolioFormat,
+        target_map: TargetMap,
+        asset_classes: &AssetClasses,
+    ) -> Result<Vec<Asset>, RebalanceError> {
+        let holdings = read_portfolio_holdings(&Input::read(path_to_portfolio)?, format)?;
+
+        let base_currency = fx::base_currency(None, &holdings);
+
+        build_portfolio(
+            holdings,
+            target_map,
+            asset_classes,
+            &PortfolioOptions {
+                base_currency,
+                ..PortfolioOptions::default()
+            },
+        )
+    }
+
+    const EXAMPLE_NAMES: [&str; 4] = [
+        "TIPS fund",
+        "Bond fund",
+        "Domestic Stock ETF",
+        "International Stock ETF",
+    ];
+    const TICKERS: [&str; 4] = ["SCHP", "BND", "VTI", "VXUS"];
+
+    // Shares to buy of the example portfolio, for a contribution of 10,000.00.
+    const EXAMPLE_UNITS: [Option<f64>; 4] = [Some(170.33), Some(64.935), Some(2.463), Some(0.0)];
+
+    // Amounts to buy of the example portfolio, for a contribution of 10,000.00, with the names of its assets.
+    fn example_deltas(names: [&'static str; 4]) -> Vec<(&'static str, f64)> {
+        names
+            .iter()
+            .cloned()
+            .zip([4428.57, 5357.14, 214.29, 0.0].iter().cloned())
+            .collect()
+    }
+
+    fn round(amount: f64, dec_places: i32) -> f64 {
+        let scale = 10f64.powi(dec_places);
+        (amount * scale).round() / scale
+    }
+
+    // Amount to buy or sell of each asset, to the cent.
+    fn deltas(balanced_portfolio: &[Asset]) -> Vec<(&str, f64)> {
+        balanced_portfolio
+            .iter()
+            .map(|asset| (asset.name(), round(asset.delta(), 2)))
+            .collect()
+    }
+
+    // Units to buy or sell of each asset with a unit price, to the thousandth.
+    fn units(balanced_portfolio: &[Asset]) -> Vec<Option<f64>> {
+        balanced_portfolio
+            .iter()
+            .map(|asset| asset.units().map(|units| round(units, 3)))
+            .collect()
+    }
+
+    // Amount to buy or sell of each asset in the currency it is traded in, to the cent.
+    fn native_deltas(balanced_portfolio: &[Asset]) -> Vec<Option<(f64, &str)>> {
+        balanced_portfolio
+            .iter()
+            .map(|asset| {
+                asset
+                    .native_delta()
+                    .map(|(delta, currency)| (round(delta, 2), currency))
+            })
+            .collect()
+    }
+
+    fn accounts(balanced_portfolio: &[Asset]) -> Vec<String> {
+        balanced_portfolio
+            .iter()
+            .map(|asset| asset.accounts().join(", "))
+            .collect()
+    }
+
+    // Drift of each asset from its target, in percentage points.
+    fn drifts(balanced_portfolio: &[Asset]) -> Vec<Option<f64>> {
+        balanced_portfolio
+            .iter()
+            .map(|asset| asset.drift().map(|drift| round(drift * 100.0, 3)))
+            .collect()
+    }
+
+    fn left_out(balanced_portfolio: &[Asset]) -> Vec<&str> {
+        balanced_portfolio
+            .iter()
+            .filter(|asset| asset.is_left_out())
+            .map(|asset| asset.name())
+            .collect()
+    }
+
+    // Cash left in the cash holding after the rebalance.
+    fn remaining_cash(balanced_portfolio: &[Asset]) -> f64 {
+        let cash = balanced_portfolio
+            .iter()
+            .find(|asset| asset.name() == "CASH")
+            .unwrap();
+
+        round(cash.value() + cash.delta(), 2)
+    }
+
+    #[test]
+    fn test_example() {
+        let path_to_targets = "example/targets.csv";
+        let path_to_portfolio = "example/portfolio.csv";
+        let contribution_amount = 10000.00;
+        let portfolio_format = PortfolioFormat::default();
+
+        let target_map = create_target_map(path_to_targets).unwrap();
+
+        let portfolio = create_portfolio(
+            path_to_portfolio,
+            &portfolio_format,
+            target_map,
+            &AssetClasses::default(),
+        )
+        .unwrap();
+
+        let balanced_portfolio = lazy_rebalance(contribution_amount, portfolio).unwrap();
+
+        let expected = r###"
+Asset name               Asset value  Holdings %  New holdings %  Target allocation %  Target value  $ to buy/sell
+TIPS fund                6500.00      6.500       9.935           10.000               11000.00      4428.57
+Bond fund                16500.00     16.500      19.870          20.000               22000.00      5357.14
+Domestic Stock ETF       43500.00     43.500      39.740          40.000               44000.00      214.29
+International Stock ETF  33500.00     33.500      30.455          30.000               33000.00      0.00
+Total                    100000.00    100.000     100.000         100.000              110000.00     10000.00
+        "###.trim();
+
+        assert_eq!(
+            to_string(&balanced_portfolio, &OutputOptions::default()),
+            expected
+        );
+    }
+
+    #[test]
+    fn test_new_example() {
+        let path_to_targets = "example/targets.csv";
+        let path_to_portfolio = "example/portfolio.csv";
+        let contribution_amount = 10000.00;
+        let portfolio_format = PortfolioFormat::default();
+
+        let target_map = create_target_map(path_to_targets).unwrap();
+
+        let portfolio = create_portfolio(
+            path_to_portfolio,
+            &portfolio_format,
+            target_map,
+            &AssetClasses::default(),
+        )
+        .unwrap();
+        let portfolio = convert_old_portfolio(portfolio);
+
+        let balanced_portfolio = new_lazy_rebalance(contribution_amount, portfolio).unwrap();
+
+        let expected = r###"
+Asset name               Asset value  Holdings %  New holdings %  Target allocation %  Target value  $ to buy/sell
+TIPS fund                6500.00      6.500       9.935           10.000               11000.00      4428.57
+Bond fund                16500.00     16.500      19.870          20.000               22000.00      5357.14
+Domestic Stock ETF       43500.00     43.500      39.740          40.000               44000.00      214.29
+International Stock ETF  33500.00     33.500      30.455          30.000               33000.00      0.00
+Total                    100000.00    100.000     100.000         100.000              110000.00     10000.00
+        "###.trim();
+
+        assert_eq!(new_to_string(&balanced_portfolio), expected);
+    }
+
+    #[test]
+    fn test_nested_targets() {
+        let input = Input::read("example/nested/targets.csv").unwrap();
+
+        let target_tree = normalize_targets(
+            read_target_map(&input, &glide_options()).unwrap(),
+            TargetNotation::Percent,
+        )
+        .unwrap();
+
+        assert!(validation::check_targets(&target_tree).is_empty());
+
+        let create_nested_portfolio = || {
+            create_portfolio(
+                "example/nested/portfolio.csv",
+                &PortfolioFormat::default(),
+                target_tree.leaves(),
+                &AssetClasses::default(),
+            )
+            .unwrap()
+        };
+
+        let balanced_portfolio = lazy_rebalance(10000.00, create_nested_portfolio()).unwrap();
+
+        // The leaves are rebalanced as a flat portfolio.
+        assert_eq!(
+            deltas(&balanced_portfolio),
+            [
+                ("TIPS", 2924.05),
+                ("Bonds", 2696.2),
+                ("US", 2645.57),
+                ("International", 1734.18),
+                ("Canada", 0.0),
+            ]
+        );
+
+        let expected = r###"
+Target           Asset value  Holdings %  New holdings %  Target allocation %  Drift %  New drift %  $ to buy/sell
+Equity           66000.00     73.333      70.380          70.000               3.333    0.380        4379.75
+  Canada         22000.00     24.444      22.000          21.000               3.444    1.000        0.00
+  US             25000.00     27.778      27.646          28.000               -0.222   -0.354       2645.57
+  International  19000.00     21.111      20.734          21.000               0.111    -0.266       1734.18
+Fixed Income     24000.00     26.667      29.620          30.000               -3.333   -0.380       5620.25
+  Bonds          21000.00     23.333      23.696          24.000               -0.667   -0.304       2696.20
+  TIPS           3000.00      3.333       5.924           6.000                -2.667   -0.076       2924.05
+        "###.trim();
+
+        assert_eq!(
+            to_tree_string(&balanced_portfolio, &target_tree, &OutputOptions::default()),
+            expected
+        );
+    }
+
+    #[test]
+    fn test_portfolio_with_headers() {
+        let path_to_targets = "example/targets.csv";
+        let path_to_portfolio = "example/portfolio_with_headers.csv";
+        let contribution_amount = 10000.00;
+        let portfolio_format = PortfolioFormat {
+            has_headers: true,
+            name: Column::Header("symbol".to_string()),
+            value: Some(Column::Header("Market Value".to_string())),
+            ..PortfolioFormat::default()
+        };
+
+        let target_map = create_target_map(path_to_targets).unwrap();
+
+        let portfolio = create_portfolio(
+            path_to_portfolio,
+            &portfolio_format,
+            target_map,
+            &AssetClasses::default(),
+        )
+        .unwrap();
+
+        let balanced_portfolio = lazy_rebalance(contribution_amount, portfolio).unwrap();
+
+        assert_eq!(deltas(&balanced_portfolio), example_deltas(EXAMPLE_NAMES));
+    }
+
+    #[test]
+    fn test_portfolio_with_units() {
+        let path_to_targets = "example/targets.csv";
+        let path_to_portfolio = "example/portfolio_with_headers.csv";
+        let contribution_amount = 10000.00;
+        let portfolio_format = PortfolioFormat {
+            has_headers: true,
+            name: Column::Header("Symbol".to_string()),
+            value: None,
+            quantity: Some(Column::Header("Quantity".to_string())),
+            price: Some(Column::Header("Price".to_string())),
+            ..PortfolioFormat::default()
+        };
+
+        let target_map = create_target_map(path_to_targets).unwrap();
+
+        let portfolio = create_portfolio(
+            path_to_portfolio,
+            &portfolio_format,
+            target_map,
+            &AssetClasses::default(),
+        )
+        .unwrap();
+
+        let balanced_portfolio = lazy_rebalance(contribution_amount, portfolio).unwrap();
+
+        assert_eq!(deltas(&balanced_portfolio), example_deltas(EXAMPLE_NAMES));
+        assert_eq!(units(&balanced_portfolio), EXAMPLE_UNITS);
+
+        // Holdings without a positive price are rebalanced by their value, without their units.
+        let input = Input::inline(
+            "--portfolio-inline",
+            "Symbol,Quantity,Price\nTIPS fund,250,0\nBond fund,-200,82.50\nDomestic Stock ETF,500,-87.00",
+        );
+
+        let portfolio = build_portfolio(
+            read_portfolio_holdings(&input, &portfolio_format).unwrap(),
+            create_target_map(path_to_targets).unwrap(),
+            &AssetClasses::default(),
+            &PortfolioOptions::default(),
+        )
+        .unwrap();
+
+        assert!(lazy_rebalance(contribution_amount, portfolio).is_ok());
+    }
+
+    #[test]
+    fn test_whole_shares() {
+        let portfolio_format = PortfolioFormat {
+            has_headers: true,
+            name: Column::Header("Symbol".to_string()),
+            value: None,
+            quantity: Some(Column::Header("Quantity".to_string())),
+            price: Some(Column::Header("Price".to_string())),
+            ..PortfolioFormat::default()
+        };
+
+        let target_map = create_target_map("example/targets.csv").unwrap();
+
+        let portfolio = create_portfolio(
+            "example/portfolio_with_headers.csv",
+            &portfolio_format,
+            target_map,
+            &AssetClasses::default(),
+        )
+        .unwrap();
+
+        let mut balanced_portfolio = lazy_rebalance(10000.00, portfolio).unwrap();
+
+        // Rounded down, 170, 64 and 2 shares leave 126.00, which buys one more share of the TIPS and bond funds.
+        assert_eq!(round_to_whole_units(&mut balanced_portfolio), 17.5);
+
+        assert_eq!(
+            deltas(&balanced_portfolio),
+            [
+                ("TIPS fund", 4446.0),
+                ("Bond fund", 5362.5),
+                ("Domestic Stock ETF", 174.0),
+                ("International Stock ETF", 0.0),
+            ]
+        );
+        assert_eq!(
+            units(&balanced_portfolio),
+            [Some(171.0), Some(65.0), Some(2.0), Some(0.0)]
+        );
+    }
+
+    #[test]
+    fn test_asset_classes() {
+        let portfolio_format = PortfolioFormat {
+            has_headers: true,
+            name: Column::Header("Symbol".to_string()),
+            value: Some(Column::Header("Market Value".to_string())),
+            quantity: Some(Column::Header("Quantity".to_string())),
+            price: Some(Column::Header("Price".to_string())),
+            ..PortfolioFormat::default()
+        };
+
+        let target_map = create_target_map("example/targets.csv").unwrap();
+
+        let asset_classes =
+            read_asset_classes(&Input::read("example/asset_classes.csv").unwrap()).unwrap();
+
+        let portfolio = create_portfolio(
+            "example/portfolio_with_tickers.csv",
+            &portfolio_format,
+            target_map,
+            &asset_classes,
+        )
+        .unwrap();
+
+        let balanced_portfolio = lazy_rebalance(10000.00, portfolio).unwrap();
+
+        // Shares are those of the preferred ticker of each asset class.
+        assert_eq!(deltas(&balanced_portfolio), example_deltas(EXAMPLE_NAMES));
+        assert_eq!(
+            units(&balanced_portfolio),
+            [Some(170.33), Some(71.429), Some(2.143), Some(0.0)]
+        );
+        assert_eq!(
+            balanced_portfolio
+                .iter()
+                .map(|asset| asset.ticker().unwrap())
+                .collect::<Vec<&str>>(),
+            TICKERS
+        );
+    }
+
+    #[test]
+    fn test_portfolio_with_accounts() {
+        let path_to_targets = "example/targets.csv";
+        let path_to_portfolio = "example/portfolio_with_accounts.csv";
+        let contribution_amount = 10000.00;
+        let portfolio_format = PortfolioFormat {
+            has_headers: true,
+            name: Column::Header("Symbol".to_string()),
+            value: Some(Column::Header("Market Value".to_string())),
+            account: Some(Column::Header("Account".to_string())),
+            ..PortfolioFormat::default()
+        };
+
+        let target_map = create_target_map(path_to_targets).unwrap();
+
+        let portfolio = create_portfolio(
+            path_to_portfolio,
+            &portfolio_format,
+            target_map,
+            &AssetClasses::default(),
+        )
+        .unwrap();
+
+        let balanced_portfolio = lazy_rebalance(contribution_amount, portfolio).unwrap();
+
+        assert_eq!(deltas(&balanced_portfolio), example_deltas(EXAMPLE_NAMES));
+        assert_eq!(
+            accounts(&balanced_portfolio),
+            ["RRSP", "RRSP, TFSA", "RRSP, TFSA", "TFSA"]
+        );
+    }
+
+    #[test]
+    fn test_broker_formats() {
+        for &(format, path_to_portfolio) in [
+            ("questrade", "example/broker/questrade.csv"),
+            ("schwab", "example/broker/schwab.csv"),
+            ("ibkr", "example/broker/ibkr.csv"),
+        ]
+        .iter()
+        {
+            let portfolio_format = PortfolioFormat::preset(format).unwrap();
+
+            let target_map = create_target_map("example/broker/targets.csv").unwrap();
+
+            let portfolio = create_portfolio(
+                path_to_portfolio,
+                &portfolio_format,
+                target_map,
+                &AssetClasses::default(),
+            )
+            .unwrap();
+
+            let balanced_portfolio = lazy_rebalance(10000.00, portfolio).unwrap();
+
+            assert_eq!(
+                deltas(&balanced_portfolio),
+                example_deltas(TICKERS),
+                "{}",
+                format
+            );
+            assert_eq!(units(&balanced_portfolio), EXAMPLE_UNITS, "{}", format);
+        }
+    }
+
+    #[test]
+    fn test_broker_presets() {
+        let read_holdings = |format: &str| {
+            let input = Input::read(&format!("example/broker/{}.csv", format)).unwrap();
+
+            PortfolioFormat::preset(format)
+                .unwrap()
+                .read_holdings(&input)
+                .unwrap()
+        };
+
+        let names = |holdings: &[Holding]| -> Vec<String> {
+            holdings
+                .iter()
+                .map(|holding| holding.name.clone())
+                .collect()
+        };
+
+        // The closing total is after a blank row, and quantities have thousands separators.
+        let holdings = read_holdings("questrade");
+
+        assert_eq!(names(&holdings), ["SCHP", "BND", "VTI", "VXUS"]);
+        assert_eq!(holdings[3].value, 33500.0);
+        assert_eq!(holdings[3].units, Some((1000.0, 33.5)));
+
+        let holdings = read_holdings("wealthsimple");
+
+        assert_eq!(names(&holdings), ["SCHP", "BND", "VTI", "VXUS"]);
+        assert_eq!(holdings[1].value, 16500.0);
+        assert_eq!(holdings[1].units, Some((200.0, 82.5)));
+        assert_eq!(
+            holdings
+                .iter()
+                .map(|holding| holding.account.clone().unwrap())
+                .collect::<Vec<String>>(),
+            ["RRSP", "TFSA", "RRSP", "TFSA"]
+        );
+
+        // The transactions after the positions are not read.
+        let holdings = read_holdings("vanguard");
+
+        assert_eq!(names(&holdings), ["VMFXX", "SCHP", "BND", "VTI", "VXUS"]);
+        assert_eq!(holdings[2].value, 16500.0);
+        assert_eq!(holdings[2].units, Some((200.0, 82.5)));
+        assert_eq!(holdings[2].account, Some("12345678".to_string()));
+
+        // The core position is named without its marker, and pending activity is left out.
+        let holdings = read_holdings("fidelity");
+
+        assert_eq!(names(&holdings), ["SPAXX", "SCHP", "BND", "VTI", "VXUS"]);
+        assert_eq!(holdings[0].value, 1250.0);
+        assert_eq!(holdings[0].units, None);
+        assert_eq!(holdings[4].units, Some((1000.0, 33.5)));
+        assert_eq!(holdings[4].account, Some("Individual".to_string()));
+
+        // Positions are in the currency of the statement, and converted to the base currency.
+        let holdings = read_holdings("ibkr");
+
+        assert!(holdings
+            .iter()
+            .all(|holding| holding.currency == Some("USD".to_string())));
+
+        let mut fx_rates = FxRates::default();
+        fx_rates.add("USD", Some("CAD"), 1.35).unwrap();
+
+        let portfolio = build_portfolio(
+            holdings,
+            create_target_map("example/broker/targets.csv").unwrap(),
+            &AssetClasses::default(),
+            &PortfolioOptions {
+                base_currency: "CAD".to_string(),
+                fx_rates,
+                ..PortfolioOptions::default()
+            },
+        )
+        .unwrap();
+
+        // 10,000 USD
+        let balanced_portfolio = lazy_rebalance(13500.00, portfolio).unwrap();
+
+        assert_eq!(
+            deltas(&balanced_portfolio),
+            [
+                ("SCHP", 5978.57),
+                ("BND", 7232.14),
+                ("VTI", 289.29),
+                ("VXUS", 0.0),
+            ]
+        );
+        assert_eq!(units(&balanced_portfolio), EXAMPLE_UNITS);
+        assert_eq!(
+            native_deltas(&balanced_portfolio),
+            [
+                Some((4428.57, "USD")),
+                Some((5357.14, "USD")),
+                Some((214.29, "USD")),
+                Some((0.0, "USD")),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_ofx_statement() {
+        let portfolio_format = PortfolioFormat::default();
+
+        let target_map = create_target_map("example/broker/targets.csv").unwrap();
+
+        let portfolio = create_portfolio(
+            "example/broker/statement.ofx",
+            &portfolio_format,
+            target_map,
+            &AssetClasses::default(),
+        )
+        .unwrap();
+
+        let balanced_portfolio = lazy_rebalance(10000.00, portfolio).unwrap();
+
+        assert_eq!(deltas(&balanced_portfolio), example_deltas(TICKERS));
+        assert_eq!(units(&balanced_portfolio), EXAMPLE_UNITS);
+        assert_eq!(
+            accounts(&balanced_portfolio),
+            ["123456789", "123456789", "123456789", "123456789"]
+        );
+    }
+
+    #[test]
+    fn test_journal() {
+        let portfolio_format = PortfolioFormat::default();
+
+        let target_map = create_target_map("example/broker/targets.csv").unwrap();
+
+        let portfolio = create_portfolio(
+            "example/broker/portfolio.ledger",
+            &portfolio_format,
+            target_map,
+            &AssetClasses::default(),
+        )
+        .unwrap();
+
+        let balanced_portfolio = lazy_rebalance(10000.00, portfolio).unwrap();
+
+        assert_eq!(deltas(&balanced_portfolio), example_deltas(TICKERS));
+        assert_eq!(units(&balanced_portfolio), EXAMPLE_UNITS);
+        assert_eq!(
+            accounts(&balanced_portfolio),
+            [
+                "Assets:Investments:RRSP:SCHP",
+                "Assets:Investments:RRSP:BND",
+                "Assets:Investments:RRSP:VTI, Assets:Investments:TFSA:VTI",
+                "Assets:Investments:TFSA:VXUS",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_plan() {
+        let plan = read_plan("example/plan.toml").unwrap();
+
+        let target_map = match plan.targets {
+            Some(Source::Inline(targets)) => normalize_targets(targets, TargetNotation::Percent)
+                .unwrap()
+                .leaves(),
+            _ => panic!("expected inline targets"),
+        };
+
+        let holdings = match plan.holdings {
+            Some(Source::Inline(holdings)) => holdings,
+            _ => panic!("expected inline holdings"),
+        };
+
+        let portfolio = build_portfolio(
+            holdings,
+            target_map,
+            &AssetClasses::default(),
+            &PortfolioOptions::default(),
+        )
+        .unwrap();
+
+        let balanced_portfolio = lazy_rebalance(plan.contribution.unwrap(), portfolio).unwrap();
+
+        assert_eq!(deltas(&balanced_portfolio), example_deltas(EXAMPLE_NAMES));
+        assert_eq!(units(&balanced_portfolio), [None, None, None, Some(0.0)]);
+        assert_eq!(accounts(&balanced_portfolio), ["", "", "RRSP, TFSA", ""]);
+    }
+
+    #[test]
+    fn test_portfolio_inline() {
+        let target_map = create_target_map("example/targets.csv").unwrap();
+
+        let input = Input::inline(
+            "--portfolio-inline",
+            "Bond fund,$16500.00\nTIPS fund,$6500.00\nDomestic Stock ETF,$43500.00\nInternational Stock ETF,$33500.00",
+        );
+
+        let holdings = read_portfolio_holdings(&input, &PortfolioFormat::default()).unwrap();
+
+        let portfolio = build_portfolio(
+            holdings,
+            target_map,
+            &AssetClasses::default(),
+            &PortfolioOptions::default(),
+        )
+        .unwrap();
+
+        let balanced_portfolio = lazy_rebalance(10000.00, portfolio).unwrap();
+
+        assert_eq!(deltas(&balanced_portfolio), example_deltas(EXAMPLE_NAMES));
+    }
+
+    #[test]
+    fn test_multi_currency() {
+        let portfolio_format = PortfolioFormat {
+            has_headers: true,
+            name: Column::Header("Symbol".to_string()),
+            value: Some(Column::Header("Market Value".to_string())),
+            currency: Some(Column::Header("Currency".to_string())),
+            ..PortfolioFormat::default()
+        };
+
+        let target_map = create_target_map("example/currency/targets.csv").unwrap();
+
+        let holdings = read_portfolio_holdings(
+            &Input::read("example/currency/portfolio.csv").unwrap(),
+            &portfolio_format,
+        )
+        .unwrap();
+
+        let fx_rates =
+            read_fx_rates(&Input::read("example/currency/fx_rates.csv").unwrap()).unwrap();
+
+        let portfolio = build_portfolio(
+            holdings,
+            target_map,
+            &AssetClasses::default(),
+            &PortfolioOptions {
+                fx_rates,
+                ..PortfolioOptions::default()
+            },
+        )
+        .unwrap();
+
+        let balanced_portfolio = lazy_rebalance(4500.00, portfolio).unwrap();
+
+        assert_eq!(
+            deltas(&balanced_portfolio),
+            [("BND", 3000.0), ("XIC", 1500.0), ("VTI", 0.0)]
+        );
+        assert_eq!(
+            native_deltas(&balanced_portfolio),
+            [Some((2222.22, "USD")), None, Some((0.0, "USD")),]
+        );
+    }
+
+    #[test]
+    fn test_prices() {
+        let portfolio_format = PortfolioFormat {
+            has_headers: true,
+            name: Column::Header("Symbol".to_string()),
+            value: None,
+            quantity: Some(Column::Header("Quantity".to_string())),
+            ..PortfolioFormat::default()
+        };
+
+        let target_map = create_target_map("example/broker/targets.csv").unwrap();
+
+        let mut holdings = read_portfolio_holdings(
+            &Input::read("example/prices/portfolio.csv").unwrap(),
+            &portfolio_format,
+        )
+        .unwrap();
+
+        let prices = read_prices(&Input::read("example/prices/prices.csv").unwrap()).unwrap();
+
+        prices::value_holdings(&mut holdings, &prices).unwrap();
+
+        let issues = validation::check_price_ages(
+            &holdings,
+            &prices,
+            NaiveDate::from_ymd(2019, 8, 5),
+            DEFAULT_MAX_PRICE_AGE,
+        );
+
+        let (report, _) = validation::ValidationOptions::default().report(&issues);
+
+        assert_eq!(
+            report,
+            vec![
+                "warning: example/prices/prices.csv: price of \"VXUS\" is 21 days old (2019-07-15)"
+            ]
+        );
+
+        let portfolio = build_portfolio(
+            holdings,
+            target_map,
+            &AssetClasses::default(),
+            &PortfolioOptions {
+                base_currency: "USD".to_string(),
+                ..PortfolioOptions::default()
+            },
+        )
+        .unwrap();
+
+        let balanced_portfolio = lazy_rebalance(10000.00, portfolio).unwrap();
+
+        assert_eq!(deltas(&balanced_portfolio), example_deltas(TICKERS));
+        assert_eq!(units(&balanced_portfolio), EXAMPLE_UNITS);
+    }
+
+    #[test]
+    fn test_liquidate_untargeted_holdings() {
+        let target_map = create_target_map("example/targets.csv").unwrap();
+
+        let input = Input::inline(
+            "--portfolio-inline",
+            "Bond fund,16500\nTIPS fund,6500\nDomestic Stock ETF,43500\nInternational Stock ETF,33500\nLegacy fund,5000",
+        );
+
+        let holdings = read_portfolio_holdings(&input, &PortfolioFormat::default()).unwrap();
+
+        let portfolio = build_portfolio(
+            holdings,
+            target_map,
+            &AssetClasses::default(),
+            &PortfolioOptions {
+                untargeted: UntargetedMode::Liquidate,
+                ..PortfolioOptions::default()
+            },
+        )
+        .unwrap();
+
+        let balanced_portfolio = lazy_rebalance(10000.00, portfolio).unwrap();
+
+        assert_eq!(
+            deltas(&balanced_portfolio),
+            [
+                ("TIPS fund", 5000.0),
+                ("Bond fund", 6500.0),
+                ("Domestic Stock ETF", 2500.0),
+                ("International Stock ETF", 1000.0),
+                ("Legacy fund", -5000.0),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_invest_available_cash() {
+        let target_map = create_target_map("example/targets.csv").unwrap();
+
+        let input = Input::inline(
+            "--portfolio-inline",
+            "Bond fund,16500\nTIPS fund,6500\nDomestic Stock ETF,43500\nInternational Stock ETF,33500\nCASH,6000",
+        );
+
+        let holdings = read_portfolio_holdings(&input, &PortfolioFormat::default()).unwrap();
+
+        let portfolio = build_portfolio(
+            holdings,
+            target_map,
+            &AssetClasses::default(),
+            &PortfolioOptions {
+                cash_names: vec!["CASH".to_string()],
+                cash_reserve: 1000.0,
+                ..PortfolioOptions::default()
+            },
+        )
+        .unwrap();
+
+        assert_eq!(rebalance::available_cash(&portfolio), 5000.0);
+
+        let balanced_portfolio = lazy_rebalance(5000.00, portfolio).unwrap();
+
+        let mut expected = example_deltas(EXAMPLE_NAMES);
+        expected.push(("CASH", -5000.0));
+
+        assert_eq!(deltas(&balanced_portfolio), expected);
+        assert_eq!(remaining_cash(&balanced_portfolio), 1000.0);
+    }
+
+    #[test]
+    fn test_min_trade() {
+        let min_trade_portfolio = |min_trades: AssetAmounts| {
+            let input = Input::inline(
+                "--portfolio-inline",
+                "Bond fund,16500\nTIPS fund,6500\nDomestic Stock ETF,43500\nInternational Stock ETF,33500\nCASH,1000",
+            );
+
+            build_portfolio(
+                read_portfolio_holdings(&input, &PortfolioFormat::default()).unwrap(),
+                create_target_map("example/targets.csv").unwrap(),
+                &AssetClasses::default(),
+                &PortfolioOptions {
+                    cash_names: vec!["CASH".to_string()],
+                    min_trades,
+                    ..PortfolioOptions::default()
+                },
+            )
+            .unwrap()
+        };
+
+        // The 214.29 of the domestic stock ETF goes to the bond and TIPS funds instead.
+        let mut min_trades = AssetAmounts::default();
+        min_trades.set(None, 500.0).unwrap();
+
+        let balanced_portfolio = lazy_rebalance(9000.00, min_trade_portfolio(min_trades)).unwrap();
+
+        assert_eq!(
+            deltas(&balanced_portfolio),
+            [
+                ("TIPS fund", 4500.0),
+                ("Bond fund", 5500.0),
+                ("Domestic Stock ETF", 0.0),
+                ("International Stock ETF", 0.0),
+                ("CASH", -1000.0),
+            ]
+        );
+        assert_eq!(left_out(&balanced_portfolio), ["Domestic Stock ETF"]);
+        assert_eq!(remaining_cash(&balanced_portfolio), 0.0);
+
+        // The bond fund has a minimum of its own, which it reaches once it gets the money of the TIPS fund.
+        let mut min_trades = AssetAmounts::default();
+        min_trades.set(Some("Bond fund"), 6000.0).unwrap();
+        min_trades.set(None, 20000.0).unwrap();
+
+        let balanced_portfolio = lazy_rebalance(9000.00, min_trade_portfolio(min_trades)).unwrap();
+
+        assert_eq!(
+            deltas(&balanced_portfolio),
+            [
+                ("TIPS fund", 0.0),
+                ("Bond fund", 10000.0),
+                ("Domestic Stock ETF", 0.0),
+                ("International Stock ETF", 0.0),
+                ("CASH", -1000.0),
+            ]
+        );
+        assert_eq!(
+            left_out(&balanced_portfolio),
+            ["TIPS fund", "Domestic Stock ETF", "International Stock ETF"]
+        );
+
+        // Without a trade large enough, the contribution stays in cash.
+        let mut min_trades = AssetAmounts::default();
+        min_trades.set(None, 20000.0).unwrap();
+
+        let balanced_portfolio = lazy_rebalance(9000.00, min_trade_portfolio(min_trades)).unwrap();
+
+        assert!(!has_trades(&balanced_portfolio));
+
+        assert_eq!(
+            deltas(&balanced_portfolio),
+            [
+                ("TIPS fund", 0.0),
+                ("Bond fund", 0.0),
+                ("Domestic Stock ETF", 0.0),
+                ("International Stock ETF", 0.0),
+                ("CASH", 9000.0),
+            ]
+        );
+        assert_eq!(remaining_cash(&balanced_portfolio), 10000.0);
+    }
+
+    #[test]
+    fn test_trade_costs() {
+        let rebalance_with_costs = |costs: TradeCosts| {
+            let portfolio = build_portfolio(
+                read_portfolio_holdings(
+                    &Input::read("example/portfolio.csv").unwrap(),
+                    &PortfolioFormat::default(),
+                )
+                .unwrap(),
+                create_target_map("example/targets.csv").unwrap(),
+                &AssetClasses::default(),
+                &PortfolioOptions {
+                    costs,
+                    ..PortfolioOptions::default()
+                },
+            )
+            .unwrap();
+
+            rebalance_net_of_costs(10000.00, portfolio, &RebalanceOptions::default()).unwrap()
+        };
+
+        let trade_costs = |balanced_portfolio: &[Asset]| -> Vec<f64> {
+            balanced_portfolio
+                .iter()
+                .map(|asset| round(asset.cost(), 2))
+                .collect()
+        };
+
+        // The trades and their costs add up to the contribution.
+        let mut costs = TradeCosts::default();
+        costs.buy_commission.set(None, 9.99).unwrap();
+        costs.sell_commission.set(None, 9.99).unwrap();
+        costs.spread.set(None, 0.1).unwrap();
+
+        let balanced_portfolio = rebalance_with_costs(costs);
+
+        assert_eq!(
+            deltas(&balanced_portfolio),
+            [
+                ("TIPS fund", 4422.87),
+                ("Bond fund", 5345.73),
+                ("Domestic Stock ETF", 191.47),
+                ("International Stock ETF", 0.0),
+            ]
+        );
+        assert_eq!(trade_costs(&balanced_portfolio), [14.41, 15.34, 10.18, 0.0]);
+
+        // The 214.29 of the domestic stock ETF would cost more than it brings it closer to its target.
+        let mut costs = TradeCosts::default();
+        costs.buy_commission.set(None, 250.0).unwrap();
+        costs.sell_commission.set(None, 0.0).unwrap();
+
+        let balanced_portfolio = rebalance_with_costs(costs);
+
+        assert_eq!(
+            deltas(&balanced_portfolio),
+            [
+                ("TIPS fund", 4333.33),
+                ("Bond fund", 5166.67),
+                ("Domestic Stock ETF", 0.0),
+                ("International Stock ETF", 0.0),
+            ]
+        );
+        assert_eq!(trade_costs(&balanced_portfolio), [250.0, 250.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn test_rebalance_modes() {
+        let rebalance_in_mode = |holdings: &str, mode: RebalanceMode, tolerance: f64| {
+            let portfolio = build_portfolio(
+                read_portfolio_holdings(
+                    &Input::inline("--portfolio-inline", holdings),
+                    &PortfolioFormat::default(),
+                )
+                .unwrap(),
+                create_target_map("example/targets.csv").unwrap(),
+                &AssetClasses::default(),
+                &PortfolioOptions::default(),
+            )
+            .unwrap();
+
+            rebalance_net_of_costs(
+                0.0,
+                portfolio,
+                &RebalanceOptions {
+                    mode,
+                    tolerance,
+                    ..RebalanceOptions::default()
+                },
+            )
+            .unwrap()
+        };
+
+        let holdings =
+            "Bond fund,16500\nTIPS fund,6500\nDomestic Stock ETF,43500\nInternational Stock ETF,33500";
+
+        // Every asset is brought to its target, selling the overweight ones.
+        let balanced_portfolio = rebalance_in_mode(holdings, RebalanceMode::Full, 0.0);
+
+        assert_eq!(
+            deltas(&balanced_portfolio),
+            [
+                ("TIPS fund", 3500.0),
+                ("Bond fund", 3500.0),
+                ("Domestic Stock ETF", -3500.0),
+                ("International Stock ETF", -3500.0),
+            ]
+        );
+
+        // Every asset is within 5 points of its target, so nothing is sold.
+        let balanced_portfolio = rebalance_in_mode(holdings, RebalanceMode::Hybrid, 5.0);
+
+        assert_eq!(
+            deltas(&balanced_portfolio),
+            [
+                ("TIPS fund", 0.0),
+                ("Bond fund", 0.0),
+                ("Domestic Stock ETF", 0.0),
+                ("International Stock ETF", 0.0),
+            ]
+        );
+
+        // Within 2 points, the stock ETFs are sold down to 2 points above their targets, and the funds bought up to 2
+        // points below theirs.
+        let balanced_portfolio = rebalance_in_mode(holdings, RebalanceMode::Hybrid, 2.0);
+
+        assert_eq!(
+            deltas(&balanced_portfolio),
+            [
+                ("TIPS fund", 1500.0),
+                ("Bond fund", 1500.0),
+                ("Domestic Stock ETF", -1500.0),
+                ("International Stock ETF", -1500.0),
+            ]
+        );
+
+        // The international stock ETF sold down to 35% is not enough to buy the bond fund up to 15%, so the rest is sold
+        // from the assets furthest above their targets, as a withdrawal would.
+        let holdings =
+            "Bond fund,8000\nTIPS fund,10000\nDomestic Stock ETF,44000\nInternational Stock ETF,38000";
+
+        let balanced_portfolio = rebalance_in_mode(holdings, RebalanceMode::Hybrid, 5.0);
+
+        assert_eq!(
+            deltas(&balanced_portfolio),
+            [
+                ("Bond fund", 7000.0),
+                ("TIPS fund", 0.0),
+                ("Domestic Stock ETF", -1142.86),
+                ("International Stock ETF", -5857.14),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_tolerance_bands() {
+        let rebalance_by_bands = |amount_to_contribute: f64, bands: ToleranceBands| {
+            let portfolio = create_portfolio(
+                "example/portfolio.csv",
+                &PortfolioFormat::default(),
+                create_target_map("example/targets.csv").unwrap(),
+                &AssetClasses::default(),
+            )
+            .unwrap();
+
+            rebalance_net_of_costs(
+                amount_to_contribute,
+                portfolio,
+                &RebalanceOptions {
+                    mode: RebalanceMode::Bands,
+                    bands,
+                    ..RebalanceOptions::default()
+                },
+            )
+            .unwrap()
+        };
+
+        // By the 5/25 rule, the TIPS fund may only drift 2.5 points from its 10% target, so it is bought back to its
+        // target, and the rest of the contribution is invested as in lazy mode. Drift is measured before the
+        // contribution.
+        let balanced_portfolio = rebalance_by_bands(50000.0, ToleranceBands::default());
+
+        assert_eq!(
+            deltas(&balanced_portfolio),
+            [
+                ("TIPS fund", 8500.0),
+                ("Bond fund", 13500.0),
+                ("Domestic Stock ETF", 16500.0),
+                ("International Stock ETF", 11500.0),
+            ]
+        );
+        assert_eq!(
+            drifts(&balanced_portfolio),
+            [Some(-3.5), Some(-3.5), Some(3.5), Some(3.5)]
+        );
+        assert!(has_assets_out_of_band(&balanced_portfolio));
+        assert!(assets_left_out_of_band(&balanced_portfolio).is_empty());
+
+        // Without a contribution, nothing funds the TIPS fund: the assets within their band are not sold.
+        let balanced_portfolio = rebalance_by_bands(0.0, ToleranceBands::default());
+
+        assert!(!has_trades(&balanced_portfolio));
+        assert_eq!(
+            assets_left_out_of_band(&balanced_portfolio),
+            vec!["TIPS fund".to_string()]
+        );
+
+        // Within 3 points, every asset is out of band. Sold to the edge of their band, the stock ETFs fund two thirds
+        // of what it takes to buy the TIPS and bond funds back to theirs.
+        let mut bands = ToleranceBands {
+            trade_to: BandTarget::Edge,
+            ..ToleranceBands::default()
+        };
+        bands.absolute.set(None, 3.0).unwrap();
+
+        let balanced_portfolio = rebalance_by_bands(0.0, bands);
+
+        assert_eq!(
+            deltas(&balanced_portfolio),
+            [
+                ("TIPS fund", 666.67),
+                ("Bond fund", 333.33),
+                ("Domestic Stock ETF", -500.0),
+                ("International Stock ETF", -500.0),
+            ]
+        );
+
+        // Within 40% of its target, every asset is in band, and nothing is traded.
+        let mut bands = ToleranceBands::default();
+        bands.relative.set(None, 40.0).unwrap();
+
+        let balanced_portfolio = rebalance_by_bands(0.0, bands);
+
+        assert!(!has_trades(&balanced_portfolio));
+        assert!(!has_assets_out_of_band(&balanced_portfolio));
+    }
+
+    #[test]
+    fn test_missing_portfolio_column() {
+        let portfolio_format = PortfolioFormat {
+            has_headers: true,
+            name: Column::Header("Symbol".to_string()),
+            value: Some(Column::Header("Book Value".to_string())),
+            ..PortfolioFormat::default()
+        };
+
+        let target_map = create_target_map("example/targets.csv").unwrap();
+
+        let error = create_portfolio(
+            "example/portfolio_with_headers.csv",
+            &portfolio_format,
+            target_map,
+            &AssetClasses::default(),
+        )
+        .err()
+        .unwrap();
+
+        assert_eq!(
+            error.to_string(),
+            "example/portfolio_with_headers.csv: line 1: column \"Book Value\" not found in portfolio file; available columns: \"Symbol\", \"Description\", \"Quantity\", \"Price\", \"Market Value\""
+        );
+    }
+
+    #[test]
+    fn test_rebalance_errors() {
+        let error = Asset::new(
+            "Bond fund".to_string(),
+            BigRational::new(3.into(), 2.into()),
+            100.0,
+        )
+        .err()
+        .unwrap();
+
+        assert_eq!(
+            error.to_string(),
+            "invalid target allocation 150% for \"Bond fund\"; expected between 0% and 100%"
+        );
+
+        let error = lazy_rebalance(100.0, vec![]).err().unwrap();
+
+        assert_eq!(error.to_string(), "portfolio has no assets to rebalance");
+
+        let portfolio = vec![Asset::new("Bond fund".to_string(), BigRational::one(), 0.0).unwrap()];
+
+        let error = lazy_rebalance(0.0, portfolio).err().unwrap();
+
+        assert_eq!(
+            error.to_string(),
+            "cannot rebalance: the portfolio value after the contribution is zero"
+        );
     }
 }
@@ -0,0 +1,227 @@
+// Parsing of monetary amounts as they appear in broker exports.
+//
+// Supported notations include:
+//
+// - currency symbols and codes before or after the number: `$1,234.56`, `CAD 100.00`, `100.00 USD`, `C$ 5`
+// - thousands separators, between groups of three digits: `1,234.56`, `1 234.56`, `1'234.56`, and `1.234,56` / `1 234,56` with a `,` decimal separator
+// - negatives: `-12.00`, `$-12.00`, `-$12.00`, `12.00-` and parenthesised `(250.00)`, without a sign inside
+
+pub fn parse_amount(input: &str, decimal_separator: char) -> Result<f64, String> {
+    let invalid = |reason: &str| Err(format!("invalid amount \"{}\": {}", input, reason));
+
+    let mut amount = input.trim();
+
+    if amount.is_empty() {
+        return invalid("empty value");
+    }
+
+    let mut is_negative = false;
+    let mut is_parenthesised = false;
+
+    if amount.starts_with('(') && amount.ends_with(')') {
+        is_negative = true;
+        is_parenthesised = true;
+        amount = &amount[1..amount.len() - 1];
+    }
+
+    // Peel off currency markers and a single sign from both ends of the amount.
+    let mut has_sign = false;
+
+    loop {
+        let trimmed = amount.trim_start_matches(is_currency_marker);
+        let trimmed = trimmed.trim_end_matches(is_currency_marker);
+
+        let (trimmed, sign) = if let Some(rest) = trimmed.strip_prefix('-') {
+            (rest, Some(true))
+        } else if let Some(rest) = trimmed.strip_prefix('+') {
+            (rest, Some(false))
+        } else if let Some(rest) = trimmed.strip_suffix('-') {
+            (rest, Some(true))
+        } else {
+            (trimmed, None)
+        };
+
+        if let Some(negative_sign) = sign {
+            if is_parenthesised {
+                return invalid("sign inside parentheses");
+            }
+            if has_sign {
+                return invalid("more than one sign");
+            }
+            has_sign = true;
+            is_negative = is_negative || negative_sign;
+        }
+
+        if trimmed == amount {
+            break;
+        }
+
+        amount = trimmed;
+    }
+
+    if !amount.chars().any(|c| c.is_ascii_digit()) {
+        return invalid("no digits found");
+    }
+
+    let mut normalized = String::with_capacity(amount.len() + 1);
+    let mut seen_decimal_separator = false;
+
+    // Digits since the last thousands separator, which must be three.
+    let mut group_digits: Option<usize> = None;
+    let is_bad_group = |group_digits: Option<usize>| group_digits.is_some_and(|digits| digits != 3);
+    let bad_group = "thousands separator not followed by three digits";
+
+    if is_negative {
+        normalized.push('-');
+    }
+
+    for c in amount.chars() {
+        if c.is_ascii_digit() {
+            normalized.push(c);
+            group_digits = group_digits.map(|digits| digits + 1);
+        } else if c == decimal_separator {
+            if seen_decimal_separator {
+                return invalid("more than one decimal separator");
+            }
+            if is_bad_group(group_digits) {
+                return invalid(bad_group);
+            }
+            group_digits = None;
+            seen_decimal_separator = true;
+            normalized.push('.');
+        } else if is_thousands_separator(c, decimal_separator) {
+            if seen_decimal_separator {
+                return invalid("thousands separator after the decimal separator");
+            }
+            if is_bad_group(group_digits) {
+                return invalid(bad_group);
+            }
+            group_digits = Some(0);
+        } else {
+            return invalid(&format!("unexpected character '{}'", c));
+        }
+    }
+
+    if is_bad_group(group_digits) {
+        return invalid(bad_group);
+    }
+
+    match normalized.parse::<f64>() {
+        Ok(amount) => Ok(amount),
+        Err(error) => invalid(&error.to_string()),
+    }
+}
+
+// Finds the ISO 4217 currency code of an amount, such as USD in `100.00 USD` or `USD -5`. Symbols are ambiguous (`$` is
+// used by several currencies), so they are not recognized.
+pub fn parse_currency(input: &str) -> Option<String> {
+    let amount = input
+        .trim()
+        .trim_matches(|c| c == '(' || c == ')' || c == '-' || c == '+')
+        .trim();
+
+    let prefix: String = amount.chars().take_while(|c| c.is_alphabetic()).collect();
+    let suffix: String = amount
+        .chars()
+        .rev()
+        .take_while(|c| c.is_alphabetic())
+        .collect::<Vec<char>>()
+        .into_iter()
+        .rev()
+        .collect();
+
+    [prefix, suffix]
+        .iter()
+        .find(|code| is_currency_code(code))
+        .map(|code| code.to_uppercase())
+}
+
+pub fn is_currency_code(code: &str) -> bool {
+    code.len() == 3 && code.chars().all(|c| c.is_ascii_alphabetic())
+}
+
+fn is_currency_marker(c: char) -> bool {
+    c.is_alphabetic() || c.is_whitespace() || is_currency_symbol(c)
+}
+
+fn is_currency_symbol(c: char) -> bool {
+    matches!(c, '$' | '€' | '£' | '¥' | '₹' | '₩' | '₣' | '¢')
+}
+
+fn is_thousands_separator(c: char, decimal_separator: char) -> bool {
+    match c {
+        ',' | '.' => c != decimal_separator,
+        _ => matches!(c, ' ' | '\'' | '\u{a0}' | '\u{202f}'),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_amount() {
+        assert_eq!(parse_amount("$16500.00", '.'), Ok(16500.0));
+        assert_eq!(parse_amount("16500", '.'), Ok(16500.0));
+        assert_eq!(parse_amount("1,234.56", '.'), Ok(1234.56));
+        assert_eq!(parse_amount("1,234,567", '.'), Ok(1234567.0));
+        assert_eq!(parse_amount("CAD 100.00", '.'), Ok(100.0));
+        assert_eq!(parse_amount("100.00 USD", '.'), Ok(100.0));
+        assert_eq!(parse_amount("C$5", '.'), Ok(5.0));
+        assert_eq!(parse_amount("(250.00)", '.'), Ok(-250.0));
+        assert_eq!(parse_amount("($250.00)", '.'), Ok(-250.0));
+        assert_eq!(parse_amount("$-12.00", '.'), Ok(-12.0));
+        assert_eq!(parse_amount("-$12.00", '.'), Ok(-12.0));
+        assert_eq!(parse_amount("12.00-", '.'), Ok(-12.0));
+        assert_eq!(parse_amount("1 234,56", ','), Ok(1234.56));
+        assert_eq!(parse_amount("1.234,56 €", ','), Ok(1234.56));
+    }
+
+    #[test]
+    fn test_parse_currency() {
+        assert_eq!(parse_currency("100.00 USD"), Some("USD".to_string()));
+        assert_eq!(parse_currency("cad 100.00"), Some("CAD".to_string()));
+        assert_eq!(parse_currency("(EUR 250.00)"), Some("EUR".to_string()));
+        assert_eq!(parse_currency("$16500.00"), None);
+        assert_eq!(parse_currency("C$5"), None);
+        assert_eq!(parse_currency("16500"), None);
+    }
+
+    #[test]
+    fn test_parse_invalid_amount() {
+        assert_eq!(
+            parse_amount("", '.'),
+            Err("invalid amount \"\": empty value".to_string())
+        );
+        assert_eq!(
+            parse_amount("N/A", '.'),
+            Err("invalid amount \"N/A\": no digits found".to_string())
+        );
+        assert_eq!(
+            parse_amount("1.2.3", '.'),
+            Err("invalid amount \"1.2.3\": more than one decimal separator".to_string())
+        );
+        assert_eq!(
+            parse_amount("--5", '.'),
+            Err("invalid amount \"--5\": more than one sign".to_string())
+        );
+        assert_eq!(
+            parse_amount("12,50", '.'),
+            Err(
+                "invalid amount \"12,50\": thousands separator not followed by three digits"
+                    .to_string()
+            )
+        );
+        assert_eq!(
+            parse_amount("1.2345,00", ','),
+            Err(
+                "invalid amount \"1.2345,00\": thousands separator not followed by three digits"
+                    .to_string()
+            )
+        );
+        assert_eq!(
+            parse_amount("(-250)", '.'),
+            Err("invalid amount \"(-250)\": sign inside parentheses".to_string())
+        );
+    }
+}
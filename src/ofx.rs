@@ -0,0 +1,429 @@
+// Import of positions from OFX/QFX investment statements.
+//
+// Both OFX 1.x (SGML, where elements holding a value are not closed) and OFX 2.x (XML) are supported. Positions are
+// read from each statement's INVPOSLIST, with security ids resolved through the SECLIST, and cash is read from
+// INVBAL.
+
+// rust imports
+
+use std::collections::HashMap;
+
+// local imports
+
+use error::RebalanceError;
+use holding::Holding;
+use input::Input;
+use money::parse_amount;
+
+// Name of the holding for the available cash of a statement.
+pub const CASH_NAME: &str = "Cash";
+
+struct Element {
+    name: String,
+    text: Option<String>,
+    children: Vec<Element>,
+}
+
+impl Element {
+    fn new(name: &str) -> Self {
+        Element {
+            name: name.to_uppercase(),
+            text: None,
+            children: vec![],
+        }
+    }
+
+    fn child(&self, name: &str) -> Option<&Element> {
+        self.children.iter().find(|child| child.name == name)
+    }
+
+    fn child_text(&self, name: &str) -> Option<&str> {
+        self.child(name)
+            .and_then(|child| child.text.as_ref())
+            .map(|text| text.as_str())
+    }
+
+    // Finds all elements with the given name, at any depth.
+    fn descendants<'a>(&'a self, name: &str, found: &mut Vec<&'a Element>) {
+        for child in &self.children {
+            if child.name == name {
+                found.push(child);
+            } else {
+                child.descendants(name, found);
+            }
+        }
+    }
+
+    fn find_all(&self, name: &str) -> Vec<&Element> {
+        let mut found = vec![];
+        self.descendants(name, &mut found);
+        found
+    }
+}
+
+// Statements are recognized by their extension or, when piped in, by their header.
+pub fn is_ofx(input: &Input) -> bool {
+    if input.has_extension(&["ofx", "qfx"]) {
+        return true;
+    }
+
+    let contents = input.contents.trim_start();
+
+    contents.starts_with("OFXHEADER")
+        || contents.starts_with("<OFX>")
+        || (contents.starts_with("<?xml") && contents.contains("<?OFX"))
+}
+
+pub fn read_holdings(input: &Input) -> Result<Vec<Holding>, RebalanceError> {
+    parse_holdings(&input.contents)
+        .map_err(|error| RebalanceError::parse(&input.name, None, None, error))
+}
+
+fn parse_holdings(contents: &str) -> Result<Vec<Holding>, String> {
+    let root = parse(contents)?;
+
+    // Security ids are resolved to tickers, falling back to the security name.
+    let mut securities: HashMap<String, String> = HashMap::new();
+
+    for security in root.find_all("SECINFO") {
+        let unique_id = match security
+            .child("SECID")
+            .and_then(|id| id.child_text("UNIQUEID"))
+        {
+            Some(unique_id) => unique_id,
+            None => continue,
+        };
+
+        let name = security
+            .child_text("TICKER")
+            .or_else(|| security.child_text("SECNAME"))
+            .unwrap_or(unique_id);
+
+        securities.insert(unique_id.to_string(), name.to_string());
+    }
+
+    let statements = root.find_all("INVSTMTRS");
+
+    if statements.is_empty() {
+        return Err("no investment statement (INVSTMTRS) found".to_string());
+    }
+
+    let mut holdings = vec![];
+
+    for statement in statements {
+        let account = statement
+            .child("INVACCTFROM")
+            .and_then(|account| account.child_text("ACCTID"))
+            .map(|account| account.to_string());
+
+        // Amounts are in the statement's default currency, unless a position states its own.
+        let default_currency = statement
+            .child_text("CURDEF")
+            .map(|currency| currency.to_uppercase());
+
+        for position in statement.find_all("INVPOS") {
+            let unique_id = position
+                .child("SECID")
+                .and_then(|id| id.child_text("UNIQUEID"))
+                .ok_or_else(|| "position without a security id".to_string())?;
+
+            let name = match securities.get(unique_id) {
+                Some(name) => name.clone(),
+                None => unique_id.to_string(),
+            };
+
+            let amount = |element: &str| -> Result<Option<f64>, String> {
+                match position.child_text(element) {
+                    Some(text) => parse_amount(text, '.')
+                        .map(Some)
+                        .map_err(|error| format!("{} of {}: {}", element, name, error)),
+                    None => Ok(None),
+                }
+            };
+
+            let is_short = position.child_text("POSTYPE") == Some("SHORT");
+            let sign = |amount: f64| if is_short { -amount.abs() } else { amount };
+
+            let units = match (amount("UNITS")?, amount("UNITPRICE")?) {
+                (Some(quantity), Some(unit_price)) => Some((sign(quantity), unit_price)),
+                _ => None,
+            };
+
+            let value = match (amount("MKTVAL")?, units) {
+                (Some(value), _) => sign(value),
+                (None, Some((quantity, unit_price))) => quantity * unit_price,
+                (None, None) => return Err(format!("position {} has no market value", name)),
+            };
+
+            let currency = position
+                .child("CURRENCY")
+                .or_else(|| position.child("ORIGCURRENCY"))
+                .and_then(|currency| currency.child_text("CURSYM"))
+                .map(|currency| currency.to_uppercase())
+                .or_else(|| default_currency.clone());
+
+            holdings.push(Holding {
+                name,
+                value,
+                units,
+                account: account.clone(),
+                currency,
+                unvalued_quantity: None,
+            });
+        }
+
+        let cash = statement
+            .child("INVBAL")
+            .and_then(|balance| balance.child_text("AVAILCASH"));
+
+        if let Some(cash) = cash {
+            let value = parse_amount(cash, '.').map_err(|error| format!("AVAILCASH: {}", error))?;
+
+            if value != 0.0 {
+                holdings.push(Holding {
+                    name: CASH_NAME.to_string(),
+                    value,
+                    units: None,
+                    account: account.clone(),
+                    currency: default_currency.clone(),
+                    unvalued_quantity: None,
+                });
+            }
+        }
+    }
+
+    Ok(holdings)
+}
+
+fn parse(contents: &str) -> Result<Element, String> {
+    // Skip the OFX 1.x header, or the XML declaration and processing instructions of OFX 2.x.
+    let start = match contents.find("<OFX>").or_else(|| contents.find("<ofx>")) {
+        Some(start) => start,
+        None => return Err("not an OFX file".to_string()),
+    };
+
+    let mut stack: Vec<Element> = vec![Element::new("")];
+
+    // Pops the innermost element and appends it to its parent.
+    fn close(stack: &mut Vec<Element>) {
+        let element = stack.pop().unwrap();
+        stack.last_mut().unwrap().children.push(element);
+    }
+
+    let mut rest = &contents[start..];
+
+    while let Some(open) = rest.find('<') {
+        let text = rest[..open].trim();
+
+        if !text.is_empty() && stack.len() > 1 {
+            stack.last_mut().unwrap().text = Some(decode_entities(text));
+        }
+
+        let end = match rest[open..].find('>') {
+            Some(end) => open + end,
+            None => return Err("unterminated tag".to_string()),
+        };
+
+        let tag = rest[open + 1..end].trim();
+        rest = &rest[end + 1..];
+
+        if tag.starts_with('?') || tag.starts_with('!') {
+            continue;
+        }
+
+        if let Some(name) = tag.strip_prefix('/') {
+            let name = name.trim().to_uppercase();
+
+            if !stack.iter().skip(1).any(|element| element.name == name) {
+                return Err(format!("unexpected closing tag </{}>", name));
+            }
+
+            // Elements holding a value need not be closed in OFX 1.x.
+            while stack.last().unwrap().name != name {
+                close(&mut stack);
+            }
+            close(&mut stack);
+        } else {
+            // An element with a value cannot have children, so it has implicitly been closed.
+            if stack.len() > 1 && stack.last().unwrap().text.is_some() {
+                close(&mut stack);
+            }
+
+            stack.push(Element::new(tag.trim_end_matches('/')));
+
+            if tag.ends_with('/') {
+                close(&mut stack);
+            }
+        }
+    }
+
+    while stack.len() > 1 {
+        close(&mut stack);
+    }
+
+    let mut root = stack.pop().unwrap();
+
+    match root.children.pop() {
+        Some(ofx) => Ok(ofx),
+        None => Err("not an OFX file".to_string()),
+    }
+}
+
+fn decode_entities(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_xml_holdings() {
+        let contents = r###"
+<?xml version="1.0" encoding="UTF-8" standalone="no"?>
+<?OFX OFXHEADER="200" VERSION="220" SECURITY="NONE" OLDFILEUID="NONE" NEWFILEUID="NONE"?>
+<OFX>
+  <INVSTMTMSGSRSV1>
+    <INVSTMTTRNRS>
+      <INVSTMTRS>
+        <INVACCTFROM><BROKERID>example.com</BROKERID><ACCTID>TFSA-1</ACCTID></INVACCTFROM>
+        <INVPOSLIST>
+          <POSSTOCK>
+            <INVPOS>
+              <SECID><UNIQUEID>US9229087690</UNIQUEID><UNIQUEIDTYPE>ISIN</UNIQUEIDTYPE></SECID>
+              <HELDINACCT>CASH</HELDINACCT>
+              <POSTYPE>LONG</POSTYPE>
+              <UNITS>10</UNITS>
+              <UNITPRICE>87.00</UNITPRICE>
+              <MKTVAL>870.00</MKTVAL>
+            </INVPOS>
+          </POSSTOCK>
+        </INVPOSLIST>
+        <INVBAL><AVAILCASH>12.34</AVAILCASH></INVBAL>
+      </INVSTMTRS>
+    </INVSTMTTRNRS>
+  </INVSTMTMSGSRSV1>
+  <SECLISTMSGSRSV1>
+    <SECLIST>
+      <STOCKINFO>
+        <SECINFO>
+          <SECID><UNIQUEID>US9229087690</UNIQUEID><UNIQUEIDTYPE>ISIN</UNIQUEIDTYPE></SECID>
+          <SECNAME>Vanguard Total Stock Market &amp; More</SECNAME>
+        </SECINFO>
+      </STOCKINFO>
+    </SECLIST>
+  </SECLISTMSGSRSV1>
+</OFX>
+        "###;
+
+        let holdings = parse_holdings(contents).unwrap();
+
+        assert_eq!(holdings.len(), 2);
+
+        assert_eq!(holdings[0].name, "Vanguard Total Stock Market & More");
+        assert_eq!(holdings[0].value, 870.0);
+        assert_eq!(holdings[0].units, Some((10.0, 87.0)));
+        assert_eq!(holdings[0].account, Some("TFSA-1".to_string()));
+
+        assert_eq!(holdings[1].name, CASH_NAME);
+        assert_eq!(holdings[1].value, 12.34);
+    }
+
+    #[test]
+    fn test_parse_sgml_holdings() {
+        let contents = r###"
+OFXHEADER:100
+DATA:OFXSGML
+VERSION:102
+SECURITY:NONE
+ENCODING:USASCII
+
+<OFX>
+<INVSTMTMSGSRSV1>
+<INVSTMTTRNRS>
+<INVSTMTRS>
+<CURDEF>cad
+<INVACCTFROM><BROKERID>example.com<ACCTID>RRSP-1</INVACCTFROM>
+<INVPOSLIST>
+<POSSTOCK>
+<INVPOS>
+<SECID><UNIQUEID>922908769<UNIQUEIDTYPE>CUSIP</SECID>
+<HELDINACCT>MARGIN
+<POSTYPE>SHORT
+<UNITS>50
+<UNITPRICE>20.00
+<MKTVAL>1000.00
+<CURRENCY><CURRATE>1.35<CURSYM>usd</CURRENCY>
+</INVPOS>
+</POSSTOCK>
+<POSMF>
+<INVPOS>
+<SECID><UNIQUEID>XIC<UNIQUEIDTYPE>TICKER</SECID>
+<POSTYPE>LONG
+<UNITS>100
+<UNITPRICE>30.00
+</INVPOS>
+</POSMF>
+</INVPOSLIST>
+</INVSTMTRS>
+</INVSTMTTRNRS>
+</INVSTMTMSGSRSV1>
+<SECLISTMSGSRSV1>
+<SECLIST>
+<STOCKINFO>
+<SECINFO>
+<SECID><UNIQUEID>922908769<UNIQUEIDTYPE>CUSIP</SECID>
+<SECNAME>Vanguard Total Stock Market ETF
+<TICKER>VTI
+</SECINFO>
+</STOCKINFO>
+</SECLIST>
+</SECLISTMSGSRSV1>
+</OFX>
+        "###;
+
+        let holdings = parse_holdings(contents).unwrap();
+
+        assert_eq!(holdings.len(), 2);
+
+        // A short position is negative, whatever the sign of its market value.
+        assert_eq!(holdings[0].name, "VTI");
+        assert_eq!(holdings[0].value, -1000.0);
+        assert_eq!(holdings[0].units, Some((-50.0, 20.0)));
+        assert_eq!(holdings[0].account, Some("RRSP-1".to_string()));
+        assert_eq!(holdings[0].currency, Some("USD".to_string()));
+
+        // Without a market value, the position is valued by its units, in the default currency of the statement.
+        assert_eq!(holdings[1].name, "XIC");
+        assert_eq!(holdings[1].value, 3000.0);
+        assert_eq!(holdings[1].currency, Some("CAD".to_string()));
+    }
+
+    #[test]
+    fn test_parse_invalid_statements() {
+        let error = |contents: &str| parse_holdings(contents).err().unwrap();
+
+        assert_eq!(error("Symbol,Value\nVTI,100"), "not an OFX file");
+        assert_eq!(
+            error("<OFX><SIGNONMSGSRSV1></SIGNONMSGSRSV1></OFX>"),
+            "no investment statement (INVSTMTRS) found"
+        );
+        assert_eq!(error("<OFX><INVSTMTRS"), "unterminated tag");
+        assert_eq!(
+            error("<OFX><INVSTMTRS></INVPOS></OFX>"),
+            "unexpected closing tag </INVPOS>"
+        );
+        assert_eq!(
+            error("<OFX><INVSTMTRS><INVPOS><UNITS>10</INVPOS></INVSTMTRS></OFX>"),
+            "position without a security id"
+        );
+        assert_eq!(
+            error("<OFX><INVSTMTRS><INVPOS><SECID><UNIQUEID>VTI</SECID><MKTVAL>N/A</INVPOS></INVSTMTRS></OFX>"),
+            "MKTVAL of VTI: invalid amount \"N/A\": no digits found"
+        );
+    }
+}
@@ -0,0 +1,853 @@
+// A plan file: a single TOML document declaring the targets, the holdings (or the portfolio file to read them from)
+// and the options of a run. Options given on the command line take precedence over those of the plan.
+//
+// Example:
+//
+//     version = 1
+//     contribution = 5000
+//     currency = "CAD"
+//     rounding = 2
+//     whole_shares = true
+//     strategy = "hybrid"
+//     tolerance = 5
+//     target_notation = "percent"
+//     untargeted = "liquidate"
+//     cash = "CASH"
+//     cash_reserve = 500
+//     min_trade = 100
+//
+//     [targets]
+//     "Bond fund" = 20
+//     "Domestic Stock ETF" = 80
+//
+//     [classes]
+//     "Domestic Stock ETF" = ["VTI", "ITOT"]
+//
+//     [fx_rates]
+//     USD = 1.35
+//
+//     [holdings]
+//     "Bond fund" = "$16,500.00"
+//     "Domestic Stock ETF" = { quantity = 500, price = 87.00, account = "RRSP" }
+//
+//     [validation]
+//     targets-sum = "error"
+//
+//     [ledger]
+//     destination_account = "Assets:Investments"
+//     source_account = "Assets:Checking"
+//
+// Targets may be nested in tables, each with its own allocation as a `target` key (see `parse_targets`). A targets file
+// that is a glide path is read at the date of `as_of` (default: today), and at the age of the investor born on
+// `birth_date` when it is by age. Dates and ages are also used to resolve formula targets, e.g.
+// `"Bond fund" = "age - 20"`.
+//
+// Instead of inline holdings, a `[portfolio]` table may point to a portfolio file, with the same options as the
+// command line: `file`, `format`, `headers`, `name_column`, `value_column`, `value_index`, `quantity_column`,
+// `price_column`, `account_column`, `currency_column`, `decimal_separator`, `journal_prefix` and `journal_accounts`. Likewise, targets may
+// be read from a targets file with `targets_file`, asset classes from an asset classes file with `classes_file`, and
+// exchange rates from an FX rates file with `fx_rates_file`. Holdings given by their quantity alone are valued from the
+// price file of `prices_file`; prices older than `max_price_age` days are reported. Holdings named in `cash` are
+// uninvested cash: all of it above `cash_reserve` is invested, on top of the contribution. Trades smaller than
+// `min_trade` are left out; it is an amount, or a table of amounts by asset. So are the costs of trading:
+// `buy_commission` and `sell_commission` per trade, `share_fee` per share and `spread`, a percentage of the amount
+// traded. `strategy` is the rebalancing mode (lazy, full, hybrid or bands), and `tolerance` the drift allowed by the
+// hybrid mode, in percentage points. The bands of the bands mode are `band_absolute`, in percentage points, and
+// `band_relative`, in percent of the target allocation, each an amount or a table of amounts by asset; assets outside
+// their band are traded to `band_trade_to`, "target" or "edge".
+
+// rust imports
+
+use std::fs;
+use std::path::Path;
+
+// 3rd-party imports
+
+use chrono::NaiveDate;
+
+use toml::value::Table;
+use toml::Value;
+
+// local imports
+
+use classes::AssetClasses;
+use error::RebalanceError;
+use format::{Column, PortfolioFormat};
+use fx::FxRates;
+use holding::Holding;
+use money::{parse_amount, parse_currency};
+use rebalance::{
+    AssetAmounts, BandTarget, RebalanceMode, ToleranceBands, TradeCosts, UntargetedMode,
+    BAND_TARGETS, REBALANCE_MODES, UNTARGETED_MODES,
+};
+use targets::{parse_allocation, parse_date, TargetNotation, Targets, TARGET_NOTATIONS};
+use validation::ValidationOptions;
+
+pub const PLAN_VERSION: i64 = 1;
+
+// Where the targets or holdings of a plan come from.
+pub enum Source<T> {
+    File(String),
+    Inline(T),
+}
+
+pub struct LedgerPlan {
+    pub enabled: bool,
+    pub dest_account_name: Option<String>,
+    pub source_account_name: Option<String>,
+}
+
+pub struct Plan {
+    pub contribution: Option<f64>,
+    pub targets: Option<Source<Targets>>,
+    pub target_notation: Option<TargetNotation>,
+    // Date to read the allocations of a glide path at, and the birth date of the investor.
+    pub as_of: Option<NaiveDate>,
+    pub birth_date: Option<NaiveDate>,
+    pub holdings: Option<Source<Vec<Holding>>>,
+    pub classes: Option<Source<AssetClasses>>,
+    pub fx_rates: Option<Source<FxRates>>,
+    // Path of the price file.
+    pub prices: Option<String>,
+    pub max_price_age: Option<i64>,
+    pub untargeted: Option<UntargetedMode>,
+    // Names of the holdings of uninvested cash, and the amount of cash to keep.
+    pub cash: Vec<String>,
+    pub cash_reserve: Option<f64>,
+    pub min_trades: AssetAmounts,
+    pub costs: TradeCosts,
+    pub portfolio_format: PortfolioFormat,
+    pub ledger: LedgerPlan,
+    pub validation: ValidationOptions,
+    pub currency: Option<String>,
+    pub rounding: Option<usize>,
+    // Whether trades are rounded to whole shares.
+    pub whole_shares: bool,
+    pub mode: Option<RebalanceMode>,
+    pub tolerance: Option<f64>,
+    pub bands: ToleranceBands,
+}
+
+impl Default for Plan {
+    fn default() -> Self {
+        Plan {
+            contribution: None,
+            targets: None,
+            target_notation: None,
+            as_of: None,
+            birth_date: None,
+            holdings: None,
+            classes: None,
+            fx_rates: None,
+            prices: None,
+            max_price_age: None,
+            untargeted: None,
+            cash: vec![],
+            cash_reserve: None,
+            min_trades: AssetAmounts::default(),
+            costs: TradeCosts::default(),
+            portfolio_format: PortfolioFormat::default(),
+            ledger: LedgerPlan {
+                enabled: false,
+                dest_account_name: None,
+                source_account_name: None,
+            },
+            validation: ValidationOptions::default(),
+            currency: None,
+            rounding: None,
+            whole_shares: false,
+            mode: None,
+            tolerance: None,
+            bands: ToleranceBands::default(),
+        }
+    }
+}
+
+pub fn read_plan(path: &str) -> Result<Plan, RebalanceError> {
+    let contents = fs::read_to_string(path).map_err(|error| RebalanceError::io(path, error))?;
+
+    // Files referenced by the plan are relative to the plan itself.
+    let base_dir = Path::new(path).parent().unwrap_or_else(|| Path::new(""));
+
+    parse_plan(&contents, base_dir).map_err(|error| RebalanceError::parse(path, None, None, error))
+}
+
+fn parse_plan(contents: &str, base_dir: &Path) -> Result<Plan, String> {
+    let document = contents
+        .parse::<Value>()
+        .map_err(|error| error.to_string())?;
+
+    let document = match document {
+        Value::Table(table) => table,
+        _ => return Err("plan must be a table".to_string()),
+    };
+
+    match document.get("version") {
+        Some(&Value::Integer(PLAN_VERSION)) => {}
+        Some(version) => {
+            return Err(format!(
+                "unsupported plan version {}; expected {}",
+                version, PLAN_VERSION
+            ))
+        }
+        None => return Err("plan has no version".to_string()),
+    }
+
+    let resolve_path = |file: &str| base_dir.join(file).to_string_lossy().into_owned();
+
+    let contribution = match document.get("contribution") {
+        Some(value) => Some(number(value, "contribution")?),
+        None => None,
+    };
+
+    let targets = match (document.get("targets"), document.get("targets_file")) {
+        (Some(_), Some(_)) => {
+            return Err("plan has both targets and a targets_file".to_string());
+        }
+        (Some(Value::Table(targets)), None) => Some(Source::Inline(parse_targets(targets)?)),
+        (Some(_), None) => return Err("targets must be a table".to_string()),
+        (None, Some(file)) => Some(Source::File(resolve_path(string(file, "targets_file")?))),
+        (None, None) => None,
+    };
+
+    let target_notation = match optional_string(&document, "target_notation")? {
+        Some(name) => match TargetNotation::from_name(&name) {
+            Some(notation) => Some(notation),
+            None => {
+                return Err(format!(
+                    "unknown target notation \"{}\"; expected one of: {}",
+                    name,
+                    TARGET_NOTATIONS.join(", ")
+                ))
+            }
+        },
+        None => None,
+    };
+
+    let classes = match (document.get("classes"), document.get("classes_file")) {
+        (Some(_), Some(_)) => {
+            return Err("plan has both classes and a classes_file".to_string());
+        }
+        (Some(Value::Table(classes)), None) => Some(Source::Inline(parse_classes(classes)?)),
+        (Some(_), None) => return Err("classes must be a table".to_string()),
+        (None, Some(file)) => Some(Source::File(resolve_path(string(file, "classes_file")?))),
+        (None, None) => None,
+    };
+
+    let fx_rates = match (document.get("fx_rates"), document.get("fx_rates_file")) {
+        (Some(_), Some(_)) => {
+            return Err("plan has both fx_rates and an fx_rates_file".to_string());
+        }
+        (Some(Value::Table(fx_rates)), None) => Some(Source::Inline(parse_fx_rates(fx_rates)?)),
+        (Some(_), None) => return Err("fx_rates must be a table".to_string()),
+        (None, Some(file)) => Some(Source::File(resolve_path(string(file, "fx_rates_file")?))),
+        (None, None) => None,
+    };
+
+    let prices = optional_string(&document, "prices_file")?.map(|file| resolve_path(&file));
+
+    let max_price_age = match document.get("max_price_age") {
+        Some(&Value::Integer(days)) if days >= 0 => Some(days),
+        Some(_) => return Err("max_price_age must be a non-negative number of days".to_string()),
+        None => None,
+    };
+
+    let untargeted = match optional_string(&document, "untargeted")? {
+        Some(name) => match UntargetedMode::from_name(&name) {
+            Some(mode) => Some(mode),
+            None => {
+                return Err(format!(
+                    "unknown untargeted mode \"{}\"; expected one of: {}",
+                    name,
+                    UNTARGETED_MODES.join(", ")
+                ))
+            }
+        },
+        None => None,
+    };
+
+    let cash = match document.get("cash") {
+        Some(Value::String(name)) => vec![name.trim().to_string()],
+        Some(Value::Array(names)) => names
+            .iter()
+            .map(|name| string(name, "cash").map(|name| name.trim().to_string()))
+            .collect::<Result<Vec<String>, String>>()?,
+        Some(_) => return Err("cash must be a holding name or a list of them".to_string()),
+        None => vec![],
+    };
+
+    let cash_reserve = match document.get("cash_reserve") {
+        Some(value) => match number(value, "cash_reserve")? {
+            cash_reserve if cash_reserve >= 0.0 => Some(cash_reserve),
+            _ => return Err("cash_reserve must be a non-negative amount".to_string()),
+        },
+        None => None,
+    };
+
+    let min_trades = asset_amounts(&document, "min_trade")?;
+
+    let costs = TradeCosts {
+        buy_commission: asset_amounts(&document, "buy_commission")?,
+        sell_commission: asset_amounts(&document, "sell_commission")?,
+        share_fee: asset_amounts(&document, "share_fee")?,
+        spread: asset_amounts(&document, "spread")?,
+    };
+
+    let portfolio = optional_table(&document, "portfolio")?;
+
+    let holdings = match (
+        document.get("holdings"),
+        portfolio.and_then(|p| p.get("file")),
+    ) {
+        (Some(_), Some(_)) => {
+            return Err("plan has both holdings and a portfolio file".to_string());
+        }
+        (Some(Value::Table(holdings)), None) => Some(Source::Inline(parse_holdings(holdings)?)),
+        (Some(_), None) => return Err("holdings must be a table".to_string()),
+        (None, Some(file)) => Some(Source::File(resolve_path(string(file, "portfolio.file")?))),
+        (None, None) => None,
+    };
+
+    let portfolio_format = match portfolio {
+        Some(portfolio) => parse_portfolio_format(portfolio)?,
+        None => PortfolioFormat::default(),
+    };
+
+    let ledger = match optional_table(&document, "ledger")? {
+        Some(ledger) => LedgerPlan {
+            enabled: match ledger.get("enabled") {
+                Some(&Value::Boolean(enabled)) => enabled,
+                Some(_) => return Err("ledger.enabled must be true or false".to_string()),
+                None => false,
+            },
+            dest_account_name: optional_string(ledger, "destination_account")?,
+            source_account_name: optional_string(ledger, "source_account")?,
+        },
+        None => Plan::default().ledger,
+    };
+
+    let mut validation = ValidationOptions::default();
+
+    if let Some(severities) = optional_table(&document, "validation")? {
+        for (kind_name, severity_name) in severities {
+            let severity_name = string(severity_name, &format!("validation.{}", kind_name))?;
+
+            validation.set(kind_name, severity_name)?;
+        }
+    }
+
+    let whole_shares = match document.get("whole_shares") {
+        Some(&Value::Boolean(whole_shares)) => whole_shares,
+        Some(_) => return Err("whole_shares must be true or false".to_string()),
+        None => false,
+    };
+
+    let rounding = match document.get("rounding") {
+        Some(&Value::Integer(places)) if places >= 0 => Some(places as usize),
+        Some(_) => {
+            return Err("rounding must be a non-negative number of decimal places".to_string())
+        }
+        None => None,
+    };
+
+    let mode = match optional_string(&document, "strategy")? {
+        Some(strategy) => match RebalanceMode::from_name(&strategy) {
+            Some(mode) => Some(mode),
+            None => {
+                return Err(format!(
+                    "unknown strategy \"{}\"; expected one of: {}",
+                    strategy,
+                    REBALANCE_MODES.join(", ")
+                ))
+            }
+        },
+        None => None,
+    };
+
+    let tolerance = match document.get("tolerance") {
+        Some(value) => match number(value, "tolerance")? {
+            tolerance if tolerance >= 0.0 => Some(tolerance),
+            _ => {
+                return Err(
+                    "tolerance must be a non-negative number of percentage points".to_string(),
+                )
+            }
+        },
+        None => None,
+    };
+
+    let bands = ToleranceBands {
+        absolute: asset_amounts(&document, "band_absolute")?,
+        relative: asset_amounts(&document, "band_relative")?,
+        trade_to: match optional_string(&document, "band_trade_to")? {
+            Some(name) => match BandTarget::from_name(&name) {
+                Some(trade_to) => trade_to,
+                None => {
+                    return Err(format!(
+                        "unknown band_trade_to \"{}\"; expected one of: {}",
+                        name,
+                        BAND_TARGETS.join(", ")
+                    ))
+                }
+            },
+            None => BandTarget::Target,
+        },
+    };
+
+    Ok(Plan {
+        contribution,
+        targets,
+        target_notation,
+        as_of: optional_date(&document, "as_of")?,
+        birth_date: optional_date(&document, "birth_date")?,
+        holdings,
+        classes,
+        fx_rates,
+        prices,
+        max_price_age,
+        untargeted,
+        cash,
+        cash_reserve,
+        min_trades,
+        costs,
+        portfolio_format,
+        ledger,
+        validation,
+        currency: optional_string(&document, "currency")?,
+        rounding,
+        whole_shares,
+        mode,
+        tolerance,
+        bands,
+    })
+}
+
+// Allocations are read as written, like those of a targets file: `40`, `0.4` or `"40%"`. A table nests targets under
+// a target, whose own allocation is its `target` key:
+//
+//     [targets.Equity]
+//     target = 70
+//     Canada = 30
+//     US = 40
+//     International = 30
+fn parse_targets(table: &Table) -> Result<Targets, String> {
+    let mut targets = Targets::default();
+
+    parse_nested_targets(table, None, &mut targets)?;
+
+    Ok(targets)
+}
+
+fn parse_nested_targets(
+    table: &Table,
+    parent: Option<&str>,
+    targets: &mut Targets,
+) -> Result<(), String> {
+    for (asset_name, allocation) in table {
+        let asset_name = asset_name.trim();
+
+        if parent.is_some() && asset_name == "target" {
+            continue;
+        }
+
+        let context = format!("target of \"{}\"", asset_name);
+
+        let (allocation, children) = match *allocation {
+            Value::Table(ref children) => match children.get("target") {
+                Some(allocation) => (allocation, Some(children)),
+                None => return Err(format!("{} needs a target key", context)),
+            },
+            ref allocation => (allocation, None),
+        };
+
+        // Floats are read from their shortest representation, so that 0.4 is exactly 2/5.
+        let allocation = match *allocation {
+            Value::Integer(number) => parse_allocation(&number.to_string()),
+            Value::Float(number) => parse_allocation(&number.to_string()),
+            Value::String(ref allocation) => parse_allocation(allocation),
+            _ => {
+                return Err(format!(
+                    "{} must be a number, a percentage or a formula",
+                    context
+                ))
+            }
+        }
+        .map_err(|error| format!("{}: {}", context, error))?;
+
+        // Same as a targets file: assets without an allocation are left out.
+        if allocation.is_left_out() {
+            continue;
+        }
+
+        targets.insert(
+            asset_name.to_string(),
+            allocation,
+            parent.map(|parent| parent.to_string()),
+        );
+
+        if let Some(children) = children {
+            parse_nested_targets(children, Some(asset_name), targets)?;
+        }
+    }
+
+    Ok(())
+}
+
+// Each asset class lists its tickers, preferred ticker first.
+fn parse_classes(table: &Table) -> Result<AssetClasses, String> {
+    let mut asset_classes = AssetClasses::default();
+
+    for (class_name, tickers) in table {
+        let context = format!("tickers of asset class \"{}\"", class_name);
+
+        let tickers = match *tickers {
+            Value::Array(ref tickers) => tickers
+                .iter()
+                .map(|ticker| string(ticker, &context).map(|ticker| ticker.trim().to_string()))
+                .collect::<Result<Vec<String>, String>>()?,
+            _ => return Err(format!("{} must be a list", context)),
+        };
+
+        asset_classes.add(class_name, tickers)?;
+    }
+
+    Ok(asset_classes)
+}
+
+// Each rate is the value of one unit of the currency in the base currency, such as `USD = 1.35`, or in the currency
+// given with the rate, such as `USD = "1.35 CAD"`.
+fn parse_fx_rates(table: &Table) -> Result<FxRates, String> {
+    let mut fx_rates = FxRates::default();
+
+    for (currency, rate) in table {
+        let context = format!("exchange rate of {}", currency);
+
+        fx_rates.add(
+            currency,
+            currency_of(rate).as_deref(),
+            number(rate, &context)?,
+        )?;
+    }
+
+    Ok(fx_rates)
+}
+
+fn parse_holdings(table: &Table) -> Result<Vec<Holding>, String> {
+    let mut holdings = vec![];
+
+    for (asset_name, value) in table {
+        match *value {
+            // The same asset held in several accounts.
+            Value::Array(ref positions) => {
+                for position in positions {
+                    holdings.push(parse_holding(asset_name, position)?);
+                }
+            }
+            _ => holdings.push(parse_holding(asset_name, value)?),
+        }
+    }
+
+    Ok(holdings)
+}
+
+fn parse_holding(asset_name: &str, value: &Value) -> Result<Holding, String> {
+    let context = format!("holding \"{}\"", asset_name);
+
+    let holding = match *value {
+        Value::Table(ref position) => {
+            let amount = |key: &str| -> Result<Option<f64>, String> {
+                match position.get(key) {
+                    Some(value) => number(value, &format!("{}.{}", context, key)).map(Some),
+                    None => Ok(None),
+                }
+            };
+
+            let quantity = amount("quantity")?;
+
+            let units = match (quantity, amount("price")?) {
+                (Some(quantity), Some(price)) => Some((quantity, price)),
+                (_, None) => None,
+                (None, Some(_)) => {
+                    return Err(format!("{} needs both a quantity and a price", context))
+                }
+            };
+
+            // A quantity without a price or a value is valued from a price file.
+            let mut unvalued_quantity = None;
+
+            let value = match (amount("value")?, units, quantity) {
+                (Some(value), _, _) => value,
+                (None, Some((quantity, price)), _) => quantity * price,
+                (None, None, Some(quantity)) => {
+                    unvalued_quantity = Some(quantity);
+                    0.0
+                }
+                (None, None, None) => {
+                    return Err(format!(
+                        "{} needs a value, or a quantity and a price",
+                        context
+                    ))
+                }
+            };
+
+            let currency = match optional_string(position, "currency")? {
+                Some(currency) => Some(currency.to_uppercase()),
+                None => position.get("value").and_then(currency_of),
+            };
+
+            Holding {
+                name: asset_name.trim().to_string(),
+                value,
+                units,
+                account: optional_string(position, "account")?,
+                currency,
+                unvalued_quantity,
+            }
+        }
+        _ => Holding {
+            name: asset_name.trim().to_string(),
+            value: number(value, &context)?,
+            units: None,
+            account: None,
+            currency: currency_of(value),
+            unvalued_quantity: None,
+        },
+    };
+
+    Ok(holding)
+}
+
+fn parse_portfolio_format(portfolio: &Table) -> Result<PortfolioFormat, String> {
+    let mut format = match optional_string(portfolio, "format")? {
+        Some(name) => match PortfolioFormat::preset(&name) {
+            Some(format) => format,
+            None => return Err(format!("unknown portfolio format \"{}\"", name)),
+        },
+        None => PortfolioFormat::default(),
+    };
+
+    if let Some(&Value::Boolean(has_headers)) = portfolio.get("headers") {
+        format.has_headers = has_headers;
+    }
+
+    if let Some(index) = portfolio.get("value_index") {
+        match *index {
+            Value::Integer(index) if index >= 0 => {
+                format.value = Some(Column::Index(index as usize));
+            }
+            _ => return Err("portfolio.value_index must be a column index".to_string()),
+        }
+    }
+
+    if let Some(header) = optional_string(portfolio, "name_column")? {
+        format.name = Column::Header(header);
+        format.has_headers = true;
+    }
+
+    let header_column = |key: &str| -> Result<Option<Column>, String> {
+        Ok(optional_string(portfolio, key)?.map(Column::Header))
+    };
+
+    let value_column = header_column("value_column")?;
+    let quantity_column = header_column("quantity_column")?;
+    let price_column = header_column("price_column")?;
+    let account_column = header_column("account_column")?;
+    let currency_column = header_column("currency_column")?;
+
+    let is_valued_by_units = value_column.is_none()
+        && !portfolio.contains_key("value_index")
+        && !portfolio.contains_key("format")
+        && quantity_column.is_some();
+
+    for (column, target) in [
+        (value_column, &mut format.value),
+        (quantity_column, &mut format.quantity),
+        (price_column, &mut format.price),
+        (account_column, &mut format.account),
+        (currency_column, &mut format.currency),
+    ] {
+        if column.is_some() {
+            *target = column;
+            format.has_headers = true;
+        }
+    }
+
+    // Without a value column, holdings given as quantity and price are valued as quantity × price, and holdings given as
+    // quantity alone are valued from the price file.
+    if is_valued_by_units {
+        format.value = None;
+    }
+
+    if let Some(separator) = optional_string(portfolio, "decimal_separator")? {
+        let mut chars = separator.chars();
+
+        match (chars.next(), chars.next()) {
+            (Some(separator), None) => format.decimal_separator = separator,
+            _ => return Err("portfolio.decimal_separator must be a single character".to_string()),
+        }
+    }
+
+    if let Some(prefix) = optional_string(portfolio, "journal_prefix")? {
+        format.journal.account_prefix = prefix;
+    }
+
+    if let Some(accounts) = optional_table(portfolio, "journal_accounts")? {
+        for (account, asset_name) in accounts {
+            let asset_name = string(asset_name, &format!("journal_accounts.\"{}\"", account))?;
+
+            format
+                .journal
+                .account_names
+                .push((account.to_string(), asset_name.to_string()));
+        }
+    }
+
+    Ok(format)
+}
+
+// Numbers may also be written as strings, such as "$16,500.00".
+fn number(value: &Value, context: &str) -> Result<f64, String> {
+    match *value {
+        Value::Integer(number) => Ok(number as f64),
+        Value::Float(number) => Ok(number),
+        Value::String(ref amount) => {
+            parse_amount(amount, '.').map_err(|error| format!("{}: {}", context, error))
+        }
+        _ => Err(format!("{} must be a number", context)),
+    }
+}
+
+// An amount for every asset, such as `min_trade = 100`, or a table of amounts by asset, with `all` for the others.
+fn asset_amounts(table: &Table, key: &str) -> Result<AssetAmounts, String> {
+    let mut amounts = AssetAmounts::default();
+
+    match table.get(key) {
+        Some(Value::Table(asset_amounts)) => {
+            for (asset_name, amount) in asset_amounts {
+                let context = format!("{}.{}", key, asset_name);
+
+                let asset_name = match asset_name.as_str() {
+                    "all" => None,
+                    asset_name => Some(asset_name),
+                };
+
+                amounts
+                    .set(asset_name, number(amount, &context)?)
+                    .map_err(|error| format!("{}: {}", context, error))?;
+            }
+        }
+        Some(amount) => amounts
+            .set(None, number(amount, key)?)
+            .map_err(|error| format!("{}: {}", key, error))?,
+        None => {}
+    }
+
+    Ok(amounts)
+}
+
+// Currency code of an amount written as a string, such as "USD 1,000".
+fn currency_of(value: &Value) -> Option<String> {
+    match *value {
+        Value::String(ref amount) => parse_currency(amount),
+        _ => None,
+    }
+}
+
+fn string<'a>(value: &'a Value, context: &str) -> Result<&'a str, String> {
+    match *value {
+        Value::String(ref string) => Ok(string),
+        _ => Err(format!("{} must be a string", context)),
+    }
+}
+
+fn optional_string(table: &Table, key: &str) -> Result<Option<String>, String> {
+    match table.get(key) {
+        Some(value) => string(value, key).map(|string| Some(string.to_string())),
+        None => Ok(None),
+    }
+}
+
+// Dates may be TOML dates, or strings.
+fn optional_date(table: &Table, key: &str) -> Result<Option<NaiveDate>, String> {
+    let date = match table.get(key) {
+        Some(Value::Datetime(date)) => date.to_string(),
+        Some(Value::String(date)) => date.clone(),
+        Some(_) => return Err(format!("{} must be a date (YYYY-MM-DD)", key)),
+        None => return Ok(None),
+    };
+
+    match parse_date(&date) {
+        Some(date) => Ok(Some(date)),
+        None => Err(format!("invalid {} \"{}\"; expected YYYY-MM-DD", key, date)),
+    }
+}
+
+fn optional_table<'a>(table: &'a Table, key: &str) -> Result<Option<&'a Table>, String> {
+    match table.get(key) {
+        Some(Value::Table(table)) => Ok(Some(table)),
+        Some(_) => Err(format!("{} must be a table", key)),
+        None => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_plan_with_portfolio_file() {
+        let contents = r###"
+version = 1
+targets_file = "targets.csv"
+
+[portfolio]
+file = "schwab.csv"
+format = "schwab"
+decimal_separator = ","
+        "###;
+
+        let plan = parse_plan(contents, Path::new("example/broker")).unwrap();
+
+        match plan.targets {
+            Some(Source::File(path)) => assert_eq!(path, "example/broker/targets.csv"),
+            _ => panic!("expected a targets file"),
+        }
+
+        match plan.holdings {
+            Some(Source::File(path)) => assert_eq!(path, "example/broker/schwab.csv"),
+            _ => panic!("expected a portfolio file"),
+        }
+
+        assert!(plan.portfolio_format.skip_preamble);
+        assert_eq!(plan.portfolio_format.decimal_separator, ',');
+        assert_eq!(plan.contribution, None);
+        assert!(!plan.ledger.enabled);
+    }
+
+    #[test]
+    fn test_parse_invalid_plan() {
+        let error = |contents: &str| parse_plan(contents, Path::new("")).err().unwrap();
+
+        assert_eq!(error("contribution = 5"), "plan has no version");
+        assert_eq!(
+            error("version = 2"),
+            "unsupported plan version 2; expected 1"
+        );
+        assert_eq!(
+            error("version = 1\nstrategy = \"eager\""),
+            "unknown strategy \"eager\"; expected one of: lazy, full, hybrid, bands"
+        );
+        assert_eq!(
+            error("version = 1\nband_trade_to = \"middle\""),
+            "unknown band_trade_to \"middle\"; expected one of: target, edge"
+        );
+        assert_eq!(
+            error("version = 1\n[holdings]\nVTI = { price = 5 }"),
+            "holding \"VTI\" needs both a quantity and a price"
+        );
+        assert_eq!(
+            error("version = 1\n[min_trade]\nall = -5"),
+            "min_trade.all: invalid amount -5; expected a non-negative amount"
+        );
+    }
+}
@@ -0,0 +1,200 @@
+// Prices of securities, read from a price file, to value holdings by their number of units.
+//
+// A price file is a CSV file with one price per row: the ticker, the price and, optionally, the currency of the price
+// and the date it was quoted (YYYY-MM-DD). A header row is skipped. This lets a portfolio list only the units held,
+// while prices are refreshed before each run.
+//
+// Example:
+//
+//     Ticker, Price, Currency, Date
+//     VTI,    87.00, USD,      2019-08-02
+//     XIC,    28.50, CAD,      2019-08-02
+
+// rust imports
+
+use std::collections::HashMap;
+
+// 3rd-party imports
+
+use chrono::NaiveDate;
+
+// local imports
+
+use error::RebalanceError;
+use holding::Holding;
+use input::Input;
+use money::{is_currency_code, parse_amount};
+
+// Number of days after which a price is stale, unless set otherwise.
+pub const DEFAULT_MAX_PRICE_AGE: i64 = 7;
+
+pub struct Price {
+    pub price: f64,
+    pub currency: Option<String>,
+    // Date the price was quoted, if the price file gives one.
+    pub date: Option<NaiveDate>,
+}
+
+#[derive(Default)]
+pub struct Prices {
+    // Name of the price file, for messages.
+    pub name: String,
+    // Price of each ticker; the last row of a ticker is used.
+    pub prices: HashMap<String, Price>,
+}
+
+pub fn read_prices(input: &Input) -> Result<Prices, RebalanceError> {
+    let path = input.name.as_str();
+
+    let mut reader = input.csv_reader();
+
+    let mut prices = HashMap::new();
+
+    for result in reader.records() {
+        let record = result.map_err(|error| RebalanceError::csv(path, error))?;
+
+        let line = record.position().map(|position| position.line());
+        let error = |column: usize, message: String| {
+            RebalanceError::parse(path, line, Some(column), message)
+        };
+
+        let cell = |index: usize| record.get(index).map(|cell| cell.trim()).unwrap_or("");
+
+        let ticker = cell(0);
+
+        if ticker.is_empty() {
+            continue;
+        }
+
+        // A header row has no digits where the price should be.
+        if line == Some(1) && !cell(1).chars().any(|c| c.is_ascii_digit()) {
+            continue;
+        }
+
+        let price = parse_amount(cell(1), '.').map_err(|message| error(2, message))?;
+
+        if price <= 0.0 {
+            return Err(error(
+                2,
+                format!("price of \"{}\" must be a positive number", ticker),
+            ));
+        }
+
+        let currency = match cell(2) {
+            "" => None,
+            currency if is_currency_code(currency) => Some(currency.to_uppercase()),
+            currency => return Err(error(3, format!("invalid currency \"{}\"", currency))),
+        };
+
+        let date = match cell(3) {
+            "" => None,
+            date => Some(
+                NaiveDate::parse_from_str(date, "%Y-%m-%d")
+                    .or_else(|_| NaiveDate::parse_from_str(date, "%Y/%m/%d"))
+                    .map_err(|_| {
+                        error(4, format!("invalid date \"{}\"; expected YYYY-MM-DD", date))
+                    })?,
+            ),
+        };
+
+        prices.insert(
+            ticker.to_string(),
+            Price {
+                price,
+                currency,
+                date,
+            },
+        );
+    }
+
+    Ok(Prices {
+        name: path.to_string(),
+        prices,
+    })
+}
+
+// Values the holdings of each ticker in the price file at its price. Holdings listed by their number of units alone
+// must have a price.
+pub fn value_holdings(holdings: &mut [Holding], prices: &Prices) -> Result<(), RebalanceError> {
+    for holding in holdings.iter_mut() {
+        let quantity = match (holding.unvalued_quantity, holding.units) {
+            (Some(quantity), _) => quantity,
+            (None, Some((quantity, _))) => quantity,
+            // Holdings without units, such as cash, are kept at their value.
+            (None, None) => continue,
+        };
+
+        let price = match prices.prices.get(&holding.name) {
+            Some(price) => price,
+            None if holding.unvalued_quantity.is_some() => {
+                let message = if prices.prices.is_empty() {
+                    format!(
+                        "holding \"{}\" has a quantity but no price; give its price in a price file with --prices",
+                        holding.name
+                    )
+                } else {
+                    format!(
+                        "holding \"{}\" has no price in {}",
+                        holding.name, prices.name
+                    )
+                };
+
+                return Err(RebalanceError::Invalid(message));
+            }
+            None => continue,
+        };
+
+        holding.value = quantity * price.price;
+        holding.units = Some((quantity, price.price));
+        holding.unvalued_quantity = None;
+
+        if price.currency.is_some() {
+            holding.currency = price.currency.clone();
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_value_holdings() {
+        let input = Input::inline(
+            "prices.csv",
+            "Ticker,Price,Currency,Date\nVTI,87.00,USD,2019-08-02\nXIC,28.50,,\n",
+        );
+
+        let prices = read_prices(&input).unwrap();
+
+        let holding = |name: &str, unvalued_quantity: Option<f64>| Holding {
+            name: name.to_string(),
+            value: 0.0,
+            units: None,
+            account: None,
+            currency: None,
+            unvalued_quantity,
+        };
+
+        let mut holdings = vec![holding("VTI", Some(10.0)), holding("Cash", None)];
+
+        value_holdings(&mut holdings, &prices).unwrap();
+
+        assert_eq!(holdings[0].value, 870.0);
+        assert_eq!(holdings[0].units, Some((10.0, 87.0)));
+        assert_eq!(holdings[0].currency, Some("USD".to_string()));
+        assert_eq!(holdings[1].value, 0.0);
+
+        let mut holdings = vec![holding("BND", Some(5.0))];
+
+        assert_eq!(
+            value_holdings(&mut holdings, &prices)
+                .err()
+                .unwrap()
+                .to_string(),
+            "holding \"BND\" has no price in prices.csv"
+        );
+    }
+}
@@ -3,7 +3,6 @@
 use std::cmp::Ordering;
 use std::collections::HashMap;
 use std::io::Write;
-use std::iter::FromIterator;
 
 // 3rd-party imports
 
@@ -16,218 +15,1377 @@ use chrono::prelude::*;
 
 use tabwriter::TabWriter;
 
+// local imports
+
+use error::RebalanceError;
+use targets::TargetTree;
+
+// How holdings without a target allocation are rebalanced.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum UntargetedMode {
+    // Left out of the rebalance, and of the totals.
+    Ignore,
+    // Counted in the total of the portfolio, but not traded.
+    Hold,
+    // Sold in full, with the proceeds contributed to the targeted assets.
+    Liquidate,
+}
+
+pub const UNTARGETED_MODES: &[&str] = &["ignore", "hold", "liquidate"];
+
+// How far the portfolio is rebalanced.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RebalanceMode {
+    // The contribution is invested in the assets furthest below their target; nothing is sold.
+    Lazy,
+    // Every asset is bought or sold to its exact target.
+    Full,
+    // The contribution is invested first, and assets are sold only as needed for every asset to be within the
+    // tolerance of its target.
+    Hybrid,
+    // Only the assets outside their tolerance band are traded, back to the band or to their target.
+    Bands,
+}
+
+pub const REBALANCE_MODES: &[&str] = &["lazy", "full", "hybrid", "bands"];
+
+// Tolerance of the hybrid mode, in percentage points of the portfolio, unless set otherwise.
+pub const DEFAULT_TOLERANCE: f64 = 5.0;
+
+impl RebalanceMode {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.trim().to_lowercase().as_str() {
+            "lazy" => Some(RebalanceMode::Lazy),
+            "full" => Some(RebalanceMode::Full),
+            "hybrid" => Some(RebalanceMode::Hybrid),
+            "bands" => Some(RebalanceMode::Bands),
+            _ => None,
+        }
+    }
+}
+
+// Where an asset outside its tolerance band is traded to.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BandTarget {
+    // Its target allocation.
+    Target,
+    // The nearest edge of its band, which trades as little as possible.
+    Edge,
+}
+
+pub const BAND_TARGETS: &[&str] = &["target", "edge"];
+
+impl BandTarget {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.trim().to_lowercase().as_str() {
+            "target" => Some(BandTarget::Target),
+            "edge" => Some(BandTarget::Edge),
+            _ => None,
+        }
+    }
+}
+
+// Bands of the 5/25 rule, unless set otherwise: an asset may drift 5 percentage points of the portfolio, or 25% of its
+// target allocation, from its target, whichever is smaller.
+pub const DEFAULT_ABSOLUTE_BAND: f64 = 5.0;
+pub const DEFAULT_RELATIVE_BAND: f64 = 25.0;
+
+// How far each asset may drift from its target before it is traded: an absolute band, in percentage points of the
+// portfolio, and a relative band, in percent of its target allocation. The narrower of the two applies.
+pub struct ToleranceBands {
+    pub absolute: AssetAmounts,
+    pub relative: AssetAmounts,
+    pub trade_to: BandTarget,
+}
+
+impl Default for ToleranceBands {
+    fn default() -> Self {
+        ToleranceBands {
+            absolute: AssetAmounts::default(),
+            relative: AssetAmounts::default(),
+            trade_to: BandTarget::Target,
+        }
+    }
+}
+
+impl ToleranceBands {
+    // Half-width of the band of an asset, as a fraction of the portfolio.
+    fn width(&self, asset: &Asset) -> BigRational {
+        let percent = |amounts: &AssetAmounts, default: f64| {
+            BigRational::from_f64(amounts.of(&asset.name).unwrap_or(default)).unwrap()
+                / BigRational::from_integer(100.into())
+        };
+
+        let absolute = percent(&self.absolute, DEFAULT_ABSOLUTE_BAND);
+        let relative =
+            percent(&self.relative, DEFAULT_RELATIVE_BAND) * &asset.target_allocation_percent;
+
+        absolute.min(relative)
+    }
+}
+
+pub struct RebalanceOptions {
+    pub mode: RebalanceMode,
+    // How far an asset may drift from its target allocation in the hybrid mode, in percentage points.
+    pub tolerance: f64,
+    pub bands: ToleranceBands,
+}
+
+impl Default for RebalanceOptions {
+    fn default() -> Self {
+        RebalanceOptions {
+            mode: RebalanceMode::Lazy,
+            tolerance: DEFAULT_TOLERANCE,
+            bands: ToleranceBands::default(),
+        }
+    }
+}
+
+// An amount set for every asset, and for some assets in particular, such as the minimum trade size.
+#[derive(Default)]
+pub struct AssetAmounts {
+    default: Option<f64>,
+    amounts: HashMap<String, f64>,
+}
+
+impl AssetAmounts {
+    // Sets the amount of an asset, or of every asset without one of its own.
+    pub fn set(&mut self, asset_name: Option<&str>, amount: f64) -> Result<(), String> {
+        if !(amount >= 0.0 && amount.is_finite()) {
+            return Err(format!(
+                "invalid amount {}; expected a non-negative amount",
+                amount
+            ));
+        }
+
+        match asset_name {
+            Some(asset_name) => {
+                self.amounts.insert(asset_name.trim().to_string(), amount);
+            }
+            None => self.default = Some(amount),
+        }
+
+        Ok(())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.default.is_none() && self.amounts.is_empty()
+    }
+
+    pub fn of(&self, asset_name: &str) -> Option<f64> {
+        self.amounts.get(asset_name).cloned().or(self.default)
+    }
+}
+
+// Costs of trading, in the base currency: a commission per trade, which brokers often charge on sells but not on buys,
+// a fee per share, and the bid/ask spread, as a percentage of the amount traded.
+#[derive(Default)]
+pub struct TradeCosts {
+    pub buy_commission: AssetAmounts,
+    pub sell_commission: AssetAmounts,
+    pub share_fee: AssetAmounts,
+    pub spread: AssetAmounts,
+}
+
+impl TradeCosts {
+    pub fn is_empty(&self) -> bool {
+        self.buy_commission.is_empty()
+            && self.sell_commission.is_empty()
+            && self.share_fee.is_empty()
+            && self.spread.is_empty()
+    }
+}
+
+// Costs of trading an asset; the spread is a fraction of the amount traded.
+#[derive(Clone, Debug)]
+struct TradeCost {
+    buy_commission: BigRational,
+    sell_commission: BigRational,
+    share_fee: BigRational,
+    spread: BigRational,
+}
+
+impl TradeCost {
+    // Cost of a trade. The fee per share only applies when the unit price is known.
+    fn of(&self, delta: &BigRational, unit_price: Option<&BigRational>) -> BigRational {
+        if delta.is_zero() {
+            return BigRational::zero();
+        }
+
+        let commission = if delta.is_positive() {
+            &self.buy_commission
+        } else {
+            &self.sell_commission
+        };
+
+        let share_fee = match unit_price {
+            Some(unit_price) => &self.share_fee * delta.abs() / unit_price,
+            None => BigRational::zero(),
+        };
+
+        commission + share_fee + delta.abs() * &self.spread
+    }
+}
+
+// When a trade is not worth placing, the fraction of the way it gets there: of the minimum trade size, or of its cost,
+// for the improvement it buys in how far the asset is from its target value.
+fn trade_shortfall(
+    value: &BigRational,
+    target_value: Option<&BigRational>,
+    delta: Option<&BigRational>,
+    min_trade: Option<&BigRational>,
+    cost: &BigRational,
+) -> Option<BigRational> {
+    let delta = match delta {
+        Some(delta) if !delta.is_zero() => delta,
+        _ => return None,
+    };
+
+    let mut shortfalls = vec![];
+
+    if let Some(min_trade) = min_trade {
+        if delta.abs() < *min_trade {
+            shortfalls.push(delta.abs() / min_trade);
+        }
+    }
+
+    if let (true, Some(target_value)) = (cost.is_positive(), target_value) {
+        let improvement = (value - target_value).abs() - (value + delta - target_value).abs();
+
+        if improvement <= *cost {
+            shortfalls.push(improvement.max(BigRational::zero()) / cost);
+        }
+    }
+
+    shortfalls.into_iter().min()
+}
+
+impl UntargetedMode {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.trim().to_lowercase().as_str() {
+            "ignore" => Some(UntargetedMode::Ignore),
+            "hold" => Some(UntargetedMode::Hold),
+            "liquidate" => Some(UntargetedMode::Liquidate),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone)]
 pub struct Asset {
     name: String,
     value: BigRational,
     actual_allocation: BigRational,
     target_allocation_percent: BigRational,
 
-    target_value: Option<BigRational>,
-    deviation: Option<BigRational>,
-    delta: Option<BigRational>,
-}
+    // Number of units (e.g. shares) held, and the price of each unit.
+    quantity: Option<BigRational>,
+    unit_price: Option<BigRational>,
+
+    // Accounts the asset is held in, if the portfolio file reports them.
+    accounts: Vec<String>,
+
+    // Ticker to buy or sell when the asset is an asset class held through several tickers.
+    ticker: Option<String>,
+
+    // Currency the asset is traded in, and the value of one unit of it in the base currency, when it is not the base
+    // currency. The value and unit price are in the base currency.
+    currency: Option<(String, BigRational)>,
+
+    // How the asset is rebalanced when it has no target allocation.
+    untargeted: Option<UntargetedMode>,
+
+    // Amount of cash to keep, when the asset is uninvested cash that funds the contribution.
+    cash_reserve: Option<BigRational>,
+
+    // Smallest amount worth buying or selling; smaller trades are left out.
+    min_trade: Option<BigRational>,
+
+    // Costs of buying or selling the asset.
+    costs: Option<TradeCost>,
+
+    // Whether the trade of the asset was left out, as smaller than its minimum or costing more than it is worth.
+    left_out: bool,
+
+    // Half-width of the tolerance band of the asset, as a fraction of the portfolio, when rebalancing by bands.
+    band: Option<BigRational>,
+    // Difference between the allocation of the asset and its target before the rebalance, as a fraction of the
+    // targeted assets, when rebalancing by bands: its fractional deviation times its target allocation.
+    drift: Option<BigRational>,
+
+    target_value: Option<BigRational>,
+    // Relative deviation from the target value: value / target_value - 1.
+    deviation: Option<BigRational>,
+    delta: Option<BigRational>,
+}
+
+impl Asset {
+    // The target allocation is a fraction of the portfolio, e.g. 2/5 for 40%.
+    pub fn new(
+        name: String,
+        target_allocation: BigRational,
+        value: f64,
+    ) -> Result<Self, RebalanceError> {
+        if target_allocation.is_negative() || target_allocation > BigRational::one() {
+            return Err(RebalanceError::InvalidAllocation {
+                asset_name: name,
+                allocation: to_f64(&target_allocation) * 100.0,
+            });
+        }
+
+        let value = to_rational(value, &format!("value of \"{}\"", name))?;
+
+        Ok(Asset {
+            name,
+
+            value,
+            actual_allocation: BigRational::zero(),
+            target_allocation_percent: target_allocation,
+
+            quantity: None,
+            unit_price: None,
+
+            accounts: vec![],
+
+            ticker: None,
+
+            currency: None,
+
+            untargeted: None,
+
+            cash_reserve: None,
+
+            min_trade: None,
+
+            costs: None,
+
+            left_out: false,
+
+            band: None,
+            drift: None,
+
+            target_value: None,
+            deviation: None,
+            delta: None,
+        })
+    }
+
+    pub fn with_units(mut self, quantity: f64, unit_price: f64) -> Result<Self, RebalanceError> {
+        if !(unit_price > 0.0 && unit_price.is_finite()) {
+            return Err(RebalanceError::Invalid(format!(
+                "invalid unit price {} of \"{}\"; expected a positive price",
+                unit_price, self.name
+            )));
+        }
+
+        self.quantity = Some(to_rational(
+            quantity,
+            &format!("quantity of \"{}\"", self.name),
+        )?);
+        self.unit_price = Some(BigRational::from_f64(unit_price).unwrap());
+        Ok(self)
+    }
+
+    pub fn with_account(mut self, account: String) -> Self {
+        self.accounts.push(account);
+        self
+    }
+
+    pub fn with_ticker(mut self, ticker: String) -> Self {
+        self.ticker = Some(ticker);
+        self
+    }
+
+    pub fn with_untargeted(mut self, mode: UntargetedMode) -> Self {
+        self.untargeted = Some(mode);
+        self
+    }
+
+    pub fn with_cash_reserve(mut self, cash_reserve: f64) -> Result<Self, RebalanceError> {
+        if !(cash_reserve >= 0.0 && cash_reserve.is_finite()) {
+            return Err(RebalanceError::Invalid(format!(
+                "invalid cash reserve {} of \"{}\"; expected a non-negative amount",
+                cash_reserve, self.name
+            )));
+        }
+
+        self.cash_reserve = Some(BigRational::from_f64(cash_reserve).unwrap());
+        Ok(self)
+    }
+
+    pub fn with_min_trade(mut self, min_trade: f64) -> Result<Self, RebalanceError> {
+        if !(min_trade >= 0.0 && min_trade.is_finite()) {
+            return Err(RebalanceError::Invalid(format!(
+                "invalid minimum trade {} of \"{}\"; expected a non-negative amount",
+                min_trade, self.name
+            )));
+        }
+
+        self.min_trade = Some(BigRational::from_f64(min_trade).unwrap());
+        Ok(self)
+    }
+
+    // Costs of trading the asset, as set for it or for every asset.
+    pub fn with_costs(mut self, costs: &TradeCosts) -> Self {
+        if costs.is_empty() {
+            return self;
+        }
+
+        let cost = |amounts: &AssetAmounts| {
+            BigRational::from_f64(amounts.of(&self.name).unwrap_or(0.0)).unwrap()
+        };
+
+        self.costs = Some(TradeCost {
+            buy_commission: cost(&costs.buy_commission),
+            sell_commission: cost(&costs.sell_commission),
+            share_fee: cost(&costs.share_fee),
+            spread: cost(&costs.spread) / BigRational::from_integer(100.into()),
+        });
+        self
+    }
+
+    // Cost of the trade of the asset.
+    fn trade_cost(&self) -> BigRational {
+        match (&self.costs, &self.delta) {
+            (Some(costs), Some(delta)) => costs.of(delta, self.unit_price.as_ref()),
+            _ => BigRational::zero(),
+        }
+    }
+
+    // When the trade of the asset is not worth placing, how far it falls short; see `trade_shortfall`.
+    fn trade_shortfall(&self) -> Option<BigRational> {
+        trade_shortfall(
+            &self.value,
+            self.target_value.as_ref(),
+            self.delta.as_ref(),
+            self.min_trade.as_ref(),
+            &self.trade_cost(),
+        )
+    }
+
+    // Drift relative to the target allocation, e.g. 1/4 for an asset at 12.5% of the portfolio for a target of 10%.
+    fn relative_drift(&self) -> Option<BigRational> {
+        self.drift
+            .as_ref()
+            .map(|drift| drift / &self.target_allocation_percent)
+    }
+
+    // Whether the asset drifted beyond its tolerance band.
+    fn is_out_of_band(&self) -> bool {
+        match (&self.band, &self.drift) {
+            (Some(band), Some(drift)) => drift.abs() > *band,
+            _ => false,
+        }
+    }
+
+    // Whether the asset has a target allocation to rebalance towards.
+    fn has_target(&self) -> bool {
+        self.untargeted.is_none() && self.cash_reserve.is_none()
+    }
+
+    // Cash available to invest, above the reserve, if the asset is cash.
+    fn cash_to_invest(&self) -> Option<BigRational> {
+        match self.cash_reserve {
+            Some(ref cash_reserve) if self.value > *cash_reserve => {
+                Some(&self.value - cash_reserve)
+            }
+            Some(_) => Some(BigRational::zero()),
+            None => None,
+        }
+    }
+
+    pub fn with_currency(mut self, currency: String, rate: f64) -> Result<Self, RebalanceError> {
+        if !(rate > 0.0 && rate.is_finite()) {
+            return Err(RebalanceError::Invalid(format!(
+                "invalid exchange rate {} from {} for \"{}\"; expected a positive rate",
+                rate, currency, self.name
+            )));
+        }
+
+        self.currency = Some((currency, BigRational::from_f64(rate).unwrap()));
+        Ok(self)
+    }
+
+    // Combines another holding of the same asset into this one; for example, the same fund held in two accounts.
+    pub fn merge(&mut self, other: Asset) {
+        self.value += other.value;
+
+        let units = match (
+            self.quantity.take(),
+            self.unit_price.take(),
+            other.quantity,
+            other.unit_price,
+        ) {
+            (Some(quantity), Some(unit_price), Some(other_quantity), Some(other_unit_price)) => {
+                let total_quantity = &quantity + &other_quantity;
+
+                if total_quantity.is_zero() {
+                    None
+                } else {
+                    // Average the unit prices, weighted by quantity.
+                    let unit_price = (quantity * unit_price + other_quantity * other_unit_price)
+                        / &total_quantity;
+                    Some((total_quantity, unit_price))
+                }
+            }
+            _ => None,
+        };
+
+        if let Some((quantity, unit_price)) = units {
+            self.quantity = Some(quantity);
+            self.unit_price = Some(unit_price);
+        }
+
+        for account in other.accounts {
+            if !self.accounts.contains(&account) {
+                self.accounts.push(account);
+            }
+        }
+
+        if self.currency.is_none() {
+            self.currency = other.currency;
+        }
+    }
+}
+
+// Read by the tests of the app, which check the rebalanced assets value by value.
+#[cfg(test)]
+impl Asset {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn value(&self) -> f64 {
+        to_f64(&self.value)
+    }
+
+    pub fn delta(&self) -> f64 {
+        self.delta.as_ref().map_or(0.0, to_f64)
+    }
+
+    // Units to buy or sell, when the unit price is known.
+    pub fn units(&self) -> Option<f64> {
+        self.unit_price
+            .as_ref()
+            .map(|unit_price| self.delta() / to_f64(unit_price))
+    }
+
+    // Amount to buy or sell in the currency the asset is traded in, when it is not the base currency.
+    pub fn native_delta(&self) -> Option<(f64, &str)> {
+        self.currency
+            .as_ref()
+            .map(|(currency, rate)| (self.delta() / to_f64(rate), currency.as_str()))
+    }
+
+    pub fn accounts(&self) -> &[String] {
+        &self.accounts
+    }
+
+    pub fn ticker(&self) -> Option<&str> {
+        self.ticker.as_deref()
+    }
+
+    pub fn cost(&self) -> f64 {
+        to_f64(&self.trade_cost())
+    }
+
+    pub fn drift(&self) -> Option<f64> {
+        self.drift.as_ref().map(to_f64)
+    }
+
+    pub fn is_left_out(&self) -> bool {
+        self.left_out
+    }
+}
+
+fn comparator(left: &Asset, right: &Asset) -> Ordering {
+    if left.deviation < right.deviation {
+        return Ordering::Less;
+    }
+
+    if left.deviation > right.deviation {
+        return Ordering::Greater;
+    }
+
+    Ordering::Equal
+}
+
+// Splits the assets into the targeted assets, with their target value, and the others, with the trades that fund the
+// contribution. Also returns the amount to contribute to the targeted assets.
+fn prepare_rebalance(
+    amount_to_contribute: f64,
+    assets: Vec<Asset>,
+) -> Result<(Vec<Asset>, Vec<Asset>, BigRational), RebalanceError> {
+    let (mut assets, mut untargeted_assets): (Vec<Asset>, Vec<Asset>) =
+        assets.into_iter().partition(|asset| asset.has_target());
+
+    if assets.is_empty() {
+        return Err(RebalanceError::EmptyPortfolio);
+    }
+
+    let amount_to_contribute = to_rational(amount_to_contribute, "contribution")?;
+
+    let portfolio_total: BigRational = assets
+        .iter()
+        .chain(untargeted_assets.iter())
+        .fold(BigRational::zero(), |total, asset| total + &asset.value);
+
+    // Assets without a target are not traded, or are sold to fund the targeted assets: untargeted assets in full, and
+    // cash down to its reserve.
+    for asset in untargeted_assets.iter_mut() {
+        asset.actual_allocation = if portfolio_total <= BigRational::zero() {
+            BigRational::zero()
+        } else {
+            &asset.value / &portfolio_total
+        };
+
+        asset.delta = match (asset.cash_to_invest(), asset.untargeted) {
+            (Some(cash_to_invest), _) => Some(-cash_to_invest),
+            (None, Some(UntargetedMode::Liquidate)) => Some(-asset.value.clone()),
+            _ => Some(BigRational::zero()),
+        };
+    }
+
+    let amount_to_contribute =
+        untargeted_assets
+            .iter()
+            .fold(amount_to_contribute, |amount, asset| match asset.delta {
+                Some(ref delta) => amount - delta,
+                None => amount,
+            });
+
+    // Targets apply to what the targeted assets are worth after the contribution, leaving out what stays in the
+    // other assets.
+    let total: BigRational = assets
+        .iter()
+        .fold(amount_to_contribute.clone(), |total, asset| {
+            total + &asset.value
+        });
+
+    if total.is_zero() {
+        return Err(RebalanceError::DivisionByZero(
+            "the portfolio value after the contribution".to_string(),
+        ));
+    }
+
+    for asset in assets.iter_mut() {
+        let target_value = &total * &asset.target_allocation_percent;
+
+        if target_value.is_zero() {
+            return Err(RebalanceError::DivisionByZero(format!(
+                "the target value of \"{}\"",
+                asset.name
+            )));
+        }
+
+        // equivalent to: (value - target_value) / target_value
+        // see: https://en.wikipedia.org/wiki/Approximation_error#Formal_Definition
+        //
+        // This will be negative for underweighted assets and positive for overweighted assets.
+        let deviation = (&asset.value / &target_value) - BigRational::one();
+
+        asset.actual_allocation = if portfolio_total <= BigRational::zero() {
+            BigRational::zero()
+        } else {
+            &asset.value / &portfolio_total
+        };
+
+        asset.target_value = Some(target_value);
+        asset.deviation = Some(deviation);
+    }
+
+    Ok((assets, untargeted_assets, amount_to_contribute))
+}
+
+pub fn lazy_rebalance(
+    amount_to_contribute: f64,
+    assets: Vec<Asset>,
+) -> Result<Vec<Asset>, RebalanceError> {
+    let (assets, mut untargeted_assets, amount_to_contribute) =
+        prepare_rebalance(amount_to_contribute, assets)?;
+
+    let portfolio_total: BigRational = assets
+        .iter()
+        .chain(untargeted_assets.iter())
+        .fold(BigRational::zero(), |total, asset| total + &asset.value);
+
+    let mut assets = convert_new_portfolio(new_lazy_rebalance(
+        to_f64(&amount_to_contribute),
+        convert_old_portfolio(assets),
+    )?);
+
+    // Allocations are of the whole portfolio, including the assets without a target.
+    for asset in assets.iter_mut() {
+        asset.actual_allocation = if portfolio_total <= BigRational::zero() {
+            BigRational::zero()
+        } else {
+            &asset.value / &portfolio_total
+        };
+    }
+
+    // When every trade is too small, nothing is bought or sold, and the contribution stays in cash.
+    if has_left_out_trades(&assets) && !has_trades(&assets) {
+        keep_in_cash(&amount_to_contribute, &mut untargeted_assets);
+    }
+
+    assets.append(&mut untargeted_assets);
+
+    Ok(assets)
+}
+
+// Buys and sells every targeted asset to its exact target value. The contribution may be zero, to only rebalance.
+pub fn full_rebalance(
+    amount_to_contribute: f64,
+    assets: Vec<Asset>,
+) -> Result<Vec<Asset>, RebalanceError> {
+    let (mut assets, mut untargeted_assets, _) = prepare_rebalance(amount_to_contribute, assets)?;
+
+    for asset in assets.iter_mut() {
+        asset.delta = Some(asset.target_value.as_ref().unwrap() - &asset.value);
+    }
+
+    let all_assets: Vec<usize> = (0..assets.len()).collect();
+    let amount_left_out = leave_out_trades(&mut assets, &all_assets);
+    keep_in_cash(&amount_left_out, &mut untargeted_assets);
+
+    assets.sort_by(comparator);
+
+    assets.append(&mut untargeted_assets);
+
+    Ok(assets)
+}
+
+// Brings every targeted asset within the tolerance of its target allocation, in percentage points, while selling as
+// little as possible:
+//
+// 1. Assets above their band are sold down to its upper edge; no contribution can bring them back within it.
+// 2. Assets below their band are bought up to its lower edge, with the contribution and the proceeds of the sales.
+//    When that is not enough, the assets furthest above their target are sold, as `contribute` would take a
+//    withdrawal from them.
+// 3. Whatever is left of the contribution is invested as `lazy_rebalance` would.
+pub fn hybrid_rebalance(
+    amount_to_contribute: f64,
+    assets: Vec<Asset>,
+    tolerance: f64,
+) -> Result<Vec<Asset>, RebalanceError> {
+    let (mut assets, mut untargeted_assets, amount_to_contribute) =
+        prepare_rebalance(amount_to_contribute, assets)?;
+
+    let tolerance = to_rational(tolerance, "tolerance")? / BigRational::from_integer(100.into());
+
+    let total: BigRational = assets
+        .iter()
+        .fold(amount_to_contribute.clone(), |total, asset| {
+            total + &asset.value
+        });
+
+    let mut cash = amount_to_contribute;
+
+    for asset in assets.iter_mut() {
+        let upper_edge = (&asset.target_allocation_percent + &tolerance) * &total;
+
+        if asset.value > upper_edge {
+            cash += &asset.value - &upper_edge;
+            asset.delta = Some(upper_edge - &asset.value);
+        }
+    }
+
+    let needs: Vec<BigRational> = assets
+        .iter()
+        .map(|asset| {
+            let lower_edge = (&asset.target_allocation_percent - &tolerance) * &total;
+
+            if lower_edge > asset.value {
+                lower_edge - &asset.value
+            } else {
+                BigRational::zero()
+            }
+        })
+        .collect();
+
+    let total_need = needs
+        .iter()
+        .fold(BigRational::zero(), |total, need| total + need);
+
+    if cash < total_need {
+        let assets_in_band: Vec<usize> = (0..assets.len())
+            .filter(|&index| needs[index].is_zero())
+            .collect();
+
+        contribute_more(&(&cash - &total_need), &mut assets, &assets_in_band);
+
+        cash = total_need.clone();
+    }
+
+    for (asset, need) in assets.iter_mut().zip(needs) {
+        if need.is_positive() {
+            asset.delta = Some(asset.delta.take().unwrap_or_else(BigRational::zero) + need);
+        }
+    }
+
+    let all_assets: Vec<usize> = (0..assets.len()).collect();
+
+    contribute_more(&(cash - total_need), &mut assets, &all_assets);
+
+    let amount_left_out = leave_out_trades(&mut assets, &all_assets);
+    keep_in_cash(&amount_left_out, &mut untargeted_assets);
+
+    assets.sort_by(comparator);
+
+    assets.append(&mut untargeted_assets);
+
+    Ok(assets)
+}
+
+// Trades the assets that drifted beyond their tolerance band back to the edge of the band or to their target, and
+// leaves the assets within their band alone. Drift is measured on the portfolio as it is, before the contribution.
+//
+// Purchases are funded by the contribution and the sales of the assets above their band only: when those fall short,
+// every purchase is scaled down in proportion. What is left of the contribution is invested as `lazy_rebalance` would,
+// and a withdrawal larger than the purchases is taken from the assets as it would be.
+pub fn bands_rebalance(
+    amount_to_contribute: f64,
+    assets: Vec<Asset>,
+    bands: &ToleranceBands,
+) -> Result<Vec<Asset>, RebalanceError> {
+    let (mut assets, mut untargeted_assets, amount_to_contribute) =
+        prepare_rebalance(amount_to_contribute, assets)?;
+
+    let current_total: BigRational = assets
+        .iter()
+        .fold(BigRational::zero(), |total, asset| total + &asset.value);
+
+    let mut cash = amount_to_contribute;
+    let mut assets_out_of_band = vec![];
+
+    // Drift is measured before the contribution.
+    let current_drifts = drifts(assets.clone(), &current_total)?;
+
+    for ((index, asset), drift) in assets.iter_mut().enumerate().zip(current_drifts) {
+        let band = bands.width(asset);
+
+        asset.band = Some(band.clone());
+        asset.drift = Some(drift.clone());
+
+        if !asset.is_out_of_band() {
+            continue;
+        }
+
+        let target_value = asset.target_value.clone().unwrap();
+
+        let goal = match bands.trade_to {
+            BandTarget::Target => target_value,
+            BandTarget::Edge => {
+                // The band in the value of the portfolio, on the side the asset drifted to.
+                let edge = band * &target_value / &asset.target_allocation_percent;
+
+                if drift.is_positive() {
+                    target_value + edge
+                } else {
+                    (target_value - edge).max(BigRational::zero())
+                }
+            }
+        };
+
+        let delta = goal - &asset.value;
+
+        cash -= &delta;
+        asset.delta = Some(delta);
+
+        assets_out_of_band.push(index);
+    }
+
+    let purchases = assets_out_of_band
+        .iter()
+        .filter_map(|&index| assets[index].delta.as_ref())
+        .filter(|delta| delta.is_positive())
+        .fold(BigRational::zero(), |total, delta| total + delta);
+
+    if cash.is_negative() && purchases.is_positive() {
+        let shortfall = (-&cash).min(purchases.clone());
+        let funded = (&purchases - &shortfall) / &purchases;
+
+        for &index in assets_out_of_band.iter() {
+            let asset = &mut assets[index];
+
+            if let Some(delta) = asset.delta.take() {
+                asset.delta = Some(if delta.is_positive() {
+                    delta * &funded
+                } else {
+                    delta
+                });
+            }
+        }
+
+        cash += shortfall;
+    }
+
+    let all_assets: Vec<usize> = (0..assets.len()).collect();
+
+    contribute_more(&cash, &mut assets, &all_assets);
+
+    // What a trade left out would have traded goes to the other trades, leaving the assets within their band alone.
+    let traded_assets: Vec<usize> = (0..assets.len())
+        .filter(|&index| assets[index].delta.is_some())
+        .collect();
+
+    let amount_left_out = leave_out_trades(&mut assets, &traded_assets);
+    keep_in_cash(&amount_left_out, &mut untargeted_assets);
+
+    assets.sort_by(comparator);
+
+    assets.append(&mut untargeted_assets);
+
+    Ok(assets)
+}
+
+// Drift of each asset from its target allocation, in a portfolio of the assets worth `total`: its fractional deviation
+// from its target value, times its target allocation.
+fn drifts(assets: Vec<Asset>, total: &BigRational) -> Result<Vec<BigRational>, RebalanceError> {
+    let mut assets = convert_old_portfolio(assets);
+
+    if total.is_positive() {
+        set_target_values(total, total, &mut assets)?;
+    } else {
+        // An empty portfolio holds none of its targets.
+        for portfolio_asset in assets.iter_mut() {
+            portfolio_asset.fractional_deviation = Some(-BigRational::one());
+        }
+    }
+
+    Ok(assets
+        .into_iter()
+        .map(|portfolio_asset| {
+            portfolio_asset.fractional_deviation.unwrap()
+                * &portfolio_asset.asset.target_allocation_percent
+        })
+        .collect())
+}
+
+// Leaves out the trades of some of the assets that are not worth placing, as `lazy_rebalance` does: the trade furthest
+// from it goes first, and what it would have bought or sold is distributed among the others, on top of their trades,
+// until every trade is worth placing. Returns what is left once every trade is left out, which no asset trades.
+fn leave_out_trades(assets: &mut [Asset], indices: &[usize]) -> BigRational {
+    let mut indices = indices.to_vec();
+
+    loop {
+        let smallest_trade = indices
+            .iter()
+            .enumerate()
+            .filter_map(|(position, &index)| {
+                assets[index]
+                    .trade_shortfall()
+                    .map(|shortfall| (position, shortfall))
+            })
+            .min_by(|left, right| left.1.cmp(&right.1))
+            .map(|(position, _)| position);
+
+        let position = match smallest_trade {
+            Some(position) => position,
+            None => return BigRational::zero(),
+        };
+
+        let index = indices.remove(position);
+
+        let asset = &mut assets[index];
+        let delta = asset.delta.take().unwrap_or_else(BigRational::zero);
+        asset.left_out = true;
+
+        if indices.is_empty() {
+            return delta;
+        }
+
+        contribute_more(&delta, assets, &indices);
+    }
+}
+
+// Keeps an amount that is not traded in the cash holding, if there is one.
+fn keep_in_cash(amount: &BigRational, untargeted_assets: &mut [Asset]) {
+    if amount.is_zero() {
+        return;
+    }
+
+    if let Some(cash) = untargeted_assets
+        .iter_mut()
+        .find(|asset| asset.cash_reserve.is_some())
+    {
+        cash.delta = Some(cash.delta.take().unwrap_or_else(BigRational::zero) + amount);
+    }
+}
+
+// Distributes an amount among some of the assets, as `distribute_contribution` does, on top of the trades they already
+// have.
+fn contribute_more(amount_to_contribute: &BigRational, assets: &mut [Asset], indices: &[usize]) {
+    if amount_to_contribute.is_zero() || indices.is_empty() {
+        return;
+    }
+
+    // The assets as they are after their trades.
+    let traded_assets: Vec<Asset> = indices
+        .iter()
+        .map(|&index| {
+            let mut asset = assets[index].clone();
+
+            asset.value = &asset.value + asset.delta.take().unwrap_or_else(BigRational::zero);
+            asset.deviation =
+                Some(&asset.value / asset.target_value.as_ref().unwrap() - BigRational::one());
 
-impl Asset {
-    pub fn new(name: String, target_percent: f64, value: f64) -> Self {
-        assert!(target_percent <= 1.0);
-        assert!(target_percent >= 0.0);
+            asset
+        })
+        .collect();
 
-        Asset {
-            name: name,
+    let mut traded_assets: Vec<(usize, PortfolioAsset)> = indices
+        .iter()
+        .cloned()
+        .zip(convert_old_portfolio(traded_assets))
+        .collect();
 
-            value: BigRational::from_f64(value).unwrap(),
-            actual_allocation: BigRational::zero(),
-            target_allocation_percent: BigRational::from_f64(target_percent).unwrap(),
+    traded_assets.sort_by(|left, right| {
+        let result = asset_comparator(&left.1, &right.1);
 
-            target_value: None,
-            deviation: None,
-            delta: None,
+        if *amount_to_contribute < BigRational::zero() {
+            result.reverse()
+        } else {
+            result
+        }
+    });
+
+    let (indices, mut traded_assets): (Vec<usize>, Vec<PortfolioAsset>) =
+        traded_assets.into_iter().unzip();
+
+    distribute_contribution(amount_to_contribute, &mut traded_assets);
+
+    for (index, traded_asset) in indices.into_iter().zip(traded_assets) {
+        if let Some(contribution) = traded_asset.contribution {
+            let asset = &mut assets[index];
+
+            asset.delta = Some(asset.delta.take().unwrap_or_else(BigRational::zero) + contribution);
         }
     }
 }
 
-fn comparator(left: &Asset, right: &Asset) -> Ordering {
-    if left.deviation < right.deviation {
-        return Ordering::Less;
-    }
+// Most times the contribution is rebalanced net of the costs of the trades, and the change in costs small enough to
+// stop at.
+const MAX_COST_ROUNDS: usize = 20;
+const COST_TOLERANCE: f64 = 1e-6;
 
-    if left.deviation > right.deviation {
-        return Ordering::Greater;
+// Rebalances the contribution net of the costs of the trades it leads to. Costs depend on the trades, so the
+// contribution is rebalanced again, net of the costs of the previous trades, until the costs settle; it is an error
+// when they have not settled after `MAX_COST_ROUNDS` rounds.
+pub fn rebalance_net_of_costs(
+    amount_to_contribute: f64,
+    assets: Vec<Asset>,
+    options: &RebalanceOptions,
+) -> Result<Vec<Asset>, RebalanceError> {
+    let mut costs = 0.0;
+    let mut change = 0.0;
+
+    for _ in 1..=MAX_COST_ROUNDS {
+        let amount = amount_to_contribute - costs;
+
+        let balanced_portfolio = match options.mode {
+            RebalanceMode::Lazy => lazy_rebalance(amount, assets.clone())?,
+            RebalanceMode::Full => full_rebalance(amount, assets.clone())?,
+            RebalanceMode::Hybrid => hybrid_rebalance(amount, assets.clone(), options.tolerance)?,
+            RebalanceMode::Bands => bands_rebalance(amount, assets.clone(), &options.bands)?,
+        };
+
+        let new_costs = to_f64(&total_cost(&balanced_portfolio));
+        change = (new_costs - costs).abs();
+
+        if change < COST_TOLERANCE {
+            return Ok(balanced_portfolio);
+        }
+
+        costs = new_costs;
     }
 
-    Ordering::Equal
+    Err(RebalanceError::CostsNotSettled {
+        rounds: MAX_COST_ROUNDS,
+        change,
+    })
 }
 
-pub fn lazy_rebalance(amount_to_contribute: f64, mut assets: Vec<Asset>) -> Vec<Asset> {
-    let amount_to_contribute = BigRational::from_f64(amount_to_contribute).unwrap();
+fn total_cost(assets: &[Asset]) -> BigRational {
+    assets.iter().fold(BigRational::zero(), |total, asset| {
+        total + asset.trade_cost()
+    })
+}
 
-    let portfolio_total: BigRational = assets
-        .iter()
-        .fold(BigRational::zero(), |total, ref asset| total + &asset.value);
+// Rounds the trades of the targeted assets that have a unit price to whole units (e.g. shares), and returns the cash
+// left over. Together, the rounded trades, with their costs, never spend more, or raise less, than the exact trades.
+//
+// Each trade is rounded to the whole number of units on either side of it, or, when neither is worth placing, as
+// smaller than the minimum trade size of the asset or costing more than it brings it closer to its target, left out.
+// Sells are rounded toward zero, so that no more units are sold than asked; when that leaves the buys short of cash,
+// they may give up units too, and only when there are no buys left to give up is one more unit sold. Of all the ways
+// to round the trades that fit, the one with the smallest sum of the absolute fractional deviations of the assets is
+// taken. Any left over cash is kept in the cash holding, if there is one.
+pub fn round_to_whole_units(balanced_portfolio: &mut [Asset]) -> f64 {
+    // What the exact trades of the rounded assets spend, with their costs.
+    let mut budget = BigRational::zero();
+
+    // Index of each rounded asset, with its unit price, its trade in whole units toward zero, and one unit further.
+    let mut rounded = vec![];
+
+    // What the trades rounded toward zero spend, with their costs.
+    let mut toward_zero_spend = BigRational::zero();
+
+    for (index, asset) in balanced_portfolio.iter().enumerate() {
+        if !asset.has_target() || asset.target_value.is_none() {
+            continue;
+        }
 
-    let total: BigRational = &portfolio_total + &amount_to_contribute;
+        let (unit_price, delta) = match (&asset.unit_price, &asset.delta) {
+            (Some(unit_price), Some(delta)) => (unit_price, delta),
+            _ => continue,
+        };
 
-    for asset in assets.iter_mut() {
-        let target_value = &total * &asset.target_allocation_percent;
+        budget += delta + asset.trade_cost();
 
-        // equivalent to: (value - target_value) / target_value
-        // see: https://en.wikipedia.org/wiki/Approximation_error#Formal_Definition
-        //
-        // This will be negative for underweighted assets and positive for overweighted assets.
-        let deviation = (&asset.value / &target_value) - BigRational::one();
+        let units = (delta / unit_price).trunc();
+        let toward_zero = &units * unit_price;
 
-        asset.actual_allocation = if portfolio_total <= BigRational::zero() {
-            BigRational::zero()
+        toward_zero_spend += &toward_zero;
+
+        if let Some(ref costs) = asset.costs {
+            toward_zero_spend += costs.of(&toward_zero, Some(unit_price));
+        }
+
+        let further = if toward_zero == *delta {
+            None
         } else {
-            &asset.value / &portfolio_total
+            Some((units + delta.signum()) * unit_price)
         };
 
-        asset.target_value = Some(target_value);
-        asset.deviation = Some(deviation);
+        rounded.push((index, unit_price.clone(), toward_zero, further));
     }
 
-    assets.sort_by(|left, right| {
-        let result = comparator(left, right);
+    // How much the trades rounded toward zero overspend, which the buys make up for by giving up units.
+    let shortfall = (toward_zero_spend - &budget).max(BigRational::zero());
 
-        if amount_to_contribute < BigRational::zero() {
-            result.reverse()
-        } else {
-            result
-        }
-    });
+    // The trades each rounded asset may make, with one unit more sold only when `sell_more` is true.
+    let choices = |sell_more: bool| -> Vec<Vec<UnitTrade>> {
+        rounded
+            .iter()
+            .map(|&(index, ref unit_price, ref toward_zero, ref further)| {
+                let asset = &balanced_portfolio[index];
 
-    let (largest_least_deviation, index_to_stop): (BigRational, usize) = {
-        // since deviations are approx. errors, original author wanted to 'minimize' approx. errors
-        // of assets with 'lowest' approx. errors. in other words, assets with lowest approx. error
-        // gets first dibs of the contribution first.
+                let mut deltas = vec![toward_zero.clone()];
 
-        let mut cumulative_target_value: BigRational = BigRational::zero();
+                if let Some(further) = further {
+                    if further.is_positive() || sell_more {
+                        deltas.push(further.clone());
+                    }
+                }
 
-        println!(
-            "amount_to_contribute: {}",
-            to_f64(&amount_to_contribute.clone())
-        );
+                if toward_zero.is_positive() {
+                    let units_to_give_up = (&shortfall / unit_price)
+                        .ceil()
+                        .min(toward_zero / unit_price);
 
-        let mut amount_left_to_contribute: BigRational = amount_to_contribute.clone();
-        // TODO: wtf is this
-        // Idea:
-        // Add money to the asset(s) with least fractional deviation until they are tied with the asset(s) with the
-        // next-least fractional deviation.
-        //
-        // When contributing to assets with lowest fractional deviation to the largest fractional deviation, we may be
-        // unable to contribute to all the assets in the portfolio.
-        //
-        // The last asset we contribute to will be compared to `largest_least_deviation`.
-        let mut largest_least_deviation: BigRational = BigRational::zero();
+                    let mut fewer = toward_zero.clone();
 
-        let mut last_known_index = None;
+                    for _ in 0..units_to_give_up.to_integer().to_usize().unwrap() {
+                        fewer -= unit_price;
+                        deltas.push(fewer.clone());
+                    }
+                }
 
-        for (index, asset) in assets.iter().enumerate() {
-            println!();
-            println!(
-                "amount_left_to_contribute: {}",
-                to_f64(&amount_left_to_contribute)
-            );
-            if amount_left_to_contribute.abs() <= BigRational::zero() {
-                break;
-            }
+                let trades: Vec<UnitTrade> = deltas
+                    .iter()
+                    .filter_map(|delta| UnitTrade::of(asset, delta))
+                    .collect();
 
-            let asset: &Asset = asset;
-            last_known_index = Some(index);
+                if trades.len() == deltas.len() {
+                    return trades;
+                }
 
-            let deviation = asset.deviation.as_ref().unwrap().clone();
+                let mut trades: Vec<UnitTrade> = trades
+                    .into_iter()
+                    .filter(|trade| !trade.delta.is_zero())
+                    .collect();
 
-            let target_value = asset.target_value.as_ref().unwrap();
-            cumulative_target_value = &cumulative_target_value + target_value;
+                let mut left_out = UnitTrade::of(asset, &BigRational::zero()).unwrap();
+                left_out.left_out = true;
+                trades.push(left_out);
 
-            let next_least_deviation = if index >= (assets.len() - 1) {
-                BigRational::zero()
-            } else {
-                assets[index + 1].deviation.as_ref().unwrap().clone()
-            };
+                trades
+            })
+            .collect()
+    };
 
-            let contribution: BigRational =
-                &cumulative_target_value * (&next_least_deviation - &deviation);
+    let least_spend = |choices: Vec<Vec<UnitTrade>>| -> Vec<UnitTrade> {
+        choices
+            .into_iter()
+            .map(|trades| {
+                trades
+                    .into_iter()
+                    .min_by(|left, right| left.spend.cmp(&right.spend))
+                    .unwrap()
+            })
+            .collect()
+    };
 
-            // TODO: todo-note
-            println!(
-                "cumulative_target_value: {}",
-                to_f64(&cumulative_target_value)
-            );
-            println!("contribution for {}: {}", asset.name, to_f64(&contribution));
+    // When even selling one more unit of every sell leaves the buys short, the trades that spend the least are taken.
+    let trades = best_unit_trades(choices(false), &budget)
+        .or_else(|| best_unit_trades(choices(true), &budget))
+        .unwrap_or_else(|| least_spend(choices(true)));
 
-            largest_least_deviation = deviation;
+    let mut leftover = budget;
 
-            if contribution.abs() <= amount_left_to_contribute.abs() {
-                amount_left_to_contribute = amount_left_to_contribute - contribution;
-                largest_least_deviation = next_least_deviation;
-            } else {
-                largest_least_deviation = largest_least_deviation
-                    + (amount_left_to_contribute / &cumulative_target_value);
-                break;
-            }
-        }
+    for (&(index, _, _, _), trade) in rounded.iter().zip(trades) {
+        let asset = &mut balanced_portfolio[index];
 
-        match last_known_index {
-            Some(last_known_index) => {
-                // We contribute to all assets before index_to_stop.
-                let index_to_stop = last_known_index + 1;
-                (largest_least_deviation, index_to_stop)
-            }
-            None => (largest_least_deviation, 0),
+        leftover -= &trade.spend;
+
+        if trade.left_out {
+            asset.delta = None;
+            asset.left_out = true;
+        } else {
+            asset.delta = Some(trade.delta);
         }
-    };
+    }
 
-    println!("----");
+    if let Some(cash) = balanced_portfolio
+        .iter_mut()
+        .find(|asset| asset.cash_reserve.is_some())
+    {
+        cash.delta = Some(cash.delta.take().unwrap_or_else(BigRational::zero) + &leftover);
+    }
 
-    // Calculate amount to contribute for each eligible asset.
-    // Eligible assets are those before `index_to_stop`.
-    for (index, asset) in assets.iter_mut().enumerate() {
-        if index >= index_to_stop {
-            break;
-        }
+    to_f64(&leftover)
+}
+
+// A trade in whole units an asset may make when rounding, with what it spends with its costs, and the absolute
+// fractional deviation of the asset after it.
+struct UnitTrade {
+    delta: BigRational,
+    spend: BigRational,
+    deviation: BigRational,
+    // Whether the trade is left out, as no trade in whole units is worth placing.
+    left_out: bool,
+}
 
+impl UnitTrade {
+    // The trade, unless it is not worth placing.
+    fn of(asset: &Asset, delta: &BigRational) -> Option<Self> {
         let target_value = asset.target_value.as_ref().unwrap();
 
-        let deviation = asset.deviation.as_ref().unwrap();
+        let cost = match asset.costs {
+            Some(ref costs) => costs.of(delta, asset.unit_price.as_ref()),
+            None => BigRational::zero(),
+        };
+
+        if trade_shortfall(
+            &asset.value,
+            Some(target_value),
+            Some(delta),
+            asset.min_trade.as_ref(),
+            &cost,
+        )
+        .is_some()
+        {
+            return None;
+        }
+
+        Some(UnitTrade {
+            delta: delta.clone(),
+            spend: delta + cost,
+            deviation: ((&asset.value + delta) / target_value - BigRational::one()).abs(),
+            left_out: false,
+        })
+    }
+}
+
+// Picks one of the trades of each asset, spending at most the budget, with the smallest sum of deviations; or None when
+// no trades fit. Every way to pick them is tried, skipping those that cannot fit or cannot beat the best found so far.
+fn best_unit_trades(choices: Vec<Vec<UnitTrade>>, budget: &BigRational) -> Option<Vec<UnitTrade>> {
+    let mut search = UnitTradeSearch {
+        least_spends: vec![BigRational::zero(); choices.len() + 1],
+        least_deviations: vec![BigRational::zero(); choices.len() + 1],
+        choices,
+        budget: budget.clone(),
+        best: None,
+    };
 
-        /****
+    for index in (0..search.choices.len()).rev() {
+        let trades = &search.choices[index];
 
-        Definition:
-        deviation = asset_market_value / target_asset_market_value - 1
+        search.least_spends[index] = &search.least_spends[index + 1]
+            + trades.iter().map(|trade| &trade.spend).min().unwrap();
+        search.least_deviations[index] = &search.least_deviations[index + 1]
+            + trades.iter().map(|trade| &trade.deviation).min().unwrap();
+    }
 
-        Goal: (asset_market_value + delta) / target_asset_market_value - 1 = largest_least_deviation
+    search.search(&BigRational::zero(), &BigRational::zero(), &mut vec![]);
 
+    let choices = search.choices;
 
-        Solve for delta from above:
-        (asset_market_value + delta) / target_asset_market_value = largest_least_deviation + 1
-        asset_market_value + delta = (largest_least_deviation + 1) * target_asset_market_value
+    search.best.map(|(_, picks)| {
+        choices
+            .into_iter()
+            .zip(picks)
+            .map(|(trades, pick)| trades.into_iter().nth(pick).unwrap())
+            .collect()
+    })
+}
 
-        (1) delta = (largest_least_deviation + 1) * target_asset_market_value - asset_market_value
+struct UnitTradeSearch {
+    choices: Vec<Vec<UnitTrade>>,
+    budget: BigRational,
 
+    // The least each asset, with the assets after it, can spend, and the smallest sum of their deviations.
+    least_spends: Vec<BigRational>,
+    least_deviations: Vec<BigRational>,
 
-        Assume:
-        delta = target_asset_market_value * (largest_least_deviation - deviation)
+    // The smallest sum of deviations found so far, with the trade picked for each asset.
+    best: Option<(BigRational, Vec<usize>)>,
+}
 
-        Manipulate algebra to see it's equivalent to (1):
-        delta = target_asset_market_value * (largest_least_deviation - [asset_market_value / target_asset_market_value - 1])
-        delta = target_asset_market_value * (largest_least_deviation + [1 - asset_market_value / target_asset_market_value])
-        delta = (largest_least_deviation + 1) * target_asset_market_value - asset_market_value
+impl UnitTradeSearch {
+    // Tries the trades of the asset after those picked, which spend `spend` with a sum of deviations of `deviation`.
+    fn search(&mut self, spend: &BigRational, deviation: &BigRational, picks: &mut Vec<usize>) {
+        let index = picks.len();
 
-        ****/
-        let delta = target_value * (&largest_least_deviation - deviation);
+        if spend + &self.least_spends[index] > self.budget {
+            return;
+        }
 
-        println!("contribution for {}: {}", asset.name, to_f64(&delta));
+        if let Some((ref best_deviation, _)) = self.best {
+            if deviation + &self.least_deviations[index] >= *best_deviation {
+                return;
+            }
+        }
 
-        asset.delta = Some(delta);
-    }
+        if index == self.choices.len() {
+            self.best = Some((deviation.clone(), picks.clone()));
+            return;
+        }
+
+        for pick in 0..self.choices[index].len() {
+            let spend = spend + &self.choices[index][pick].spend;
+            let deviation = deviation + &self.choices[index][pick].deviation;
 
-    // assert!(false);
+            picks.push(pick);
+            self.search(&spend, &deviation, picks);
+            picks.pop();
+        }
+    }
+}
 
-    assets
+// Converts an input amount, which may not be NaN or infinite.
+fn to_rational(amount: f64, context: &str) -> Result<BigRational, RebalanceError> {
+    match BigRational::from_f64(amount) {
+        Some(amount) => Ok(amount),
+        None => Err(RebalanceError::Invalid(format!(
+            "{} is not a finite number",
+            context
+        ))),
+    }
 }
 
-fn to_f64(fraction: &BigRational) -> f64 {
+pub fn to_f64(fraction: &BigRational) -> f64 {
     let numerator = fraction.numer();
     let denominator = fraction.denom();
 
@@ -307,10 +1465,27 @@ fn to_f64(fraction: &BigRational) -> f64 {
 //     String::from_utf8(tw.into_inner().unwrap()).unwrap()
 // }
 
+// How amounts are presented in the output.
+pub struct OutputOptions {
+    pub currency: String,
+    // Number of decimal places that money amounts are rounded to.
+    pub decimal_places: usize,
+}
+
+impl Default for OutputOptions {
+    fn default() -> Self {
+        OutputOptions {
+            currency: "CAD".to_string(),
+            decimal_places: 2,
+        }
+    }
+}
+
 pub fn to_ledger_string(
     balanced_portfolio: &Vec<Asset>,
     dest_account_name: &str,
     source_account_name: &str,
+    options: &OutputOptions,
 ) -> String {
     let mut buf: String = "".to_string();
 
@@ -326,18 +1501,38 @@ pub fn to_ledger_string(
 
         let date_time_now = Local::now().format("%Y-%m-%d").to_string();
 
-        let amount_to_contribute = format_f64(to_f64(&delta), 2);
-        let amount_to_withdraw = format_f64(-to_f64(&delta), 2);
+        let account_name = match asset.ticker {
+            Some(ref ticker) => format!("{} ({})", asset.name, ticker),
+            None => asset.name.clone(),
+        };
+
+        // Trades in another currency are recorded in that currency, at their total price in the base currency.
+        let amount_to_contribute = match asset.currency {
+            Some((ref currency, ref rate)) => format!(
+                "{} {} @@ {} {}",
+                format_f64(to_f64(&(&delta / rate)), options.decimal_places),
+                currency,
+                format_f64(to_f64(&delta).abs(), options.decimal_places),
+                options.currency
+            ),
+            None => format!(
+                "{} {}",
+                format_f64(to_f64(&delta), options.decimal_places),
+                options.currency
+            ),
+        };
+        let amount_to_withdraw = format_f64(-to_f64(&delta), options.decimal_places);
 
         let line: String = if delta <= BigRational::zero() {
             format!(
                 r#"
 {date} * Withdrawal from {account_name}
-    {dest_account_name:76}{amount_to_contribute} CAD
-    {source_account_name:76}{amount_to_withdraw} CAD
+    {dest_account_name:76}{amount_to_contribute}
+    {source_account_name:76}{amount_to_withdraw} {currency}
     "#,
                 date = date_time_now,
-                account_name = asset.name,
+                currency = options.currency,
+                account_name = account_name,
                 dest_account_name = dest_account_name,
                 amount_to_contribute = amount_to_contribute,
                 source_account_name = source_account_name,
@@ -349,11 +1544,12 @@ pub fn to_ledger_string(
             format!(
                 r#"
 {date} * Contribution to {account_name}
-    {dest_account_name:76}{amount_to_contribute} CAD
-    {source_account_name:76}{amount_to_withdraw} CAD
+    {dest_account_name:76}{amount_to_contribute}
+    {source_account_name:76}{amount_to_withdraw} {currency}
     "#,
                 date = date_time_now,
-                account_name = asset.name,
+                currency = options.currency,
+                account_name = account_name,
                 dest_account_name = dest_account_name,
                 amount_to_contribute = amount_to_contribute,
                 source_account_name = source_account_name,
@@ -366,14 +1562,69 @@ pub fn to_ledger_string(
         buf = format!("{}\n{}\n", buf, line);
     }
 
-    return buf.to_string();
+    buf.to_string()
 }
 
-pub fn to_string(balanced_portfolio: &Vec<Asset>) -> String {
+pub fn to_string(balanced_portfolio: &Vec<Asset>, options: &OutputOptions) -> String {
+    let money_places = options.decimal_places;
+
     let mut buf = "Asset name\tAsset value\tHoldings %\tNew holdings %\tTarget allocation \
                    %\tTarget value\t$ to buy/sell"
         .to_string();
 
+    let show_units = balanced_portfolio
+        .iter()
+        .any(|asset| asset.unit_price.is_some());
+
+    let show_costs = balanced_portfolio.iter().any(|asset| asset.costs.is_some());
+
+    // Both measures of drift, when rebalancing by bands.
+    let show_bands = balanced_portfolio.iter().any(|asset| asset.band.is_some());
+
+    if show_bands {
+        buf.push_str("\tDrift (points)\tDrift %\tBand (points)\tIn band");
+    }
+
+    if show_costs {
+        buf.push_str("\tCost");
+    }
+
+    if show_units {
+        buf.push_str("\tShares to buy/sell");
+    }
+
+    let show_accounts = balanced_portfolio
+        .iter()
+        .any(|asset| !asset.accounts.is_empty());
+
+    if show_accounts {
+        buf.push_str("\tAccounts");
+    }
+
+    let show_tickers = balanced_portfolio
+        .iter()
+        .any(|asset| asset.ticker.is_some());
+
+    if show_tickers {
+        buf.push_str("\tTicker to buy/sell");
+    }
+
+    let show_native_amounts = balanced_portfolio
+        .iter()
+        .any(|asset| asset.currency.is_some());
+
+    if show_native_amounts {
+        buf.push_str("\tNative to buy/sell");
+    }
+
+    // Value of the portfolio after rebalancing.
+    let new_total = balanced_portfolio
+        .iter()
+        .fold(BigRational::zero(), |total, asset| match asset.delta {
+            Some(ref delta) => total + &asset.value + delta,
+            None => total + &asset.value,
+        });
+
     let mut total_asset_value = BigRational::zero();
     let mut total_current_holdings = BigRational::zero();
     let mut total_new_holdings = BigRational::zero();
@@ -388,69 +1639,340 @@ pub fn to_string(balanced_portfolio: &Vec<Asset>) -> String {
         };
 
         let target_allocation_percent =
-            if asset.target_allocation_percent <= BigRational::from_f64(1.0).unwrap() {
-                asset.target_allocation_percent.clone() * BigRational::from_f64(100.00).unwrap()
-            } else {
-                asset.target_allocation_percent.clone()
-            };
+            &asset.target_allocation_percent * BigRational::from_f64(100.00).unwrap();
 
         let actual_allocation = &asset.actual_allocation * BigRational::from_f64(100.00).unwrap();
 
-        let target_value = &(asset.target_value.clone()).unwrap();
+        let target_value = &(asset.target_value.clone()).unwrap_or_else(BigRational::zero);
+
+        let final_portion = if !new_total.is_zero() {
+            (&asset.value + &delta) / &new_total
+        } else {
+            BigRational::zero()
+        };
+
+        let final_portion = &final_portion * BigRational::from_f64(100.00).unwrap();
+
+        // totals
+
+        total_asset_value += &asset.value;
+        total_current_holdings += &actual_allocation;
+        total_new_holdings += &final_portion;
+        total_target_allocation += &target_allocation_percent;
+        total_target_value += target_value;
+        let rounding_factor = 10f64.powi(money_places as i32);
+        let actual_delta = (to_f64(&delta) * rounding_factor).round() / rounding_factor;
+        total_contribution += actual_delta;
+
+        // generate line
+
+        // Held assets and cash have no target.
+        let (target_allocation_percent, target_value) = match asset.untargeted {
+            _ if asset.cash_reserve.is_some() => ("-".to_string(), "-".to_string()),
+            Some(UntargetedMode::Hold) => ("-".to_string(), "-".to_string()),
+            _ => (
+                format_f64(to_f64(&target_allocation_percent), 3),
+                format_f64(to_f64(target_value), money_places),
+            ),
+        };
+
+        let mut line = format!(
+            "{}\t{}\t{}\t{}\t{}\t{}\t{}",
+            asset.name,
+            format_f64(to_f64(&asset.value), money_places),
+            format_f64(to_f64(&actual_allocation), 3),
+            format_f64(to_f64(&final_portion), 3),
+            target_allocation_percent,
+            target_value,
+            format_f64(actual_delta, money_places)
+        );
+
+        if show_bands {
+            line = match (&asset.band, &asset.drift, asset.relative_drift()) {
+                (Some(band), Some(drift), Some(relative_drift)) => format!(
+                    "{}\t{}\t{}\t±{}\t{}",
+                    line,
+                    format_f64(to_f64(drift) * 100.0, 3),
+                    format_f64(to_f64(&relative_drift) * 100.0, 3),
+                    format_f64(to_f64(band) * 100.0, 3),
+                    if asset.is_out_of_band() { "no" } else { "yes" }
+                ),
+                _ => format!("{}\t-\t-\t-\t-", line),
+            };
+        }
+
+        if show_costs {
+            line = format!(
+                "{}\t{}",
+                line,
+                format_f64(to_f64(&asset.trade_cost()), money_places)
+            );
+        }
+
+        if show_units {
+            line = format!(
+                "{}\t{}",
+                line,
+                format_units(&delta, asset.unit_price.as_ref())
+            );
+        }
+
+        if show_accounts {
+            line = format!("{}\t{}", line, asset.accounts.join(", "));
+        }
+
+        if show_tickers {
+            line = format!("{}\t{}", line, asset.ticker.as_ref().unwrap_or(&asset.name));
+        }
+
+        if show_native_amounts {
+            line = format!(
+                "{}\t{}",
+                line,
+                format_native_amount(&delta, asset.currency.as_ref(), options)
+            );
+        }
+
+        buf = format!("{}\n{}", buf, line);
+    }
+
+    let mut total_line = format!(
+        "Total\t{}\t{}\t{}\t{}\t{}\t{}",
+        format_f64(to_f64(&total_asset_value), money_places),
+        format_f64(to_f64(&total_current_holdings), 3),
+        format_f64(to_f64(&total_new_holdings), 3),
+        format_f64(to_f64(&total_target_allocation), 3),
+        format_f64(to_f64(&total_target_value), money_places),
+        format_f64(total_contribution, money_places)
+    );
+
+    // The contribution pays for the trades, and for what they cost.
+    if show_costs {
+        if show_bands {
+            total_line.push_str("\t\t\t\t");
+        }
+
+        total_line = format!(
+            "{}\t{}",
+            total_line,
+            format_f64(to_f64(&total_cost(balanced_portfolio)), money_places)
+        );
+    }
+
+    buf = format!("{}\n{}", buf, total_line);
+
+    let cash_assets: Vec<&Asset> = balanced_portfolio
+        .iter()
+        .filter(|asset| asset.cash_reserve.is_some())
+        .collect();
+
+    if !cash_assets.is_empty() {
+        let remaining_cash =
+            cash_assets
+                .iter()
+                .fold(BigRational::zero(), |total, asset| match asset.delta {
+                    Some(ref delta) => total + &asset.value + delta,
+                    None => total + &asset.value,
+                });
+
+        buf = format!(
+            "{}\nRemaining cash\t{}",
+            buf,
+            format_f64(to_f64(&remaining_cash), money_places)
+        );
+    }
+
+    align_columns(&buf)
+}
+
+// Rolls the rebalanced assets up the tree of nested targets, to show the drift from the target at each level. Drift is
+// the difference between the holdings and the target allocation, in percentage points of the portfolio.
+pub fn to_tree_string(
+    balanced_portfolio: &[Asset],
+    target_tree: &TargetTree,
+    options: &OutputOptions,
+) -> String {
+    let money_places = options.decimal_places;
+
+    let hundred = BigRational::from_f64(100.00).unwrap();
+
+    let new_total = balanced_portfolio
+        .iter()
+        .fold(BigRational::zero(), |total, asset| match asset.delta {
+            Some(ref delta) => total + &asset.value + delta,
+            None => total + &asset.value,
+        });
+
+    let mut buf =
+        "Target\tAsset value\tHoldings %\tNew holdings %\tTarget allocation %\tDrift %\tNew drift \
+                   %\t$ to buy/sell"
+            .to_string();
+
+    for (name, depth) in target_tree.walk() {
+        let leaves = target_tree.leaves_under(name);
+
+        let assets = balanced_portfolio
+            .iter()
+            .filter(|asset| leaves.contains(&asset.name.as_str()));
+
+        let mut value = BigRational::zero();
+        let mut actual_allocation = BigRational::zero();
+        let mut delta = BigRational::zero();
+
+        for asset in assets {
+            value += &asset.value;
+            actual_allocation += &asset.actual_allocation;
 
-        let final_portion =
-            (&asset.value + &delta) * &asset.target_allocation_percent / target_value;
+            if let Some(ref asset_delta) = asset.delta {
+                delta += asset_delta;
+            }
+        }
+
+        let new_allocation = if new_total.is_zero() {
+            BigRational::zero()
+        } else {
+            (&value + &delta) / &new_total
+        };
+
+        let target_allocation = target_tree.portfolio_fraction(name);
+
+        let drift = (&actual_allocation - &target_allocation) * &hundred;
+        let new_drift = (&new_allocation - &target_allocation) * &hundred;
+
+        buf = format!(
+            "{}\n{}{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+            buf,
+            "  ".repeat(depth),
+            name,
+            format_f64(to_f64(&value), money_places),
+            format_f64(to_f64(&(actual_allocation * &hundred)), 3),
+            format_f64(to_f64(&(new_allocation * &hundred)), 3),
+            format_f64(to_f64(&(target_allocation * &hundred)), 3),
+            format_f64(to_f64(&drift), 3),
+            format_f64(to_f64(&new_drift), 3),
+            format_f64(to_f64(&delta), money_places)
+        );
+    }
+
+    align_columns(&buf)
+}
+
+// Whether any asset drifted beyond its tolerance band before the rebalance.
+pub fn has_assets_out_of_band(assets: &[Asset]) -> bool {
+    assets.iter().any(|asset| asset.is_out_of_band())
+}
+
+// Names of the assets outside their tolerance band that the rebalance does not bring back within it, for lack of money
+// to buy them, or of sales to make.
+pub fn assets_left_out_of_band(assets: &[Asset]) -> Vec<String> {
+    // The assets rebalanced by bands, as they are after their trades.
+    let traded_assets: Vec<Asset> = assets
+        .iter()
+        .filter(|asset| asset.band.is_some())
+        .cloned()
+        .map(|mut asset| {
+            asset.value = &asset.value + asset.delta.take().unwrap_or_else(BigRational::zero);
+            asset
+        })
+        .collect();
 
-        let final_portion = &final_portion * BigRational::from_f64(100.00).unwrap();
+    let total: BigRational = traded_assets
+        .iter()
+        .fold(BigRational::zero(), |total, asset| total + &asset.value);
 
-        // totals
+    if !total.is_positive() {
+        return vec![];
+    }
 
-        total_asset_value = total_asset_value + &asset.value;
-        total_current_holdings = total_current_holdings + &actual_allocation;
-        total_new_holdings = total_new_holdings + &final_portion;
-        total_target_allocation = total_target_allocation + &target_allocation_percent;
-        total_target_value = total_target_value + target_value;
-        let actual_delta = (to_f64(&delta) * 100.0).round() / 100.0;
-        total_contribution = total_contribution + actual_delta;
+    let new_drifts = match drifts(traded_assets.clone(), &total) {
+        Ok(new_drifts) => new_drifts,
+        Err(_) => return vec![],
+    };
 
-        // generate line
+    traded_assets
+        .iter()
+        .zip(new_drifts)
+        .filter(|(asset, drift)| {
+            asset.is_out_of_band() && drift.abs() > *asset.band.as_ref().unwrap()
+        })
+        .map(|(asset, _)| asset.name.clone())
+        .collect()
+}
 
-        let line = format!(
-            "{}\t{}\t{}\t{}\t{}\t{}\t{}",
-            asset.name,
-            format_f64(to_f64(&asset.value), 2),
-            format_f64(to_f64(&actual_allocation), 3),
-            format_f64(to_f64(&final_portion), 3),
-            format_f64(to_f64(&target_allocation_percent), 3),
-            format_f64(to_f64(&target_value), 2),
-            format_f64(actual_delta, 2)
-        );
+// Whether the trade of any asset was left out, as smaller than its minimum trade size or costing more than it brings
+// the asset closer to its target.
+pub fn has_left_out_trades(assets: &[Asset]) -> bool {
+    assets.iter().any(|asset| asset.left_out)
+}
 
-        buf = format!("{}\n{}", buf, line);
-    }
+// Whether any targeted asset is bought or sold.
+pub fn has_trades(assets: &[Asset]) -> bool {
+    assets.iter().any(|asset| {
+        asset.has_target() && asset.delta.as_ref().is_some_and(|delta| !delta.is_zero())
+    })
+}
 
-    let total_line = format!(
-        "Total\t{}\t{}\t{}\t{}\t{}\t{}",
-        format_f64(to_f64(&total_asset_value), 2),
-        format_f64(to_f64(&total_current_holdings), 3),
-        format_f64(to_f64(&total_new_holdings), 3),
-        format_f64(to_f64(&total_target_allocation), 3),
-        format_f64(to_f64(&total_target_value), 2),
-        format_f64(total_contribution, 2)
-    );
+// Cash available to invest in the portfolio, above the cash reserve.
+pub fn available_cash(assets: &[Asset]) -> f64 {
+    let available_cash = assets
+        .iter()
+        .filter_map(|asset| asset.cash_to_invest())
+        .fold(BigRational::zero(), |total, cash| total + cash);
 
-    buf = format!("{}\n{}", buf, total_line);
+    to_f64(&available_cash)
+}
 
+// Aligns the tab-separated columns of a table. Rows ending in empty cells are not padded with trailing whitespace.
+fn align_columns(table: &str) -> String {
     let mut tw = TabWriter::new(vec![]);
 
-    tw.write_all(buf.as_bytes()).unwrap();
+    tw.write_all(table.as_bytes()).unwrap();
     tw.flush().unwrap();
 
-    String::from_utf8(tw.into_inner().unwrap()).unwrap()
+    String::from_utf8(tw.into_inner().unwrap())
+        .unwrap()
+        .lines()
+        .map(str::trim_end)
+        .collect::<Vec<&str>>()
+        .join("\n")
 }
 
 fn format_f64(price: f64, dec_places: usize) -> String {
-    format!("{:.*}", dec_places, price)
+    let formatted = format!("{:.*}", dec_places, price);
+
+    // Amounts that round to zero, such as a total of trades that cancel out, are not shown as negative.
+    if formatted.starts_with('-') && formatted[1..].chars().all(|c| c == '0' || c == '.') {
+        formatted[1..].to_string()
+    } else {
+        formatted
+    }
+}
+
+fn format_units(delta: &BigRational, unit_price: Option<&BigRational>) -> String {
+    match unit_price {
+        Some(unit_price) => format_f64(to_f64(&(delta / unit_price)), 3),
+        None => "-".to_string(),
+    }
+}
+
+// Formats the amount to buy or sell in the currency the asset is traded in.
+fn format_native_amount(
+    delta: &BigRational,
+    currency: Option<&(String, BigRational)>,
+    options: &OutputOptions,
+) -> String {
+    match currency {
+        Some((currency, rate)) => format!(
+            "{} {}",
+            format_f64(to_f64(&(delta / rate)), options.decimal_places),
+            currency
+        ),
+        None => format!(
+            "{} {}",
+            format_f64(to_f64(delta), options.decimal_places),
+            options.currency
+        ),
+    }
 }
 
 // EVERYTHING BELOW IS NEW
@@ -460,8 +1982,20 @@ struct NewAsset {
     name: String,
     actual_value: BigRational,
 
+    quantity: Option<BigRational>,
+    unit_price: Option<BigRational>,
+
+    accounts: Vec<String>,
+
+    ticker: Option<String>,
+
+    currency: Option<(String, BigRational)>,
+
     actual_allocation_percent: BigRational,
     target_allocation_percent: BigRational,
+
+    min_trade: Option<BigRational>,
+    costs: Option<TradeCost>,
 }
 
 #[derive(Clone, Debug)]
@@ -481,6 +2015,29 @@ pub struct PortfolioAsset {
     // If it is positive, then contributions are added. Otherwise, if it is negative, then it is considered a
     // withdrawal.
     contribution: Option<BigRational>,
+    // Whether the contribution was left out, as smaller than the minimum trade or costing more than it is worth.
+    left_out: bool,
+}
+
+impl PortfolioAsset {
+    fn trade_cost(&self) -> BigRational {
+        match (&self.asset.costs, &self.contribution) {
+            (Some(costs), Some(contribution)) => {
+                costs.of(contribution, self.asset.unit_price.as_ref())
+            }
+            _ => BigRational::zero(),
+        }
+    }
+
+    fn trade_shortfall(&self) -> Option<BigRational> {
+        trade_shortfall(
+            &self.asset.actual_value,
+            self.target_value.as_ref(),
+            self.contribution.as_ref(),
+            self.asset.min_trade.as_ref(),
+            &self.trade_cost(),
+        )
+    }
 }
 
 pub fn convert_old_portfolio(old_assets: Vec<Asset>) -> Vec<PortfolioAsset> {
@@ -491,14 +2048,71 @@ pub fn convert_old_portfolio(old_assets: Vec<Asset>) -> Vec<PortfolioAsset> {
                 name: old_asset.name,
                 actual_value: old_asset.value,
 
+                quantity: old_asset.quantity,
+                unit_price: old_asset.unit_price,
+
+                accounts: old_asset.accounts,
+
+                ticker: old_asset.ticker,
+
+                currency: old_asset.currency,
+
                 actual_allocation_percent: old_asset.actual_allocation,
                 target_allocation_percent: old_asset.target_allocation_percent,
+
+                min_trade: old_asset.min_trade,
+                costs: old_asset.costs,
             };
             PortfolioAsset {
                 asset,
                 target_value: old_asset.target_value,
                 fractional_deviation: old_asset.deviation,
                 contribution: old_asset.delta,
+                left_out: old_asset.left_out,
+            }
+        })
+        .collect()
+}
+
+// The reverse of `convert_old_portfolio`, for assets with a target allocation.
+fn convert_new_portfolio(new_assets: Vec<PortfolioAsset>) -> Vec<Asset> {
+    new_assets
+        .into_iter()
+        .map(|portfolio_asset: PortfolioAsset| {
+            let new_asset = portfolio_asset.asset;
+
+            Asset {
+                name: new_asset.name,
+
+                value: new_asset.actual_value,
+                actual_allocation: new_asset.actual_allocation_percent,
+                target_allocation_percent: new_asset.target_allocation_percent,
+
+                quantity: new_asset.quantity,
+                unit_price: new_asset.unit_price,
+
+                accounts: new_asset.accounts,
+
+                ticker: new_asset.ticker,
+
+                currency: new_asset.currency,
+
+                untargeted: None,
+
+                cash_reserve: None,
+
+                min_trade: new_asset.min_trade,
+
+                costs: new_asset.costs,
+
+                left_out: portfolio_asset.left_out,
+
+                band: None,
+                drift: None,
+
+                target_value: portfolio_asset.target_value,
+                deviation: portfolio_asset.fractional_deviation,
+                delta: portfolio_asset.contribution,
             }
         })
         .collect()
@@ -519,19 +2133,95 @@ fn asset_comparator(left: &PortfolioAsset, right: &PortfolioAsset) -> Ordering {
 pub fn new_lazy_rebalance(
     amount_to_contribute: f64,
     mut assets: Vec<PortfolioAsset>,
-) -> Vec<PortfolioAsset> {
-    let amount_to_contribute = BigRational::from_f64(amount_to_contribute).unwrap();
+) -> Result<Vec<PortfolioAsset>, RebalanceError> {
+    if assets.is_empty() {
+        return Err(RebalanceError::EmptyPortfolio);
+    }
+
+    let amount_to_contribute = to_rational(amount_to_contribute, "contribution")?;
 
     let portfolio_total: BigRational = assets
         .iter()
-        .fold(BigRational::zero(), |total, ref portfolio_asset| {
+        .fold(BigRational::zero(), |total, portfolio_asset| {
             total + &portfolio_asset.asset.actual_value
         });
 
     let target_total: BigRational = &portfolio_total + &amount_to_contribute;
 
+    if target_total.is_zero() {
+        return Err(RebalanceError::DivisionByZero(
+            "the portfolio value after the contribution".to_string(),
+        ));
+    }
+
+    set_target_values(&target_total, &portfolio_total, &mut assets)?;
+
+    let order = |left: &PortfolioAsset, right: &PortfolioAsset| {
+        let result = asset_comparator(left, right);
+
+        if amount_to_contribute < BigRational::zero() {
+            result.reverse()
+        } else {
+            result
+        }
+    };
+
+    // Sort assets by their fractional deviations in ascending order. That is, from most negative (lowest)
+    assets.sort_by(|left, right| order(left, right));
+
+    // Trades smaller than the minimum trade size of their asset, or which cost more than they bring the asset closer to
+    // its target, are suppressed, and the contribution is distributed again among the other assets, until every trade
+    // is worth placing. The trade furthest from it goes first: the others may grow past their minimum once its money
+    // is distributed again.
+    let mut suppressed_assets = vec![];
+
+    loop {
+        distribute_contribution(&amount_to_contribute, &mut assets);
+
+        let smallest_trade = assets
+            .iter()
+            .enumerate()
+            .filter_map(|(index, portfolio_asset)| {
+                portfolio_asset
+                    .trade_shortfall()
+                    .map(|shortfall| (index, shortfall))
+            })
+            .min_by(|left, right| left.1.cmp(&right.1))
+            .map(|(index, _)| index);
+
+        match smallest_trade {
+            Some(index) => {
+                let mut portfolio_asset = assets.remove(index);
+                portfolio_asset.contribution = None;
+                portfolio_asset.left_out = true;
+                suppressed_assets.push(portfolio_asset);
+            }
+            None => break,
+        }
+    }
+
+    assets.append(&mut suppressed_assets);
+    assets.sort_by(|left, right| order(left, right));
+
+    Ok(assets)
+}
+
+// Sets the target value of each asset, for a portfolio worth `target_total`, and its fractional deviation from it; and
+// its allocation of a portfolio worth `portfolio_total`.
+fn set_target_values(
+    target_total: &BigRational,
+    portfolio_total: &BigRational,
+    assets: &mut [PortfolioAsset],
+) -> Result<(), RebalanceError> {
     for portfolio_asset in assets.iter_mut() {
-        let target_value = &target_total * &portfolio_asset.asset.target_allocation_percent;
+        let target_value = target_total * &portfolio_asset.asset.target_allocation_percent;
+
+        if target_value.is_zero() {
+            return Err(RebalanceError::DivisionByZero(format!(
+                "the target value of \"{}\"",
+                portfolio_asset.asset.name
+            )));
+        }
 
         // Equivalent to: (value - target_value) / target_value
         // Similar to relative error, but with positive/negative sign having semantic meaning.
@@ -541,30 +2231,26 @@ pub fn new_lazy_rebalance(
         let fractional_deviation =
             (&portfolio_asset.asset.actual_value / &target_value) - BigRational::one();
 
-        portfolio_asset.asset.actual_allocation_percent = if portfolio_total <= BigRational::zero()
+        portfolio_asset.asset.actual_allocation_percent = if *portfolio_total <= BigRational::zero()
         {
             BigRational::zero()
         } else {
-            &portfolio_asset.asset.actual_value / &portfolio_total
+            &portfolio_asset.asset.actual_value / portfolio_total
         };
 
         portfolio_asset.target_value = Some(target_value);
         portfolio_asset.fractional_deviation = Some(fractional_deviation);
     }
 
-    // Sort assets by their fractional deviations in ascending order. That is, from most negative (lowest)
-    assets.sort_by(|left, right| {
-        let result = asset_comparator(left, right);
-
-        if amount_to_contribute < BigRational::zero() {
-            result.reverse()
-        } else {
-            result
-        }
-    });
+    Ok(())
+}
 
-    // TODO: debug
-    let mut debug_contributions: HashMap<String, BigRational> = HashMap::new();
+// Distributes the contribution among the assets, sorted by their fractional deviations: the assets furthest from their
+// target get the contribution first, until they are tied with the next ones.
+fn distribute_contribution(amount_to_contribute: &BigRational, assets: &mut [PortfolioAsset]) {
+    for portfolio_asset in assets.iter_mut() {
+        portfolio_asset.contribution = None;
+    }
 
     let (largest_least_deviation, index_to_stop): (BigRational, usize) = {
         // This is the amount of contribution added to the group of assets with the most negative (lowest) fractional
@@ -587,8 +2273,6 @@ pub fn new_lazy_rebalance(
                 break;
             }
 
-            debug_contributions.insert(portfolio_asset.asset.name.clone(), BigRational::zero());
-
             last_known_index = Some(index);
 
             let fractional_deviation = portfolio_asset
@@ -631,34 +2315,12 @@ pub fn new_lazy_rebalance(
 
             contribution_added = &contribution_added + target_value;
 
-            // TODO: debug
-            {
-                let mut amount_added =
-                    if distributed_contribution.abs() <= amount_left_to_contribute.abs() {
-                        distributed_contribution.clone()
-                    } else {
-                        amount_left_to_contribute.clone()
-                    };
-
-                let group_of_assets: Vec<PortfolioAsset> =
-                    assets[0..(index + 1)].iter().cloned().collect();
-                let total_percent: BigRational = group_of_assets
-                    .iter()
-                    .map(|x: &PortfolioAsset| x.asset.target_allocation_percent.clone())
-                    .sum();
-
-                for x in assets.iter() {
-                    let portion =
-                        &amount_added * (&x.asset.target_allocation_percent / &total_percent);
+            // Once every asset is tied, whatever is left is spread over all of them: it is all of the contribution
+            // when some assets are left out, e.g. for trades below their minimum.
+            let is_last = index == assets.len() - 1;
 
-                    if let Some(x) = debug_contributions.get_mut(&x.asset.name) {
-                        *x += portion;
-                    }
-                }
-            };
-
-            if distributed_contribution.abs() <= amount_left_to_contribute.abs() {
-                amount_left_to_contribute = amount_left_to_contribute - distributed_contribution;
+            if distributed_contribution.abs() <= amount_left_to_contribute.abs() && !is_last {
+                amount_left_to_contribute -= distributed_contribution;
                 largest_least_deviation = next_least_deviation;
             } else {
                 // Find next_least_deviation such that:
@@ -695,35 +2357,41 @@ pub fn new_lazy_rebalance(
 
         let contribution = target_value * (&largest_least_deviation - fractional_deviation);
 
-        
-
-        println!(
-            "contribution for {}: {}",
-            portfolio_asset.asset.name,
-            to_f64(&contribution)
-        );
-        let portion = &debug_contributions
-            .get(&portfolio_asset.asset.name)
-            .unwrap();
-        println!(
-            "debug contribution for {}: {}",
-            portfolio_asset.asset.name,
-            to_f64(&portion)
-        );
-
-        assert!(*portion.clone() == contribution.clone());
-
         portfolio_asset.contribution = Some(contribution);
     }
-
-    assets
 }
 
+// Only the tests render the new portfolio; the app renders it through `to_string`, once converted back.
+#[cfg(test)]
 pub fn new_to_string(balanced_portfolio: &Vec<PortfolioAsset>) -> String {
     let mut buf = "Asset name\tAsset value\tHoldings %\tNew holdings %\tTarget allocation \
                    %\tTarget value\t$ to buy/sell"
         .to_string();
 
+    let show_units = balanced_portfolio
+        .iter()
+        .any(|asset| asset.asset.unit_price.is_some());
+
+    if show_units {
+        buf.push_str("\tShares to buy/sell");
+    }
+
+    let show_accounts = balanced_portfolio
+        .iter()
+        .any(|asset| !asset.asset.accounts.is_empty());
+
+    if show_accounts {
+        buf.push_str("\tAccounts");
+    }
+
+    let show_tickers = balanced_portfolio
+        .iter()
+        .any(|asset| asset.asset.ticker.is_some());
+
+    if show_tickers {
+        buf.push_str("\tTicker to buy/sell");
+    }
+
     let mut total_asset_value = BigRational::zero();
     let mut total_current_holdings = BigRational::zero();
     let mut total_new_holdings = BigRational::zero();
@@ -737,13 +2405,8 @@ pub fn new_to_string(balanced_portfolio: &Vec<PortfolioAsset>) -> String {
             None => BigRational::zero(),
         };
 
-        let target_allocation_percent = if asset.asset.target_allocation_percent
-            <= BigRational::from_f64(1.0).unwrap()
-        {
-            asset.asset.target_allocation_percent.clone() * BigRational::from_f64(100.00).unwrap()
-        } else {
-            asset.asset.target_allocation_percent.clone()
-        };
+        let target_allocation_percent =
+            &asset.asset.target_allocation_percent * BigRational::from_f64(100.00).unwrap();
 
         let actual_allocation =
             &asset.asset.actual_allocation_percent * BigRational::from_f64(100.00).unwrap();
@@ -758,27 +2421,47 @@ pub fn new_to_string(balanced_portfolio: &Vec<PortfolioAsset>) -> String {
 
         // totals
 
-        total_asset_value = total_asset_value + &asset.asset.actual_value;
-        total_current_holdings = total_current_holdings + &actual_allocation;
-        total_new_holdings = total_new_holdings + &final_portion;
-        total_target_allocation = total_target_allocation + &target_allocation_percent;
-        total_target_value = total_target_value + target_value;
+        total_asset_value += &asset.asset.actual_value;
+        total_current_holdings += &actual_allocation;
+        total_new_holdings += &final_portion;
+        total_target_allocation += &target_allocation_percent;
+        total_target_value += target_value;
         let actual_delta = (to_f64(&delta) * 100.0).round() / 100.0;
-        total_contribution = total_contribution + actual_delta;
+        total_contribution += actual_delta;
 
         // generate line
 
-        let line = format!(
+        let mut line = format!(
             "{}\t{}\t{}\t{}\t{}\t{}\t{}",
             asset.asset.name,
             format_f64(to_f64(&asset.asset.actual_value), 2),
             format_f64(to_f64(&actual_allocation), 3),
             format_f64(to_f64(&final_portion), 3),
             format_f64(to_f64(&target_allocation_percent), 3),
-            format_f64(to_f64(&target_value), 2),
+            format_f64(to_f64(target_value), 2),
             format_f64(actual_delta, 2)
         );
 
+        if show_units {
+            line = format!(
+                "{}\t{}",
+                line,
+                format_units(&delta, asset.asset.unit_price.as_ref())
+            );
+        }
+
+        if show_accounts {
+            line = format!("{}\t{}", line, asset.asset.accounts.join(", "));
+        }
+
+        if show_tickers {
+            line = format!(
+                "{}\t{}",
+                line,
+                asset.asset.ticker.as_ref().unwrap_or(&asset.asset.name)
+            );
+        }
+
         buf = format!("{}\n{}", buf, line);
     }
 
@@ -801,3 +2484,377 @@ pub fn new_to_string(balanced_portfolio: &Vec<PortfolioAsset>) -> String {
 
     String::from_utf8(tw.into_inner().unwrap()).unwrap()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // An asset held in shares, with a target allocation in percent.
+    fn asset(name: &str, target_percent: i64, value: f64, unit_price: f64) -> Asset {
+        let target_allocation = BigRational::new(target_percent.into(), 100.into());
+
+        Asset::new(name.to_string(), target_allocation, value)
+            .unwrap()
+            .with_units(value / unit_price, unit_price)
+            .unwrap()
+    }
+
+    fn portfolio() -> Vec<Asset> {
+        vec![
+            asset("TIPS fund", 10, 6500.0, 26.0),
+            asset("Bond fund", 20, 16500.0, 82.5),
+            asset("Domestic Stock ETF", 40, 43500.0, 87.0),
+            asset("International Stock ETF", 30, 33500.0, 45.5),
+        ]
+    }
+
+    fn delta(asset: &Asset) -> BigRational {
+        asset.delta.clone().unwrap_or_else(BigRational::zero)
+    }
+
+    #[test]
+    fn test_invalid_builder_amounts() {
+        let fund = || asset("Bond fund", 20, 16500.0, 82.5);
+
+        assert!(fund().with_cash_reserve(-1.0).is_err());
+        assert!(fund().with_min_trade(f64::NAN).is_err());
+        assert!(fund().with_currency("EUR".to_string(), 0.0).is_err());
+        assert!(fund().with_currency("EUR".to_string(), 1.1).is_ok());
+    }
+
+    #[test]
+    fn test_min_trade() {
+        let min_trade = BigRational::from_integer(500.into());
+
+        let assets = portfolio()
+            .into_iter()
+            .map(|asset| asset.with_min_trade(500.0).unwrap())
+            .collect();
+
+        let balanced_portfolio = lazy_rebalance(10000.0, assets).unwrap();
+
+        // Without a minimum, Domestic Stock ETF gets 214.29; it is left out, and the rest is invested elsewhere.
+        let domestic = balanced_portfolio
+            .iter()
+            .find(|asset| asset.name == "Domestic Stock ETF")
+            .unwrap();
+        assert!(domestic.delta.is_none());
+
+        for asset in balanced_portfolio.iter() {
+            assert!(
+                delta(asset).is_zero() || delta(asset).abs() >= min_trade,
+                "{} trades too little",
+                asset.name
+            );
+        }
+
+        let total: BigRational = balanced_portfolio.iter().map(delta).sum();
+        assert_eq!(to_f64(&total), 10000.0);
+    }
+
+    #[test]
+    fn test_min_trade_in_full_mode() {
+        let assets = portfolio()
+            .into_iter()
+            .map(|asset| match asset.name.as_str() {
+                "Domestic Stock ETF" => asset.with_min_trade(5000.0).unwrap(),
+                _ => asset,
+            })
+            .collect();
+
+        let balanced_portfolio = full_rebalance(0.0, assets).unwrap();
+
+        // The 3500.00 sale of Domestic Stock ETF is left out, so the others sell more or buy less to make up for it.
+        for asset in balanced_portfolio.iter() {
+            if asset.name == "Domestic Stock ETF" {
+                assert!(asset.left_out);
+                assert!(asset.delta.is_none());
+            } else {
+                assert!(!asset.left_out);
+                assert!(delta(asset) < asset.target_value.clone().unwrap() - &asset.value);
+            }
+        }
+
+        let total: BigRational = balanced_portfolio.iter().map(delta).sum();
+        assert!(total.is_zero());
+
+        // When every trade is too small, nothing is traded.
+        let assets = portfolio()
+            .into_iter()
+            .map(|asset| asset.with_min_trade(5000.0).unwrap())
+            .collect();
+
+        let balanced_portfolio = full_rebalance(0.0, assets).unwrap();
+
+        assert!(has_left_out_trades(&balanced_portfolio));
+        assert!(!has_trades(&balanced_portfolio));
+    }
+
+    #[test]
+    fn test_trade_costs() {
+        let mut costs = TradeCosts::default();
+        costs.buy_commission.set(None, 10.0).unwrap();
+        costs
+            .buy_commission
+            .set(Some("Domestic Stock ETF"), 300.0)
+            .unwrap();
+
+        let assets = portfolio()
+            .into_iter()
+            .map(|asset| asset.with_costs(&costs))
+            .collect();
+
+        let balanced_portfolio =
+            rebalance_net_of_costs(10000.0, assets, &RebalanceOptions::default()).unwrap();
+
+        // The 300.00 commission costs more than the small trade of Domestic Stock ETF would bring it closer to its target.
+        let domestic = balanced_portfolio
+            .iter()
+            .find(|asset| asset.name == "Domestic Stock ETF")
+            .unwrap();
+        assert!(domestic.delta.is_none());
+        assert!(domestic.trade_cost().is_zero());
+
+        for asset in balanced_portfolio
+            .iter()
+            .filter(|asset| asset.delta.is_some())
+        {
+            let target_value = asset.target_value.as_ref().unwrap();
+            let improvement = (&asset.value - target_value).abs()
+                - (&asset.value + delta(asset) - target_value).abs();
+
+            assert!(
+                improvement > asset.trade_cost(),
+                "{} costs more than it is worth",
+                asset.name
+            );
+        }
+
+        // The costs are paid out of the contribution.
+        let total: BigRational = balanced_portfolio.iter().map(delta).sum();
+        let total_cost = total_cost(&balanced_portfolio);
+        assert!((to_f64(&(total + total_cost)) - 10000.0).abs() < COST_TOLERANCE);
+    }
+
+    #[test]
+    fn test_trade_costs_that_do_not_settle() {
+        let mut costs = TradeCosts::default();
+        costs.buy_commission.set(None, 10.0).unwrap();
+
+        // Net of both commissions, the stock fund buys 490.00, below its minimum, and is left out, and the bond fund
+        // would overshoot its target as far as it is below it, so nothing is bought; without commissions, both funds
+        // buy 500.00 again.
+        let assets = vec![
+            asset("Stock fund", 50, 100.0, 1.0)
+                .with_min_trade(493.0)
+                .unwrap(),
+            asset("Bond fund", 50, 100.0, 1.0),
+        ]
+        .into_iter()
+        .map(|asset| asset.with_costs(&costs))
+        .collect();
+
+        match rebalance_net_of_costs(1000.0, assets, &RebalanceOptions::default()) {
+            Err(RebalanceError::CostsNotSettled { rounds, change }) => {
+                assert_eq!(rounds, MAX_COST_ROUNDS);
+                assert_eq!(change, 20.0);
+            }
+            _ => panic!("expected the costs not to settle"),
+        }
+    }
+
+    #[test]
+    fn test_bands_rebalance() {
+        let bands = ToleranceBands::default();
+
+        // Drift is measured before the contribution: Domestic Stock ETF is 3.5 points above its target, not below it
+        // once the contribution is counted.
+        let balanced_portfolio = bands_rebalance(50000.0, portfolio(), &bands).unwrap();
+
+        for asset in balanced_portfolio.iter() {
+            let drift = asset.drift.clone().unwrap();
+            assert_eq!(
+                drift,
+                &asset.value / BigRational::from_integer(100000.into())
+                    - &asset.target_allocation_percent
+            );
+        }
+
+        // Without a contribution, only the TIPS fund is out of band, and the assets within their band are not sold to
+        // fund it.
+        let balanced_portfolio = bands_rebalance(0.0, portfolio(), &bands).unwrap();
+
+        for asset in balanced_portfolio.iter() {
+            assert_eq!(asset.is_out_of_band(), asset.name == "TIPS fund");
+            assert!(delta(asset).is_zero(), "{} is traded", asset.name);
+        }
+
+        // Sales of assets above their band fund purchases, and the assets within their band are left alone.
+        let mut bands = ToleranceBands::default();
+        bands.absolute.set(Some("Domestic Stock ETF"), 3.0).unwrap();
+
+        let balanced_portfolio = bands_rebalance(0.0, portfolio(), &bands).unwrap();
+
+        for asset in balanced_portfolio.iter() {
+            match asset.name.as_str() {
+                "Domestic Stock ETF" => assert_eq!(to_f64(&delta(asset)), -3500.0),
+                "TIPS fund" => assert_eq!(to_f64(&delta(asset)), 3500.0),
+                _ => assert!(
+                    !asset.is_out_of_band() && delta(asset).is_zero(),
+                    "{} is traded",
+                    asset.name
+                ),
+            }
+        }
+    }
+
+    #[test]
+    fn test_round_to_whole_units_with_sells() {
+        let exact = full_rebalance(0.0, portfolio()).unwrap();
+
+        let mut rounded = exact.clone();
+        let leftover = round_to_whole_units(&mut rounded);
+
+        // Selling fewer shares than asked leaves the buys short, so they give up shares to stay within the proceeds.
+        assert!(leftover >= 0.0);
+
+        let total: BigRational = rounded.iter().map(delta).sum();
+        assert_eq!(to_f64(&total), -leftover);
+
+        for (exact, rounded) in exact.iter().zip(rounded.iter()) {
+            let units = delta(rounded) / rounded.unit_price.as_ref().unwrap();
+            assert!(
+                units.is_integer(),
+                "{} trades {} shares",
+                rounded.name,
+                units
+            );
+
+            // No more shares are sold than asked, and no shares are sold that were to be bought.
+            if exact.delta.as_ref().unwrap().is_negative() {
+                assert!(
+                    delta(rounded) >= delta(exact),
+                    "{} sells too much",
+                    rounded.name
+                );
+                assert!(!delta(rounded).is_positive());
+            } else {
+                assert!(delta(rounded) <= delta(exact) + rounded.unit_price.as_ref().unwrap());
+            }
+        }
+    }
+
+    #[test]
+    fn test_round_to_whole_units_with_buys() {
+        let mut balanced_portfolio = lazy_rebalance(10000.0, portfolio()).unwrap();
+
+        let leftover = round_to_whole_units(&mut balanced_portfolio);
+
+        let total: BigRational = balanced_portfolio.iter().map(delta).sum();
+        assert_eq!(to_f64(&total) + leftover, 10000.0);
+
+        // The leftover is smaller than any share whose purchase would bring its asset closer to its target.
+        for asset in balanced_portfolio.iter() {
+            let unit_price = asset.unit_price.as_ref().unwrap();
+            let target_value = asset.target_value.as_ref().unwrap();
+            let deviation = |delta: BigRational| {
+                ((&asset.value + delta) / target_value - BigRational::one()).abs()
+            };
+
+            assert!(
+                leftover < to_f64(unit_price)
+                    || deviation(delta(asset) + unit_price) >= deviation(delta(asset)),
+                "{} could buy more",
+                asset.name
+            );
+        }
+    }
+
+    #[test]
+    fn test_round_to_whole_units_beats_one_unit_at_a_time() {
+        let fund = |name: &str, sevenths: i64, value: f64, unit_price: f64| {
+            Asset::new(
+                name.to_string(),
+                BigRational::new(sevenths.into(), 7.into()),
+                value,
+            )
+            .unwrap()
+            .with_units(value / unit_price, unit_price)
+            .unwrap()
+        };
+
+        // The exact trades buy 40.00, 30.00 and 30.00. One share of A brings the portfolio closest to its targets, but
+        // leaves 40.00, which buys neither B nor C; a share of each of B and C instead brings it closer still.
+        let mut balanced_portfolio = lazy_rebalance(
+            100.0,
+            vec![
+                fund("A", 3, 1460.0, 60.0),
+                fund("B", 2, 970.0, 50.0),
+                fund("C", 2, 970.0, 50.0),
+            ],
+        )
+        .unwrap();
+
+        assert_eq!(round_to_whole_units(&mut balanced_portfolio), 0.0);
+
+        for asset in balanced_portfolio.iter() {
+            let expected = if asset.name == "A" { 0.0 } else { 50.0 };
+            assert_eq!(to_f64(&delta(asset)), expected, "{}", asset.name);
+        }
+    }
+
+    #[test]
+    fn test_round_to_whole_units_with_min_trade() {
+        // The exact trade of 120.00 meets the minimum of 110.00, but 2 shares fall short of it, and 3 shares do not
+        // fit; so the trade is left out, and kept in cash.
+        let mut balanced_portfolio = lazy_rebalance(
+            200.0,
+            vec![
+                asset("Stock fund", 50, 380.0, 50.0)
+                    .with_min_trade(110.0)
+                    .unwrap(),
+                asset("Bond fund", 50, 420.0, 1.0),
+            ],
+        )
+        .unwrap();
+
+        assert_eq!(round_to_whole_units(&mut balanced_portfolio), 120.0);
+
+        let stock_fund = &balanced_portfolio[0];
+        assert_eq!(stock_fund.name, "Stock fund");
+        assert!(stock_fund.left_out);
+        assert!(delta(stock_fund).is_zero());
+
+        assert_eq!(to_f64(&delta(&balanced_portfolio[1])), 80.0);
+    }
+
+    #[test]
+    fn test_round_to_whole_units_with_costs() {
+        let mut costs = TradeCosts::default();
+        costs.share_fee.set(None, 1.0).unwrap();
+        costs.spread.set(None, 0.5).unwrap();
+
+        let assets = portfolio()
+            .into_iter()
+            .map(|asset| asset.with_costs(&costs))
+            .collect();
+
+        let mut balanced_portfolio =
+            rebalance_net_of_costs(10000.0, assets, &RebalanceOptions::default()).unwrap();
+
+        let leftover = round_to_whole_units(&mut balanced_portfolio);
+
+        // The costs of the rounded trades are paid out of the contribution, and what is left of it is left over.
+        let total: BigRational = balanced_portfolio.iter().map(delta).sum();
+        let total_cost = total_cost(&balanced_portfolio);
+        assert!((to_f64(&(total + total_cost)) + leftover - 10000.0).abs() < COST_TOLERANCE);
+
+        for asset in balanced_portfolio.iter() {
+            assert!(
+                asset.trade_shortfall().is_none(),
+                "{} is not worth trading",
+                asset.name
+            );
+        }
+    }
+}
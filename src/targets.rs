@@ -0,0 +1,871 @@
+// Target allocations, as written in a targets file or a plan, and their normalization to fractions of the portfolio.
+//
+// An allocation is a percentage (`40%`), or a bare number read according to the target notation:
+//
+// - percent: `40` is 40% of the portfolio (the default)
+// - fraction: `0.4` is 40% of the portfolio
+// - weight: allocations are relative weights, e.g. `3`, `2` and `1` are 50%, 33.3% and 16.7% of the portfolio
+//
+// Allocations are kept as exact rationals, so that weights such as thirds sum to exactly 100%.
+//
+// A targets file may also be a glide path: allocations at several dates, or ages of the investor, given in columns
+// under a header row. The allocations used are interpolated linearly for the date of the rebalance, and stay at the
+// first or last column's allocations before or after them.
+//
+//     Date,       2020-01-01, 2040-01-01
+//     Equity,     90,         50
+//     Bonds,      10,         50
+//
+// An allocation may also be a formula, such as `age - 20` or `100 - Bonds` (see `formula`), which is resolved before
+// the allocations are normalized.
+
+// rust imports
+
+use std::collections::HashMap;
+
+// 3rd-party imports
+
+use num::bigint::BigInt;
+use num::traits::Pow;
+use num::BigRational;
+use num::{One, Signed, Zero};
+
+use chrono::{Datelike, NaiveDate};
+
+// local imports
+
+use error::RebalanceError;
+use formula::Formula;
+use rebalance::to_f64;
+
+// Target allocation of each asset, as a fraction of the portfolio.
+pub type TargetMap = HashMap<String, BigRational>;
+
+// How bare numbers in the targets are read.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TargetNotation {
+    Percent,
+    Fraction,
+    Weight,
+}
+
+pub const TARGET_NOTATIONS: &[&str] = &["percent", "fraction", "weight"];
+
+impl TargetNotation {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.trim().to_lowercase().as_str() {
+            "percent" => Some(TargetNotation::Percent),
+            "fraction" => Some(TargetNotation::Fraction),
+            "weight" => Some(TargetNotation::Weight),
+            _ => None,
+        }
+    }
+}
+
+// An allocation as written, before it is normalized.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Allocation {
+    Number(BigRational),
+    Percent(BigRational),
+    Formula(Formula),
+}
+
+impl Allocation {
+    fn number(&self) -> Option<&BigRational> {
+        match *self {
+            Allocation::Number(ref number) | Allocation::Percent(ref number) => Some(number),
+            Allocation::Formula(_) => None,
+        }
+    }
+
+    pub fn is_negative(&self) -> bool {
+        self.number().is_some_and(|number| number.is_negative())
+    }
+
+    // Targets without a positive allocation are left out. Formulas are kept until they are resolved.
+    pub fn is_left_out(&self) -> bool {
+        self.number().is_some_and(|number| !number.is_positive())
+    }
+
+    // Allocation a fraction of the way from one allocation to another. An empty cell is a zero allocation.
+    fn interpolate(
+        from: Option<Allocation>,
+        to: Option<Allocation>,
+        position: &BigRational,
+    ) -> Result<Option<Allocation>, String> {
+        let zero = BigRational::zero();
+
+        let (from, to, is_percent) = match (from, to) {
+            (None, None) => return Ok(None),
+            (Some(Allocation::Formula(_)), _) | (_, Some(Allocation::Formula(_))) => {
+                return Err("allocations of a glide path can't be formulas".to_string())
+            }
+            (Some(Allocation::Number(from)), None) => (from, zero, false),
+            (Some(Allocation::Percent(from)), None) => (from, zero, true),
+            (None, Some(Allocation::Number(to))) => (zero, to, false),
+            (None, Some(Allocation::Percent(to))) => (zero, to, true),
+            (Some(Allocation::Number(from)), Some(Allocation::Number(to))) => (from, to, false),
+            (Some(Allocation::Percent(from)), Some(Allocation::Percent(to))) => (from, to, true),
+            _ => {
+                return Err(
+                    "allocations of a glide path must all be percentages, or all bare numbers"
+                        .to_string(),
+                )
+            }
+        };
+
+        let allocation = &from + (to - &from) * position;
+
+        Ok(Some(if is_percent {
+            Allocation::Percent(allocation)
+        } else {
+            Allocation::Number(allocation)
+        }))
+    }
+}
+
+// Parses an allocation such as `40`, `0.4` or `40%` exactly.
+pub fn parse_allocation(cell: &str) -> Result<Allocation, String> {
+    let cell = cell.trim();
+
+    let invalid = || format!("invalid allocation \"{}\"", cell);
+
+    if let Some(number) = cell.strip_suffix('%') {
+        return parse_decimal(number.trim())
+            .map(Allocation::Percent)
+            .ok_or_else(invalid);
+    }
+
+    if let Some(number) = parse_decimal(cell) {
+        return Ok(Allocation::Number(number));
+    }
+
+    if cell.is_empty() {
+        return Err(invalid());
+    }
+
+    Formula::parse(cell)
+        .map(Allocation::Formula)
+        .map_err(|error| format!("{}: {}", invalid(), error))
+}
+
+// The date of the rebalance, and the birth date of the investor, to read a glide path.
+pub struct GlideOptions {
+    pub as_of: NaiveDate,
+    pub birth_date: Option<NaiveDate>,
+}
+
+impl GlideOptions {
+    // Age of the investor in whole years on the date of the rebalance, if their birth date is known.
+    pub fn age(&self) -> Option<i64> {
+        self.birth_date.map(|birth_date| {
+            let mut age = i64::from(self.as_of.year() - birth_date.year());
+
+            if (self.as_of.month(), self.as_of.day()) < (birth_date.month(), birth_date.day()) {
+                age -= 1;
+            }
+
+            age
+        })
+    }
+}
+
+// Where the rebalance falls on a glide path: the columns of allocations on either side of it, and how far it is from
+// the first to the second (0 for the first, and 1 for the second).
+pub struct GlidePosition {
+    from: usize,
+    to: usize,
+    position: BigRational,
+}
+
+impl GlidePosition {
+    // Allocation of a row of the glide path, whose first cell is the name of the target.
+    pub fn allocation(&self, cells: &[&str]) -> Result<Option<Allocation>, String> {
+        let parse = |index: usize| match cells.get(index).map_or("", |cell| cell.trim()) {
+            "" => Ok(None),
+            cell => parse_allocation(cell).map(Some),
+        };
+
+        Allocation::interpolate(parse(self.from)?, parse(self.to)?, &self.position)
+    }
+}
+
+pub fn is_glide_path_header(first_cell: &str) -> bool {
+    let first_cell = first_cell.trim();
+
+    first_cell.eq_ignore_ascii_case("date") || first_cell.eq_ignore_ascii_case("age")
+}
+
+// Finds where the rebalance falls on the glide path of the given header row, whose first cell is "Date" or "Age" and
+// whose other cells are dates (YYYY-MM-DD) or ages in years.
+pub fn glide_position(header: &[&str], options: &GlideOptions) -> Result<GlidePosition, String> {
+    let by_age = header[0].trim().eq_ignore_ascii_case("age");
+
+    // Dates and ages are compared as a number of days, or of years.
+    let to_point =
+        |date: NaiveDate| BigRational::from_integer(BigInt::from(date.num_days_from_ce()));
+
+    let as_of = if by_age {
+        let birth_date = options.birth_date.ok_or_else(|| {
+            "a glide path by age needs the birth date of the investor; pass it with --birth-date".to_string()
+        })?;
+
+        let days = options.as_of.signed_duration_since(birth_date).num_days();
+
+        // An average year of 365.25 days.
+        BigRational::new(BigInt::from(days) * 4, BigInt::from(1461))
+    } else {
+        to_point(options.as_of)
+    };
+
+    let mut points: Vec<BigRational> = vec![];
+
+    for cell in &header[1..] {
+        let cell = cell.trim();
+
+        let point = if by_age {
+            parse_decimal(cell).ok_or_else(|| format!("invalid age \"{}\" in glide path", cell))?
+        } else {
+            to_point(parse_date(cell).ok_or_else(|| {
+                format!(
+                    "invalid date \"{}\" in glide path; expected YYYY-MM-DD",
+                    cell
+                )
+            })?)
+        };
+
+        if points.last().is_some_and(|last| point <= *last) {
+            return Err(format!("glide path is not in order at \"{}\"", cell));
+        }
+
+        points.push(point);
+    }
+
+    if points.is_empty() {
+        return Err("glide path has no dates or ages".to_string());
+    }
+
+    let last = points.len() - 1;
+
+    // Allocation cells are in the columns after the name.
+    let at = |index: usize| GlidePosition {
+        from: index + 1,
+        to: index + 1,
+        position: BigRational::zero(),
+    };
+
+    if as_of <= points[0] {
+        return Ok(at(0));
+    }
+
+    if as_of >= points[last] {
+        return Ok(at(last));
+    }
+
+    let index = points.iter().rposition(|point| *point <= as_of).unwrap();
+
+    Ok(GlidePosition {
+        from: index + 1,
+        to: index + 2,
+        position: (&as_of - &points[index]) / (&points[index + 1] - &points[index]),
+    })
+}
+
+pub fn parse_date(date: &str) -> Option<NaiveDate> {
+    NaiveDate::parse_from_str(date.trim(), "%Y-%m-%d").ok()
+}
+
+// Parses a decimal number such as `-12.345` into an exact rational, rather than the nearest float.
+pub fn parse_decimal(number: &str) -> Option<BigRational> {
+    let (sign, digits) = match number.strip_prefix('-') {
+        Some(digits) => ("-", digits),
+        None => ("", number.strip_prefix('+').unwrap_or(number)),
+    };
+
+    let mut parts = digits.splitn(2, '.');
+    let whole = parts.next().unwrap_or("");
+    let fraction = parts.next().unwrap_or("");
+
+    if whole.is_empty() && fraction.is_empty()
+        || !whole
+            .chars()
+            .chain(fraction.chars())
+            .all(|c| c.is_ascii_digit())
+    {
+        return None;
+    }
+
+    let numerator: BigInt = format!("{}{}{}", sign, whole, fraction).parse().ok()?;
+    let denominator = BigInt::from(10).pow(fraction.len());
+
+    Some(BigRational::new(numerator, denominator))
+}
+
+// A target in a tree of targets: its allocation, and the target it is nested under, if any.
+struct Target<T> {
+    name: String,
+    allocation: T,
+    parent: Option<String>,
+}
+
+// Targets as written, before they are normalized. A nested target is an allocation of its parent target, e.g.
+// Canada is 30% of Equity; only the targets without children (the leaves) are assets to rebalance.
+#[derive(Default)]
+pub struct Targets {
+    // In the order written.
+    targets: Vec<Target<Allocation>>,
+}
+
+impl Targets {
+    // A target written again replaces the earlier one.
+    pub fn insert(&mut self, name: String, allocation: Allocation, parent: Option<String>) {
+        self.targets.retain(|target| target.name != name);
+        self.targets.push(Target {
+            name,
+            allocation,
+            parent,
+        });
+    }
+
+    pub fn has_formulas(&self) -> bool {
+        self.targets
+            .iter()
+            .any(|target| self.is_formula(&target.name))
+    }
+
+    fn is_formula(&self, name: &str) -> bool {
+        self.targets.iter().any(|target| {
+            target.name == name && matches!(target.allocation, Allocation::Formula(_))
+        })
+    }
+
+    // Replaces each formula with the allocation it evaluates to, given the age of the investor. A formula may refer to
+    // other targets, which are resolved first. Targets left without a positive allocation are then dropped.
+    pub fn resolve_formulas(&mut self, age: Option<i64>) -> Result<(), RebalanceError> {
+        loop {
+            let unresolved: Vec<usize> = (0..self.targets.len())
+                .filter(|&index| self.is_formula(&self.targets[index].name))
+                .collect();
+
+            if unresolved.is_empty() {
+                break;
+            }
+
+            // Formulas which only refer to targets that are already resolved.
+            let ready: Vec<usize> = unresolved
+                .iter()
+                .cloned()
+                .filter(|&index| match self.targets[index].allocation {
+                    Allocation::Formula(ref formula) => {
+                        formula.names().iter().all(|name| !self.is_formula(name))
+                    }
+                    _ => false,
+                })
+                .collect();
+
+            if ready.is_empty() {
+                let names: Vec<&str> = unresolved
+                    .iter()
+                    .map(|&index| self.targets[index].name.as_str())
+                    .collect();
+
+                return Err(RebalanceError::Invalid(format!(
+                    "formulas of targets {} refer to each other in a cycle",
+                    names.join(", ")
+                )));
+            }
+
+            for index in ready {
+                let allocation = self.evaluate(index, age)?;
+                self.targets[index].allocation = allocation;
+            }
+        }
+
+        self.targets
+            .retain(|target| !target.allocation.is_left_out());
+
+        Ok(())
+    }
+
+    fn evaluate(&self, index: usize, age: Option<i64>) -> Result<Allocation, RebalanceError> {
+        let target = &self.targets[index];
+
+        let formula = match target.allocation {
+            Allocation::Formula(ref formula) => formula,
+            ref allocation => return Ok(allocation.clone()),
+        };
+
+        let value_of = |name: &str| -> Result<BigRational, String> {
+            if name == "age" {
+                return age
+                    .map(|age| BigRational::from_integer(age.into()))
+                    .ok_or_else(|| {
+                        "age needs the birth date of the investor; pass it with --birth-date"
+                            .to_string()
+                    });
+            }
+
+            self.targets
+                .iter()
+                .find(|target| target.name == name)
+                .and_then(|target| target.allocation.number().cloned())
+                .ok_or_else(|| format!("no target named \"{}\"", name))
+        };
+
+        let error = |message: String| {
+            RebalanceError::Invalid(format!(
+                "formula of target \"{}\": {}",
+                target.name, message
+            ))
+        };
+
+        let value = formula.evaluate(&value_of).map_err(error)?;
+
+        if value.is_negative() {
+            return Err(error(format!(
+                "resolves to {}, which is not a valid allocation",
+                to_f64(&value)
+            )));
+        }
+
+        // Age is in years, so a formula of age is a percentage, as is one of percentages. Others follow the target
+        // notation.
+        let is_percent = formula.names().iter().any(|name| {
+            *name == "age"
+                || self.targets.iter().any(|target| {
+                    target.name == *name && matches!(target.allocation, Allocation::Percent(_))
+                })
+        });
+
+        Ok(if is_percent {
+            Allocation::Percent(value)
+        } else {
+            Allocation::Number(value)
+        })
+    }
+}
+
+// Targets normalized to fractions: each target is a fraction of its parent, or of the portfolio at the top level.
+pub struct TargetTree {
+    targets: Vec<Target<BigRational>>,
+}
+
+impl TargetTree {
+    pub fn is_nested(&self) -> bool {
+        self.targets.iter().any(|target| target.parent.is_some())
+    }
+
+    fn children<'a>(
+        &'a self,
+        parent: Option<&'a str>,
+    ) -> impl Iterator<Item = &'a Target<BigRational>> {
+        self.targets
+            .iter()
+            .filter(move |target| target.parent.as_deref() == parent)
+    }
+
+    fn parent_of(&self, name: &str) -> Option<&str> {
+        self.targets
+            .iter()
+            .find(|target| target.name == name)
+            .and_then(|target| target.parent.as_deref())
+    }
+
+    fn is_leaf(&self, name: &str) -> bool {
+        self.children(Some(name)).next().is_none()
+    }
+
+    // Whether a target is the given ancestor, or nested anywhere under it.
+    fn is_within(&self, name: &str, ancestor: &str) -> bool {
+        let mut name = Some(name);
+
+        // Targets nested in a cycle are reported when the tree is normalized; they must not loop forever meanwhile.
+        for _ in 0..=self.targets.len() {
+            match name {
+                Some(target_name) if target_name == ancestor => return true,
+                Some(target_name) => name = self.parent_of(target_name),
+                None => return false,
+            }
+        }
+
+        false
+    }
+
+    // Fraction of the portfolio a target is, through all of its parents.
+    pub fn portfolio_fraction(&self, name: &str) -> BigRational {
+        self.targets
+            .iter()
+            .filter(|target| self.is_within(name, &target.name))
+            .fold(BigRational::one(), |total, target| {
+                total * &target.allocation
+            })
+    }
+
+    // Allocations of the leaf targets, the assets to rebalance, as fractions of the portfolio.
+    pub fn leaves(&self) -> TargetMap {
+        self.targets
+            .iter()
+            .filter(|target| self.is_leaf(&target.name))
+            .map(|target| (target.name.clone(), self.portfolio_fraction(&target.name)))
+            .collect()
+    }
+
+    // Names of the leaf targets under a target, or the target itself when it is a leaf.
+    pub fn leaves_under(&self, name: &str) -> Vec<&str> {
+        self.targets
+            .iter()
+            .map(|target| target.name.as_str())
+            .filter(|target_name| self.is_leaf(target_name) && self.is_within(target_name, name))
+            .collect()
+    }
+
+    // Every target, depth first in the order written, with its depth in the tree.
+    pub fn walk(&self) -> Vec<(&str, usize)> {
+        let mut walk = vec![];
+        self.walk_from(None, 0, &mut walk);
+        walk
+    }
+
+    fn walk_from<'a>(
+        &'a self,
+        parent: Option<&'a str>,
+        depth: usize,
+        walk: &mut Vec<(&'a str, usize)>,
+    ) {
+        for target in self.children(parent) {
+            walk.push((target.name.as_str(), depth));
+            self.walk_from(Some(&target.name), depth + 1, walk);
+        }
+    }
+
+    // Sum of the fractions of each group of sibling targets: the top-level targets (None), and the children of each
+    // parent.
+    pub fn group_sums(&self) -> Vec<(Option<&str>, BigRational)> {
+        let mut parents: Vec<Option<&str>> = vec![];
+
+        for target in &self.targets {
+            if !parents.contains(&target.parent.as_deref()) {
+                parents.push(target.parent.as_deref());
+            }
+        }
+
+        parents
+            .into_iter()
+            .map(|parent| {
+                let sum = self
+                    .children(parent)
+                    .fold(BigRational::zero(), |total, target| {
+                        total + &target.allocation
+                    });
+
+                (parent, sum)
+            })
+            .collect()
+    }
+}
+
+// Turns the allocations into fractions of their parent, or of the portfolio. Weights are divided by the sum of
+// their siblings.
+pub fn normalize_targets(
+    targets: Targets,
+    notation: TargetNotation,
+) -> Result<TargetTree, RebalanceError> {
+    let hundred = BigRational::from_integer(BigInt::from(100));
+
+    let mut tree = TargetTree { targets: vec![] };
+
+    for target in targets.targets {
+        let fraction = match (target.allocation, notation) {
+            (Allocation::Percent(_), TargetNotation::Weight) => {
+                return Err(RebalanceError::Invalid(format!(
+                    "target of \"{}\" is a percentage, but targets are relative weights; remove the %, or use another target notation",
+                    target.name
+                )))
+            }
+            (Allocation::Percent(percent), _) => percent / &hundred,
+            (Allocation::Number(percent), TargetNotation::Percent) => percent / &hundred,
+            (Allocation::Number(number), _) => number,
+            (Allocation::Formula(_), _) => {
+                return Err(RebalanceError::Invalid(format!(
+                    "formula of target \"{}\" is not resolved",
+                    target.name
+                )))
+            }
+        };
+
+        tree.targets.push(Target {
+            name: target.name,
+            allocation: fraction,
+            parent: target.parent,
+        });
+    }
+
+    for target in &tree.targets {
+        let parent = match target.parent {
+            Some(ref parent) => parent,
+            None => continue,
+        };
+
+        if !tree.targets.iter().any(|other| other.name == *parent) {
+            return Err(RebalanceError::Invalid(format!(
+                "target \"{}\" is nested under \"{}\", which has no target",
+                target.name, parent
+            )));
+        }
+
+        // A target can't be its own ancestor.
+        if tree.is_within(parent, &target.name) {
+            return Err(RebalanceError::Invalid(format!(
+                "target \"{}\" is nested under itself",
+                target.name
+            )));
+        }
+    }
+
+    if notation == TargetNotation::Weight {
+        let group_sums: Vec<(Option<String>, BigRational)> = tree
+            .group_sums()
+            .into_iter()
+            .map(|(parent, sum)| (parent.map(|parent| parent.to_string()), sum))
+            .collect();
+
+        for (parent, total_weight) in group_sums {
+            if !total_weight.is_positive() {
+                continue;
+            }
+
+            for target in tree.targets.iter_mut() {
+                if target.parent == parent {
+                    target.allocation = &target.allocation / &total_weight;
+                }
+            }
+        }
+    }
+
+    Ok(tree)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize_targets() {
+        let fraction = |numerator: i64, denominator: i64| {
+            BigRational::new(BigInt::from(numerator), BigInt::from(denominator))
+        };
+
+        assert_eq!(
+            parse_allocation("40%"),
+            Ok(Allocation::Percent(fraction(40, 1)))
+        );
+        assert_eq!(
+            parse_allocation("0.4"),
+            Ok(Allocation::Number(fraction(2, 5)))
+        );
+        assert_eq!(
+            parse_allocation(".5"),
+            Ok(Allocation::Number(fraction(1, 2)))
+        );
+        assert_eq!(
+            parse_allocation("forty%"),
+            Err("invalid allocation \"forty%\"".to_string())
+        );
+
+        let targets = |cells: &[(&str, &str)]| {
+            let mut targets = Targets::default();
+
+            for &(name, cell) in cells {
+                // Names such as "Equity/US" are nested under their parent.
+                let mut parts = name.rsplitn(2, '/');
+                let name = parts.next().unwrap().to_string();
+                let parent = parts.next().map(|parent| parent.to_string());
+
+                targets.insert(name, parse_allocation(cell).unwrap(), parent);
+            }
+
+            targets
+        };
+
+        let target_map = normalize_targets(
+            targets(&[("VTI", "3"), ("VXUS", "2"), ("BND", "1")]),
+            TargetNotation::Weight,
+        )
+        .unwrap()
+        .leaves();
+
+        assert_eq!(target_map["VTI"], fraction(1, 2));
+        assert_eq!(target_map["VXUS"], fraction(1, 3));
+        assert_eq!(target_map["BND"], fraction(1, 6));
+
+        let target_map = normalize_targets(
+            targets(&[("VTI", "0.6"), ("BND", "40%")]),
+            TargetNotation::Fraction,
+        )
+        .unwrap()
+        .leaves();
+
+        assert_eq!(target_map["VTI"], fraction(3, 5));
+        assert_eq!(target_map["BND"], fraction(2, 5));
+
+        let target_map = normalize_targets(targets(&[("VTI", "60")]), TargetNotation::Percent)
+            .unwrap()
+            .leaves();
+
+        assert_eq!(target_map["VTI"], fraction(3, 5));
+
+        assert!(normalize_targets(targets(&[("VTI", "60%")]), TargetNotation::Weight).is_err());
+
+        // Weights are normalized among their siblings, and leaves are fractions of the whole portfolio.
+        let target_tree = normalize_targets(
+            targets(&[
+                ("Equity", "7"),
+                ("Equity/VTI", "1"),
+                ("Equity/VXUS", "1"),
+                ("BND", "3"),
+            ]),
+            TargetNotation::Weight,
+        )
+        .unwrap();
+
+        let target_map = target_tree.leaves();
+
+        assert_eq!(target_map.len(), 3);
+        assert_eq!(target_map["VTI"], fraction(7, 20));
+        assert_eq!(target_map["BND"], fraction(3, 10));
+        assert_eq!(
+            target_tree.walk(),
+            vec![("Equity", 0), ("VTI", 1), ("VXUS", 1), ("BND", 0)]
+        );
+
+        assert_eq!(
+            normalize_targets(targets(&[("US/VTI", "100")]), TargetNotation::Percent)
+                .err()
+                .unwrap()
+                .to_string(),
+            "target \"VTI\" is nested under \"US\", which has no target"
+        );
+    }
+
+    #[test]
+    fn test_glide_path() {
+        let fraction = |numerator: i64, denominator: i64| {
+            BigRational::new(BigInt::from(numerator), BigInt::from(denominator))
+        };
+
+        let options =
+            |as_of: NaiveDate, birth_date: Option<NaiveDate>| GlideOptions { as_of, birth_date };
+
+        let header = ["Date", "2020-01-01", "2020-01-11"];
+
+        // Halfway between the two dates.
+        let position =
+            glide_position(&header, &options(NaiveDate::from_ymd(2020, 1, 6), None)).unwrap();
+
+        assert_eq!(
+            position.allocation(&["VTI", "60", "40"]),
+            Ok(Some(Allocation::Number(fraction(50, 1))))
+        );
+        assert_eq!(
+            position.allocation(&["BND", "", "10%"]),
+            Ok(Some(Allocation::Percent(fraction(5, 1))))
+        );
+        assert!(position.allocation(&["BND", "10", "10%"]).is_err());
+
+        // Before the first date, and after the last.
+        let position =
+            glide_position(&header, &options(NaiveDate::from_ymd(2019, 1, 1), None)).unwrap();
+
+        assert_eq!(
+            position.allocation(&["VTI", "60", "40"]),
+            Ok(Some(Allocation::Number(fraction(60, 1))))
+        );
+
+        let position =
+            glide_position(&header, &options(NaiveDate::from_ymd(2021, 1, 1), None)).unwrap();
+
+        assert_eq!(
+            position.allocation(&["VTI", "60", "40"]),
+            Ok(Some(Allocation::Number(fraction(40, 1))))
+        );
+
+        // By age: 1461 days is exactly 4 years.
+        let header = ["Age", "0", "8"];
+        let birth_date = NaiveDate::from_ymd(2000, 1, 1);
+
+        let position = glide_position(
+            &header,
+            &options(birth_date + chrono::Duration::days(1461), Some(birth_date)),
+        )
+        .unwrap();
+
+        assert_eq!(
+            position.allocation(&["VTI", "90", "50"]),
+            Ok(Some(Allocation::Number(fraction(70, 1))))
+        );
+
+        assert_eq!(
+            glide_position(&header, &options(birth_date, None))
+                .err()
+                .unwrap(),
+            "a glide path by age needs the birth date of the investor; pass it with --birth-date"
+        );
+    }
+
+    #[test]
+    fn test_resolve_formulas() {
+        let fraction = |numerator: i64, denominator: i64| {
+            BigRational::new(BigInt::from(numerator), BigInt::from(denominator))
+        };
+
+        let targets = |cells: &[(&str, &str)]| {
+            let mut targets = Targets::default();
+
+            for &(name, cell) in cells {
+                targets.insert(name.to_string(), parse_allocation(cell).unwrap(), None);
+            }
+
+            targets
+        };
+
+        let mut age_in_bonds = targets(&[("Bonds", "age - 20"), ("Stocks", "100 - Bonds")]);
+
+        assert!(age_in_bonds.has_formulas());
+
+        age_in_bonds.resolve_formulas(Some(45)).unwrap();
+
+        assert!(!age_in_bonds.has_formulas());
+
+        let target_map = normalize_targets(age_in_bonds, TargetNotation::Percent)
+            .unwrap()
+            .leaves();
+
+        assert_eq!(target_map["Bonds"], fraction(1, 4));
+        assert_eq!(target_map["Stocks"], fraction(3, 4));
+
+        assert_eq!(
+            targets(&[("Bonds", "age - 20")])
+                .resolve_formulas(None)
+                .err()
+                .unwrap()
+                .to_string(),
+            "formula of target \"Bonds\": age needs the birth date of the investor; pass it with --birth-date"
+        );
+        assert_eq!(
+            targets(&[("Bonds", "age - 20")])
+                .resolve_formulas(Some(10))
+                .err()
+                .unwrap()
+                .to_string(),
+            "formula of target \"Bonds\": resolves to -10, which is not a valid allocation"
+        );
+        assert_eq!(
+            targets(&[("Bonds", "100 - Stocks"), ("Stocks", "100 - Bonds")])
+                .resolve_formulas(Some(45))
+                .err()
+                .unwrap()
+                .to_string(),
+            "formulas of targets Bonds, Stocks refer to each other in a cycle"
+        );
+    }
+}
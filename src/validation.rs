@@ -0,0 +1,426 @@
+// Checks of the targets and the portfolio before rebalancing.
+//
+// Each kind of issue is reported either as a warning, and the rebalance goes ahead, or as an error, and it does not.
+
+// rust imports
+
+use std::collections::HashMap;
+
+// 3rd-party imports
+
+use chrono::NaiveDate;
+
+use num::One;
+
+// local imports
+
+use classes::AssetClasses;
+use error::RebalanceError;
+use holding::Holding;
+use input::Input;
+use prices::Prices;
+use rebalance::{to_f64, UntargetedMode};
+use targets::{is_glide_path_header, parse_allocation, TargetMap, TargetTree};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum IssueKind {
+    // Targets do not sum to 100%.
+    TargetsSum,
+    // The same asset appears on several rows of the targets file.
+    DuplicateTarget,
+    // A row of the targets file has no allocation, or one that is not a non-negative number.
+    InvalidTarget,
+    // A holding of the portfolio has no target.
+    UntargetedHolding,
+    // A target has no holding in the portfolio.
+    UnheldTarget,
+    // A holding is valued at a price older than the staleness threshold.
+    StalePrice,
+}
+
+pub const ISSUE_KINDS: &[(&str, IssueKind)] = &[
+    ("targets-sum", IssueKind::TargetsSum),
+    ("duplicate-target", IssueKind::DuplicateTarget),
+    ("invalid-target", IssueKind::InvalidTarget),
+    ("untargeted-holding", IssueKind::UntargetedHolding),
+    ("unheld-target", IssueKind::UnheldTarget),
+    ("stale-price", IssueKind::StalePrice),
+];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+pub struct Issue {
+    pub kind: IssueKind,
+    pub message: String,
+}
+
+impl Issue {
+    fn new(kind: IssueKind, message: String) -> Self {
+        Issue { kind, message }
+    }
+}
+
+pub struct ValidationOptions {
+    severities: HashMap<IssueKind, Severity>,
+}
+
+impl Default for ValidationOptions {
+    fn default() -> Self {
+        let mut severities = HashMap::new();
+
+        for &(_, kind) in ISSUE_KINDS {
+            severities.insert(kind, Severity::Warning);
+        }
+
+        // A target without a usable allocation would silently change the allocations of the others.
+        severities.insert(IssueKind::InvalidTarget, Severity::Error);
+
+        ValidationOptions { severities }
+    }
+}
+
+impl ValidationOptions {
+    pub fn severity(&self, kind: IssueKind) -> Severity {
+        self.severities[&kind]
+    }
+
+    // Sets the severity of a kind of issue, or of all of them with `all`.
+    pub fn set(&mut self, kind_name: &str, severity_name: &str) -> Result<(), String> {
+        let severity = match severity_name.trim() {
+            "warning" | "warn" => Severity::Warning,
+            "error" => Severity::Error,
+            _ => {
+                return Err(format!(
+                    "unknown severity \"{}\"; expected warning or error",
+                    severity_name
+                ))
+            }
+        };
+
+        let kind_name = kind_name.trim();
+
+        if kind_name == "all" {
+            for &(_, kind) in ISSUE_KINDS {
+                self.severities.insert(kind, severity);
+            }
+            return Ok(());
+        }
+
+        match ISSUE_KINDS.iter().find(|&&(name, _)| name == kind_name) {
+            Some(&(_, kind)) => {
+                self.severities.insert(kind, severity);
+                Ok(())
+            }
+            None => {
+                let names: Vec<&str> = ISSUE_KINDS.iter().map(|&(name, _)| name).collect();
+
+                Err(format!(
+                    "unknown issue \"{}\"; expected all, or one of: {}",
+                    kind_name,
+                    names.join(", ")
+                ))
+            }
+        }
+    }
+
+    // Formats each issue as a warning or an error, and counts the errors.
+    pub fn report(&self, issues: &[Issue]) -> (Vec<String>, usize) {
+        let mut lines = vec![];
+        let mut errors = 0;
+
+        for issue in issues {
+            match self.severity(issue.kind) {
+                Severity::Warning => lines.push(format!("warning: {}", issue.message)),
+                Severity::Error => {
+                    errors += 1;
+                    lines.push(format!("error: {}", issue.message));
+                }
+            }
+        }
+
+        (lines, errors)
+    }
+}
+
+// Checks the rows of a targets file for invalid allocations and duplicate assets.
+pub fn check_target_file(input: &Input) -> Result<Vec<Issue>, RebalanceError> {
+    let path = input.name.as_str();
+
+    let mut reader = input.csv_reader();
+
+    let mut issues = vec![];
+
+    // Rows on which each asset appears.
+    let mut rows: HashMap<String, Vec<u64>> = HashMap::new();
+    let mut asset_names = vec![];
+
+    // A glide path has allocations in every column after the name.
+    let mut is_glide_path = false;
+
+    for result in reader.records() {
+        let record = result.map_err(|error| RebalanceError::csv(path, error))?;
+
+        let line = record
+            .position()
+            .map(|position| position.line())
+            .unwrap_or(0);
+
+        if line == 1 && is_glide_path_header(record.get(0).unwrap_or("")) {
+            is_glide_path = true;
+            continue;
+        }
+
+        let asset_name = record.get(0).unwrap_or("").trim();
+
+        if record.iter().all(|cell| cell.trim().is_empty()) {
+            continue;
+        }
+
+        if asset_name.is_empty() {
+            issues.push(Issue::new(
+                IssueKind::InvalidTarget,
+                format!("{}: line {}: target has no asset name", path, line),
+            ));
+            continue;
+        }
+
+        let allocation_cells: Vec<&str> = record
+            .iter()
+            .skip(1)
+            .take(if is_glide_path { usize::MAX } else { 1 })
+            .map(|cell| cell.trim())
+            .filter(|cell| !cell.is_empty())
+            .collect();
+
+        if allocation_cells.is_empty() {
+            issues.push(Issue::new(
+                IssueKind::InvalidTarget,
+                format!(
+                    "{}: line {}: target \"{}\" has no allocation",
+                    path, line, asset_name
+                ),
+            ));
+        }
+
+        for &cell in &allocation_cells {
+            match parse_allocation(cell) {
+                Ok(ref allocation) if !allocation.is_negative() => {}
+                _ => issues.push(Issue::new(
+                    IssueKind::InvalidTarget,
+                    format!(
+                        "{}: line {}: invalid allocation \"{}\" for target \"{}\"",
+                        path, line, cell, asset_name
+                    ),
+                )),
+            }
+        }
+
+        if !rows.contains_key(asset_name) {
+            asset_names.push(asset_name.to_string());
+        }
+
+        rows.entry(asset_name.to_string()).or_default().push(line);
+    }
+
+    for asset_name in asset_names {
+        let rows = &rows[&asset_name];
+
+        if rows.len() > 1 {
+            let rows: Vec<String> = rows.iter().map(|row| row.to_string()).collect();
+
+            issues.push(Issue::new(
+                IssueKind::DuplicateTarget,
+                format!(
+                    "{}: target \"{}\" appears on lines {}; the last allocation is used",
+                    path,
+                    asset_name,
+                    rows.join(", ")
+                ),
+            ));
+        }
+    }
+
+    Ok(issues)
+}
+
+// Checks that the targets sum to 100%, and so do the targets nested under each target.
+pub fn check_targets(target_tree: &TargetTree) -> Vec<Issue> {
+    let mut issues = vec![];
+
+    for (parent, total) in target_tree.group_sums() {
+        if total.is_one() {
+            continue;
+        }
+
+        let total_percent = to_f64(&total) * 100.0;
+
+        let message = match parent {
+            Some(parent) => format!(
+                "targets under \"{}\" sum to {}%, not 100%",
+                parent, total_percent
+            ),
+            None => format!("targets sum to {}%, not 100%", total_percent),
+        };
+
+        issues.push(Issue::new(IssueKind::TargetsSum, message));
+    }
+
+    issues
+}
+
+// Reconciles the holdings of the portfolio with the targets.
+pub fn check_holdings(
+    holdings: &[Holding],
+    target_map: &TargetMap,
+    asset_classes: &AssetClasses,
+    untargeted_mode: UntargetedMode,
+    cash_names: &[String],
+) -> Vec<Issue> {
+    let mut issues = vec![];
+
+    let mut untargeted_names: Vec<&str> = vec![];
+    let mut held_names: Vec<&str> = vec![];
+
+    for holding in holdings {
+        // Cash has no target; it funds the contribution.
+        if cash_names.contains(&holding.name) {
+            continue;
+        }
+
+        let asset_name = asset_classes
+            .class_of(&holding.name)
+            .unwrap_or(&holding.name);
+
+        if target_map.contains_key(asset_name) {
+            held_names.push(asset_name);
+        } else if !untargeted_names.contains(&holding.name.as_str()) {
+            untargeted_names.push(&holding.name);
+        }
+    }
+
+    let consequence = match untargeted_mode {
+        UntargetedMode::Ignore => "it is left out of the rebalance",
+        UntargetedMode::Hold => "it is counted in the total, but not traded",
+        UntargetedMode::Liquidate => "it is sold",
+    };
+
+    for holding_name in untargeted_names {
+        issues.push(Issue::new(
+            IssueKind::UntargetedHolding,
+            format!(
+                "holding \"{}\" has no target; {}",
+                holding_name, consequence
+            ),
+        ));
+    }
+
+    let mut unheld_names: Vec<&String> = target_map
+        .keys()
+        .filter(|asset_name| !held_names.contains(&asset_name.as_str()))
+        .collect();
+    unheld_names.sort();
+
+    for asset_name in unheld_names {
+        issues.push(Issue::new(
+            IssueKind::UnheldTarget,
+            format!("target \"{}\" has no holding in the portfolio", asset_name),
+        ));
+    }
+
+    issues
+}
+
+// Checks that the prices holdings are valued at are no older than the given number of days.
+pub fn check_price_ages(
+    holdings: &[Holding],
+    prices: &Prices,
+    today: NaiveDate,
+    max_age_days: i64,
+) -> Vec<Issue> {
+    let mut issues = vec![];
+
+    let mut tickers: Vec<&str> = holdings
+        .iter()
+        .map(|holding| holding.name.as_str())
+        .filter(|ticker| prices.prices.contains_key(*ticker))
+        .collect();
+    tickers.sort();
+    tickers.dedup();
+
+    for ticker in tickers {
+        let date = match prices.prices[ticker].date {
+            Some(date) => date,
+            None => continue,
+        };
+
+        let age = today.signed_duration_since(date).num_days();
+
+        if age > max_age_days {
+            issues.push(Issue::new(
+                IssueKind::StalePrice,
+                format!(
+                    "{}: price of \"{}\" is {} days old ({})",
+                    prices.name, ticker, age, date
+                ),
+            ));
+        }
+    }
+
+    issues
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use targets::{normalize_targets, TargetNotation, Targets};
+
+    #[test]
+    fn test_check_holdings() {
+        let mut targets = Targets::default();
+        targets.insert("VTI".to_string(), parse_allocation("60").unwrap(), None);
+        targets.insert("BND".to_string(), parse_allocation("30").unwrap(), None);
+
+        let target_tree = normalize_targets(targets, TargetNotation::Percent).unwrap();
+        let target_map = target_tree.leaves();
+
+        let holding = |name: &str| Holding {
+            name: name.to_string(),
+            value: 100.0,
+            units: None,
+            account: None,
+            currency: None,
+            unvalued_quantity: None,
+        };
+
+        let holdings = vec![holding("VTI"), holding("GLD"), holding("GLD")];
+
+        let mut issues = check_targets(&target_tree);
+        issues.extend(check_holdings(
+            &holdings,
+            &target_map,
+            &AssetClasses::default(),
+            UntargetedMode::Ignore,
+            &[],
+        ));
+
+        let mut options = ValidationOptions::default();
+        options.set("unheld-target", "error").unwrap();
+
+        let (lines, errors) = options.report(&issues);
+
+        assert_eq!(
+            lines,
+            vec![
+                "warning: targets sum to 90%, not 100%",
+                "warning: holding \"GLD\" has no target; it is left out of the rebalance",
+                "error: target \"BND\" has no holding in the portfolio",
+            ]
+        );
+        assert_eq!(errors, 1);
+    }
+}